@@ -0,0 +1,125 @@
+//! Benchmarks of building snapshot trees and of looking up their entries, on synthetic backups.
+//!
+//! ruplicity-fuse has no library, so the modules involved are included by path.
+
+#![allow(dead_code, unused_imports, unused_macros)]
+
+extern crate chrono;
+#[macro_use]
+extern crate criterion;
+// benches are built with cfg(test), and so are the tests of the included modules, without their
+// #[test] functions
+#[macro_use]
+extern crate proptest;
+extern crate ruplicity;
+extern crate tar;
+
+#[path = "../src/macros.rs"]
+mod macros;
+#[path = "../src/path_utils.rs"]
+mod path_utils;
+#[path = "../src/backend/mod.rs"]
+mod backend;
+#[path = "../src/fs/tree.rs"]
+mod tree;
+
+use std::path::Path;
+
+use criterion::{Criterion, black_box};
+use ruplicity::{Backup, SnapshotEntries};
+
+use backend::memory::{self, Entry, MemoryBackend};
+use tree::SnapshotTree;
+
+
+// the inodes given to the snapshot directory and to the first entry
+const SNAPSHOT_INO: u64 = 2;
+const FIRST_INO: u64 = 100;
+
+
+fn backup(entries: &[Entry]) -> Backup<MemoryBackend> {
+    let backup = Backup::new(memory::chain(&[entries.to_vec()])).unwrap();
+    // parse the signatures now, so they aren't accounted to what is measured
+    backup.snapshots().unwrap().next().unwrap().entries().unwrap();
+    backup
+}
+
+// 100 directories of 1000 files each
+fn large() -> Vec<Entry> {
+    let mut result = Vec::new();
+    for d in 0..100 {
+        let dir = format!("dir{:03}", d);
+        result.push(Entry::dir(&dir));
+        result.extend((0..1000).map(|f| Entry::file(&format!("{}/file{:04}", dir, f), b"")));
+    }
+    result
+}
+
+// a single directory of 10000 files
+fn wide() -> Vec<Entry> {
+    let mut result = vec![Entry::dir("dir")];
+    result.extend((0..10000).map(|f| Entry::file(&format!("dir/file{:05}", f), b"")));
+    result
+}
+
+// 100 nested directories, with 10 files each
+fn deep() -> Vec<Entry> {
+    let mut result = Vec::new();
+    let mut dir = String::new();
+    for d in 0..100 {
+        dir = if d == 0 {
+            "dir".to_owned()
+        } else {
+            format!("{}/dir", dir)
+        };
+        result.push(Entry::dir(&dir));
+        result.extend((0..10).map(|f| Entry::file(&format!("{}/file{}", dir, f), b"")));
+    }
+    result
+}
+
+// looks up a child by name, as the filesystem does
+fn lookup(tree: &SnapshotTree, entries: &SnapshotEntries, parent: u64, name: &str) -> u64 {
+    let node = tree.find_node(parent).unwrap();
+    let child = node.children(entries.as_signature())
+                    .find(|child| child.path() == Some(Path::new(name)))
+                    .unwrap();
+    child.ino()
+}
+
+// looks up every component of a path from the snapshot directory
+fn resolve(tree: &SnapshotTree, entries: &SnapshotEntries, path: &str) -> u64 {
+    path.split('/').fold(SNAPSHOT_INO, |parent, name| lookup(tree, entries, parent, name))
+}
+
+
+fn tree_build(c: &mut Criterion) {
+    let backup = backup(&large());
+    let snapshot = backup.snapshots().unwrap().next().unwrap();
+    c.bench_function("build tree of 100k entries", |b| {
+        b.iter(|| SnapshotTree::new(&snapshot, SNAPSHOT_INO, FIRST_INO).unwrap())
+    });
+}
+
+fn lookups(c: &mut Criterion) {
+    let cases = [("wide", wide(), "dir/file09999"),
+                 ("deep", deep(), &*format!("{}file9", "dir/".repeat(100)))];
+    for &(name, ref entries, path) in &cases {
+        let backup = backup(entries);
+        let snapshot = backup.snapshots().unwrap().next().unwrap();
+        let tree = SnapshotTree::new(&snapshot, SNAPSHOT_INO, FIRST_INO).unwrap();
+        let entries = snapshot.entries().unwrap();
+        let ino = resolve(&tree, &entries, path);
+        let (parent, file) = path.split_at(path.rfind('/').unwrap());
+        let parent = resolve(&tree, &entries, parent);
+
+        c.bench_function(&format!("lookup in {} tree", name),
+                         |b| b.iter(|| lookup(&tree, &entries, black_box(parent), &file[1..])));
+        c.bench_function(&format!("find_node in {} tree", name),
+                         |b| b.iter(|| tree.find_node(black_box(ino)).unwrap().parent()));
+    }
+}
+
+
+criterion_group!(benches, tree_build, lookups);
+criterion_main!(benches);
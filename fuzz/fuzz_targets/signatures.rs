@@ -0,0 +1,11 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate ruplicity_fuse_fuzz;
+
+use ruplicity_fuse_fuzz::{backend_with_signatures, build_trees};
+
+// arbitrary bytes as the signatures of a full snapshot
+fuzz_target!(|data: &[u8]| {
+    build_trees(backend_with_signatures(data));
+});
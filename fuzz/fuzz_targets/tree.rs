@@ -0,0 +1,13 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate ruplicity_fuse_fuzz;
+
+use ruplicity_fuse_fuzz::{build_trees, chain_from_bytes};
+use ruplicity_fuse_fuzz::memory;
+
+// well formed signatures of arbitrary entry sequences, across a chain of snapshots
+fuzz_target!(|data: &[u8]| {
+    let snapshots = chain_from_bytes(data);
+    build_trees(memory::chain(&snapshots));
+});
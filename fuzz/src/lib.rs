@@ -0,0 +1,116 @@
+//! Shared code of the fuzz targets, which include the modules of ruplicity-fuse they exercise.
+//!
+//! Run a target with `cargo fuzz run signatures` or `cargo fuzz run tree`, from the root of the
+//! repository.
+
+#![allow(dead_code, unused_macros)]
+
+extern crate chrono;
+extern crate ruplicity;
+extern crate tar;
+
+#[path = "../../src/macros.rs"]
+mod macros;
+#[path = "../../src/path_utils.rs"]
+mod path_utils;
+#[path = "../../src/backend/memory.rs"]
+pub mod memory;
+#[path = "../../src/fs/tree.rs"]
+pub mod tree;
+
+use ruplicity::{Backup, SnapshotEntries};
+
+use memory::{Entry, MemoryBackend};
+use tree::SnapshotTree;
+
+
+// the name of the signatures file of a full snapshot, and of its manifest
+const SIGNATURES_FILE: &'static str = "duplicity-full-signatures.20170714T024000Z.sigtar";
+const MANIFEST_FILE: &'static str = "duplicity-full.20170714T024000Z.manifest";
+const MANIFEST: &'static str = "Hostname fuzz\nLocaldir /\nVolume 1:\n    StartingPath   .\n    \
+                                EndingPath     .\n";
+
+// path components longer than this don't fit in a tar header as symbolic link targets
+const MAX_COMPONENT_LEN: usize = 64;
+
+
+/// Returns a backend with a single full snapshot, having the given signatures file.
+pub fn backend_with_signatures(data: &[u8]) -> MemoryBackend {
+    let mut backend = MemoryBackend::default();
+    backend.insert(SIGNATURES_FILE, data.to_vec());
+    backend.insert(MANIFEST_FILE, MANIFEST.as_bytes().to_vec());
+    backend
+}
+
+/// Decodes a chain of snapshots from arbitrary bytes.
+///
+/// Snapshots are separated by `0xff` and entries by newlines. The first byte of an entry is its
+/// kind, as in `memory::entries_from_paths`, and the others its path. Components that tar can't
+/// store are dropped.
+pub fn chain_from_bytes(data: &[u8]) -> Vec<Vec<Entry>> {
+    data.split(|b| *b == 0xff)
+        .map(|snapshot| {
+            let lines = snapshot.split(|b| *b == b'\n')
+                                .filter(|line| !line.is_empty())
+                                .map(|line| (line[0] % 3, String::from_utf8_lossy(&line[1..])))
+                                .collect::<Vec<_>>();
+            let paths = lines.iter()
+                             .map(|&(kind, ref path)| {
+                                 let components = path.split('/')
+                                                      .filter(|c| is_valid_component(c))
+                                                      .collect::<Vec<_>>();
+                                 (components, kind)
+                             })
+                             .filter(|&(ref components, _)| !components.is_empty())
+                             .collect::<Vec<_>>();
+            memory::entries_from_paths(&paths)
+        })
+        .collect()
+}
+
+/// Reads every snapshot of a backup and builds its tree, browsing it as the filesystem does.
+///
+/// Failing to read the backup is fine, as long as it doesn't panic. Inconsistent trees are fine
+/// as well, since the filesystem just presents them oddly.
+pub fn build_trees(backend: MemoryBackend) {
+    let backup = match Backup::new(backend) {
+        Ok(backup) => backup,
+        Err(_) => return,
+    };
+    let snapshots = match backup.snapshots() {
+        Ok(snapshots) => snapshots,
+        Err(_) => return,
+    };
+    let mut first_ino = 100;
+    for snapshot in snapshots {
+        let tree = match SnapshotTree::new(&snapshot, 2, first_ino) {
+            Ok(tree) => tree,
+            Err(_) => continue,
+        };
+        let entries = snapshot.entries().unwrap();
+        let _ = tree.check(entries.as_signature());
+        walk(&tree, 2, &entries);
+        if let Some((_, last)) = tree.inodes() {
+            first_ino = last + 1;
+        }
+    }
+}
+
+// lists a directory and the ones below it, looking up every child by inode
+fn walk(tree: &SnapshotTree, ino: u64, entries: &SnapshotEntries) {
+    let node = match tree.find_node(ino) {
+        Some(node) => node,
+        None => panic!("ino {} was listed, but can't be found", ino),
+    };
+    for child in node.children(entries.as_signature()) {
+        child.path();
+        let found = tree.find_node(child.ino()).unwrap();
+        found.as_path_entry(entries.as_signature()).as_signature().entry_type();
+        walk(tree, child.ino(), entries);
+    }
+}
+
+fn is_valid_component(component: &str) -> bool {
+    !component.is_empty() && component != "." && component != ".." &&
+    !component.contains('\0') && component.len() <= MAX_COMPONENT_LEN
+}
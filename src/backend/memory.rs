@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+use chrono::{TimeZone, Utc};
+// the strategies are built in tests only, the fuzz targets include this module without proptest
+#[cfg(test)]
+use proptest::collection;
+#[cfg(test)]
+use proptest::prelude::*;
+#[cfg(test)]
+use proptest::sample;
+use ruplicity::Backend;
+use tar;
+
+
+// the block size of the generated signatures
+const SIGNATURE_BLOCK_LEN: usize = 512;
+// the length of the strong checksums of the generated signatures
+const SIGNATURE_STRONG_LEN: usize = 8;
+// the magic number starting librsync signatures
+const SIGNATURE_MAGIC: u32 = 0x72730136;
+// the time of the first generated snapshot, and the time between the following ones
+const FIRST_TIME: i64 = 1500000000;
+const TIME_STEP: i64 = 60 * 60;
+// the names used in generated paths, some of them sorting differently as strings and as paths
+#[cfg(test)]
+const NAMES: [&'static str; 6] = ["a", "a b", "a-b", "b", "long name of a file", "\u{e9}t\u{e9}"];
+
+
+/// A backend serving files kept in memory.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBackend {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+/// An entry of a generated snapshot.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Entry {
+    Dir(String),
+    File(String, Vec<u8>),
+    Symlink(String, String),
+}
+
+/// Generates the files of a duplicity backup chain, one snapshot at a time.
+///
+/// Every snapshot is described by all of its entries, and incremental snapshots record only
+/// what changed since the previous one, as duplicity does. The root directory is implicit.
+#[derive(Debug, Default)]
+pub struct BackupBuilder {
+    backend: MemoryBackend,
+    // the time and the entries of the last snapshot
+    last: Option<(i64, Vec<Entry>)>,
+}
+
+// an entry of a generated tar file, below one of the duplicity directories
+enum Member<'a> {
+    Root,
+    Changed(&'a Entry),
+    Deleted(&'a str),
+}
+
+
+impl MemoryBackend {
+    /// Adds a file, replacing the one with the same name, if any.
+    pub fn insert<P: Into<PathBuf>>(&mut self, name: P, content: Vec<u8>) {
+        self.files.insert(name.into(), content);
+    }
+
+    /// Removes a file, returning its content.
+    pub fn remove(&mut self, name: &Path) -> Option<Vec<u8>> {
+        self.files.remove(name)
+    }
+}
+
+impl Backend for MemoryBackend {
+    type FileName = PathBuf;
+    type FileNameIter = Vec<PathBuf>;
+    type FileStream = Cursor<Vec<u8>>;
+
+    fn file_names(&self) -> io::Result<Self::FileNameIter> {
+        Ok(self.files.keys().cloned().collect())
+    }
+
+    fn open_file(&self, name: &Path) -> io::Result<Self::FileStream> {
+        match self.files.get(name) {
+            Some(content) => Ok(Cursor::new(content.clone())),
+            None => {
+                Err(io::Error::new(io::ErrorKind::NotFound,
+                                   format!("{:?} is not in memory", name)))
+            }
+        }
+    }
+}
+
+
+impl Entry {
+    pub fn dir(path: &str) -> Self {
+        Entry::Dir(path.to_owned())
+    }
+
+    pub fn file(path: &str, content: &[u8]) -> Self {
+        Entry::File(path.to_owned(), content.to_vec())
+    }
+
+    pub fn symlink(path: &str, target: &str) -> Self {
+        Entry::Symlink(path.to_owned(), target.to_owned())
+    }
+
+    pub fn path(&self) -> &str {
+        match *self {
+            Entry::Dir(ref path) |
+            Entry::File(ref path, _) |
+            Entry::Symlink(ref path, _) => path,
+        }
+    }
+}
+
+
+impl BackupBuilder {
+    pub fn new() -> Self {
+        BackupBuilder::default()
+    }
+
+    /// Adds a full snapshot taken at the given time, in seconds from the epoch.
+    ///
+    /// A full snapshot starts a new chain.
+    pub fn full(mut self, time: i64, entries: &[Entry]) -> Self {
+        let name = format_time(time);
+        let mut members = vec![Member::Root];
+        members.extend(entries.iter().map(Member::Changed));
+        self.add_set(&format!("duplicity-full.{}", name),
+                     &format!("duplicity-full-signatures.{}", name),
+                     time,
+                     members);
+        self.last = Some((time, entries.to_vec()));
+        self
+    }
+
+    /// Adds an incremental snapshot on top of the last one, taken at the given time.
+    ///
+    /// Panics if there is no previous snapshot.
+    pub fn inc(mut self, time: i64, entries: &[Entry]) -> Self {
+        let (last_time, last_entries) = self.last
+                                            .take()
+                                            .expect("an incremental snapshot needs a previous one");
+        let names = format!("{}.to.{}", format_time(last_time), format_time(time));
+        let mut members = entries.iter()
+                                 .filter(|entry| !last_entries.contains(entry))
+                                 .map(Member::Changed)
+                                 .collect::<Vec<_>>();
+        members.extend(last_entries.iter()
+                                   .map(Entry::path)
+                                   .filter(|path| !entries.iter().any(|e| e.path() == *path))
+                                   .map(Member::Deleted));
+        self.add_set(&format!("duplicity-inc.{}", names),
+                     &format!("duplicity-new-signatures.{}", names),
+                     time,
+                     members);
+        self.last = Some((time, entries.to_vec()));
+        self
+    }
+
+    pub fn build(self) -> MemoryBackend {
+        self.backend
+    }
+
+    /// Adds the manifest, the volume and the signatures of a backup set.
+    fn add_set(&mut self,
+               set_name: &str,
+               signatures_name: &str,
+               time: i64,
+               mut members: Vec<Member>) {
+        // duplicity records the paths in order
+        members.sort_by(|a, b| Path::new(a.path()).cmp(Path::new(b.path())));
+        let last_path = members.last().map_or("", Member::path);
+        let manifest = format!("Hostname memory\nLocaldir /\nVolume 1:\n    StartingPath   .\n    \
+                                EndingPath     {}\n",
+                               if last_path.is_empty() { "." } else { last_path });
+        self.backend.insert(format!("{}.manifest", set_name), manifest.into_bytes());
+        self.backend.insert(format!("{}.vol1.difftar", set_name),
+                            tar_file(&members, time, false));
+        self.backend.insert(format!("{}.sigtar", signatures_name),
+                            tar_file(&members, time, true));
+    }
+}
+
+
+/// Builds the entries of a snapshot from paths and entry kinds, adding the missing parents.
+///
+/// Kind 0 is a file, 1 a symbolic link and anything else a directory. Paths that are parents of
+/// other paths are directories anyway.
+pub fn entries_from_paths(paths: &[(Vec<&str>, u8)]) -> Vec<Entry> {
+    let parents = paths.iter()
+                       .flat_map(|&(ref components, _)| {
+                           (1..components.len()).map(move |len| components[..len].join("/"))
+                       })
+                       .collect::<BTreeSet<_>>();
+    let mut entries = parents.iter()
+                             .map(|path| (path.clone(), Entry::dir(path)))
+                             .collect::<BTreeMap<_, _>>();
+    for &(ref components, kind) in paths {
+        let path = components.join("/");
+        if parents.contains(&path) {
+            continue;
+        }
+        let entry = match kind {
+            0 => Entry::file(&path, path.as_bytes()),
+            1 => Entry::symlink(&path, components[0]),
+            _ => Entry::dir(&path),
+        };
+        entries.insert(path, entry);
+    }
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// Generates the entries of a snapshot, with paths up to 4 components deep.
+#[cfg(test)]
+pub fn arb_entries() -> BoxedStrategy<Vec<Entry>> {
+    let path = collection::vec(sample::select(&NAMES[..]), 1..5);
+    collection::vec((path, 0..3u8), 0..40)
+        .prop_map(|paths| entries_from_paths(&paths))
+        .boxed()
+}
+
+/// Generates a chain of up to 4 snapshots, and the backend containing it.
+#[cfg(test)]
+pub fn arb_chain() -> BoxedStrategy<(Vec<Vec<Entry>>, MemoryBackend)> {
+    collection::vec(arb_entries(), 1..5)
+        .prop_map(|snapshots| {
+            let backend = chain(&snapshots);
+            (snapshots, backend)
+        })
+        .boxed()
+}
+
+/// Returns a backend containing a chain of snapshots, with the given entries.
+///
+/// The snapshots are taken an hour apart, starting from `FIRST_TIME`. Panics if there are no
+/// snapshots.
+pub fn chain(snapshots: &[Vec<Entry>]) -> MemoryBackend {
+    let mut builder = BackupBuilder::new().full(FIRST_TIME, &snapshots[0]);
+    for (i, entries) in snapshots.iter().enumerate().skip(1) {
+        builder = builder.inc(FIRST_TIME + i as i64 * TIME_STEP, entries);
+    }
+    builder.build()
+}
+
+
+impl<'a> Member<'a> {
+    fn path(&self) -> &'a str {
+        match *self {
+            Member::Root => "",
+            Member::Changed(entry) => entry.path(),
+            Member::Deleted(path) => path,
+        }
+    }
+}
+
+
+/// Generates a tar file, with the signatures of the files or with their contents.
+fn tar_file(members: &[Member], time: i64, signatures: bool) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for member in members {
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(time as u64);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_username("root").unwrap();
+        header.set_groupname("root").unwrap();
+        let (dir, content) = match *member {
+            Member::Root | Member::Changed(&Entry::Dir(_)) => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                ("snapshot", Vec::new())
+            }
+            Member::Changed(&Entry::File(_, ref content)) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(0o644);
+                if signatures {
+                    ("signature", signature(content.len()))
+                } else {
+                    ("snapshot", content.clone())
+                }
+            }
+            Member::Changed(&Entry::Symlink(_, ref target)) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mode(0o777);
+                header.set_link_name(target).unwrap();
+                ("snapshot", Vec::new())
+            }
+            Member::Deleted(_) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(0o644);
+                ("deleted", Vec::new())
+            }
+        };
+        header.set_size(content.len() as u64);
+        let path = Path::new(dir).join(member.path());
+        builder.append_data(&mut header, path, &content[..]).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+/// Generates a librsync signature of a file of the given length.
+///
+/// Only the number of blocks is meaningful, since that's what the size hints are computed from.
+fn signature(len: usize) -> Vec<u8> {
+    let num_blocks = (len + SIGNATURE_BLOCK_LEN - 1) / SIGNATURE_BLOCK_LEN;
+    let mut result = Vec::new();
+    for n in &[SIGNATURE_MAGIC, SIGNATURE_BLOCK_LEN as u32, SIGNATURE_STRONG_LEN as u32] {
+        result.extend_from_slice(&u32_to_be(*n));
+    }
+    result.resize(12 + num_blocks * (4 + SIGNATURE_STRONG_LEN), 0);
+    result
+}
+
+fn u32_to_be(n: u32) -> [u8; 4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+/// Formats a time as in the names of the duplicity files.
+fn format_time(time: i64) -> String {
+    Utc.timestamp_opt(time, 0).unwrap().format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use ruplicity::Backup;
+    use ruplicity::signatures::EntryType;
+
+
+    fn sample() -> MemoryBackend {
+        BackupBuilder::new()
+            .full(1500000000,
+                  &[Entry::dir("etc"),
+                    Entry::file("etc/hosts", b"127.0.0.1 localhost\n"),
+                    Entry::file("etc/passwd", &[0; 1000]),
+                    Entry::symlink("hosts", "etc/hosts")])
+            .inc(1500003600,
+                 &[Entry::dir("etc"),
+                   Entry::file("etc/hosts", b"127.0.0.1 localhost\n"),
+                   Entry::file("etc/group", b"root:x:0:\n"),
+                   Entry::symlink("hosts", "etc/hosts")])
+            .build()
+    }
+
+    fn paths(backup: &Backup<MemoryBackend>, sid: usize) -> Vec<String> {
+        let snapshot = backup.snapshots().unwrap().nth(sid).unwrap();
+        let entries = snapshot.entries().unwrap();
+        entries.as_signature().map(|e| e.path().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn snapshots() {
+        let backup = Backup::new(sample()).unwrap();
+        let times = backup.snapshots().unwrap().map(|s| s.time().sec).collect::<Vec<_>>();
+        assert_eq!(times, vec![1500000000, 1500003600]);
+    }
+
+    #[test]
+    fn entries() {
+        let backup = Backup::new(sample()).unwrap();
+        assert_eq!(paths(&backup, 0), vec!["", "etc", "etc/hosts", "etc/passwd", "hosts"]);
+        assert_eq!(paths(&backup, 1), vec!["", "etc", "etc/group", "etc/hosts", "hosts"]);
+    }
+
+    #[test]
+    fn entry_metadata() {
+        let backup = Backup::new(sample()).unwrap();
+        let snapshot = backup.snapshots().unwrap().nth(0).unwrap();
+        let entries = snapshot.entries().unwrap();
+        let entries = entries.as_signature().collect::<Vec<_>>();
+        assert_eq!(entries[1].entry_type(), EntryType::Dir);
+        assert_eq!(entries[3].entry_type(), EntryType::File);
+        assert_eq!(entries[3].size_hint(), Some((513, 1024)));
+        assert_eq!(entries[4].entry_type(), EntryType::SymLink);
+        assert_eq!(entries[4].linked_path(), Some(Path::new("etc/hosts")));
+    }
+
+    #[test]
+    fn missing_signatures() {
+        let mut backend = sample();
+        backend.remove(Path::new("duplicity-new-signatures.20170714T024000Z.to.\
+                                  20170714T034000Z.sigtar"))
+               .unwrap();
+        let backup = Backup::new(backend).unwrap();
+        let snapshot = backup.snapshots().unwrap().nth(1).unwrap();
+        assert!(snapshot.entries().is_err());
+    }
+}
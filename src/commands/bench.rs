@@ -0,0 +1,62 @@
+use std::time::{Duration, Instant};
+
+use fs::{SnapshotTree, time_to_path};
+use super::open_backup;
+
+
+pub const USAGE: &'static str = "<BACKUP_PATH>";
+
+
+/// Times the steps needed to mount a backup, for every snapshot.
+pub fn run(args: &[String]) -> Result<(), String> {
+    if args.len() != 1 {
+        return Err("expected a backup path".to_owned());
+    }
+    let start = Instant::now();
+    let backup = try!(open_backup(&args[0]));
+    let snapshots = try!(backup.snapshots().map_err(|e| e.to_string())).collect::<Vec<_>>();
+    println!("open backup: {} ms, {} snapshots",
+             millis(start.elapsed()),
+             snapshots.len());
+
+    let (mut parse_total, mut tree_total) = (Duration::new(0, 0), Duration::new(0, 0));
+    let (mut entries_total, mut nodes_total) = (0, 0);
+    for snapshot in snapshots {
+        let name = time_to_path(snapshot.time());
+        // the first snapshot of each chain parses the signatures of the whole chain
+        let start = Instant::now();
+        let entries = {
+            let entries = try!(snapshot.entries().map_err(|e| format!("snapshot {}: {}", name, e)));
+            entries.as_signature().count()
+        };
+        let parse = start.elapsed();
+
+        let start = Instant::now();
+        let tree = try!(SnapshotTree::new(&snapshot, 1, 2)
+                            .map_err(|e| format!("snapshot {}: {}", name, e)));
+        let tree_time = start.elapsed();
+        let nodes = tree.inodes().map_or(0, |(first, last)| last - first + 1);
+
+        println!("{}: signatures {} ms, {} entries; tree {} ms, {} nodes",
+                 name,
+                 millis(parse),
+                 entries,
+                 millis(tree_time),
+                 nodes);
+        parse_total += parse;
+        tree_total += tree_time;
+        entries_total += entries;
+        nodes_total += nodes;
+    }
+    println!("total: signatures {} ms, {} entries; trees {} ms, {} nodes",
+             millis(parse_total),
+             entries_total,
+             millis(tree_total),
+             nodes_total);
+    Ok(())
+}
+
+
+fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + d.subsec_nanos() as u64 / 1_000_000
+}
@@ -0,0 +1,30 @@
+use std::path::Path;
+
+use compare;
+use diff::EntryInfo;
+use super::{find_snapshot, open_backup};
+
+
+pub const USAGE: &'static str = "<BACKUP_PATH> <SNAPSHOT> <LIVE_DIR>";
+
+
+/// Prints the entries of a live directory that differ from a snapshot, as in `diff`.
+///
+/// Entries present only in the live directory are reported as added, and the ones missing from
+/// it as removed.
+pub fn run(args: &[String]) -> Result<(), String> {
+    if args.len() != 3 {
+        return Err("expected a backup path, a snapshot and a directory".to_owned());
+    }
+    let backup = try!(open_backup(&args[0]));
+    let snapshot = try!(find_snapshot(&backup, &args[1]));
+    let backed_up = {
+        let entries = try!(snapshot.entries().map_err(|e| format!("snapshot {}: {}", args[1], e)));
+        entries.as_signature().map(|e| EntryInfo::from(&e)).collect::<Vec<_>>()
+    };
+    let live = try!(compare::live_entries(Path::new(&args[2])).map_err(|e| e.to_string()));
+    for change in compare::compare(backed_up, live) {
+        println!("{}", change);
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use diff::{self, EntryInfo};
+use super::{find_snapshot, open_backup};
+
+
+pub const USAGE: &'static str = "<BACKUP_PATH> <OLD_SNAPSHOT> <NEW_SNAPSHOT>";
+
+
+/// Prints the entries added, removed or changed between two snapshots.
+pub fn run(args: &[String]) -> Result<(), String> {
+    if args.len() != 3 {
+        return Err("expected a backup path and two snapshots".to_owned());
+    }
+    let backup = try!(open_backup(&args[0]));
+    let old = try!(find_snapshot(&backup, &args[1]));
+    let new = try!(find_snapshot(&backup, &args[2]));
+
+    // the two snapshots may share the signature chain, so the old one can't be borrowed while
+    // iterating the new one
+    let old = {
+        let entries = try!(old.entries().map_err(|e| format!("snapshot {}: {}", args[1], e)));
+        entries.as_signature().map(|e| EntryInfo::from(&e)).collect::<Vec<_>>()
+    };
+    let entries = try!(new.entries().map_err(|e| format!("snapshot {}: {}", args[2], e)));
+    let new = entries.as_signature().map(|e| EntryInfo::from(&e));
+    for change in diff::diff(old, new) {
+        println!("{}", change);
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use getopts;
+use ruplicity::signatures::EntryType;
+
+use options::parse_opt_num;
+use super::{find_snapshot, open_backup};
+
+
+pub const USAGE: &'static str = "[--max-depth N] <BACKUP_PATH> <SNAPSHOT>";
+
+
+/// Prints the total size of the files under each directory of a snapshot.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut opts = getopts::Options::new();
+    opts.optopt("d", "max-depth", "print only directories up to N levels deep", "N");
+    let matches = try!(opts.parse(args).map_err(|e| e.to_string()));
+    let max_depth: Option<usize> = try!(parse_opt_num(&matches, "max-depth"));
+    if matches.free.len() != 2 {
+        return Err("expected a backup path and a snapshot".to_owned());
+    }
+    let backup = try!(open_backup(&matches.free[0]));
+    let snapshot = try!(find_snapshot(&backup, &matches.free[1]));
+    let entries = try!(snapshot.entries()
+                               .map_err(|e| format!("snapshot {}: {}", matches.free[1], e)));
+
+    let mut sizes = BTreeMap::new();
+    for entry in entries.as_signature() {
+        match entry.entry_type() {
+            EntryType::Dir => {
+                sizes.entry(entry.path().to_path_buf()).or_insert(0);
+            }
+            EntryType::File => {
+                let size = entry.size_hint().map_or(0, |sh| sh.1 as u64);
+                for dir in ancestors(entry.path()) {
+                    *sizes.entry(dir.to_path_buf()).or_insert(0) += size;
+                }
+            }
+            _ => {}
+        }
+    }
+    for (dir, size) in &sizes {
+        if max_depth.map_or(true, |max| dir.components().count() <= max) {
+            let dir = if dir.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                Path::new(".").join(dir)
+            };
+            println!("{:>12} {}", size, dir.display());
+        }
+    }
+    Ok(())
+}
+
+
+/// Returns the directories containing the given path, up to the root.
+fn ancestors(path: &Path) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    let mut current = path.parent();
+    while let Some(dir) = current {
+        result.push(dir.to_path_buf());
+        current = dir.parent();
+    }
+    result
+}
@@ -0,0 +1,69 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use libc;
+
+
+pub const USAGE: &'static str = "<MOUNTPOINT>";
+
+// how long to wait for the mount to answer, since a hung filesystem blocks the calls forever
+const TIMEOUT_SECS: u64 = 10;
+
+// the filesystem type reported by statfs for FUSE mounts
+const FUSE_SUPER_MAGIC: i64 = 0x65735546;
+
+
+/// Checks that a mount is alive, failing if it doesn't answer or has no snapshots.
+pub fn run(args: &[String]) -> Result<(), String> {
+    if args.len() != 1 {
+        return Err("expected a mountpoint".to_owned());
+    }
+    let mountpoint = args[0].clone();
+    let (tx, rx) = mpsc::channel();
+    // the thread is left behind on timeout, the process exits anyway
+    thread::spawn(move || {
+        let _ = tx.send(check(&mountpoint));
+    });
+    let snapshots = match rx.recv_timeout(Duration::from_secs(TIMEOUT_SECS)) {
+        Ok(result) => try!(result),
+        Err(_) => {
+            return Err(format!("{} didn't answer in {} seconds", args[0], TIMEOUT_SECS));
+        }
+    };
+    println!("{}: ok, {} entries in the root", args[0], snapshots);
+    Ok(())
+}
+
+
+/// Returns the number of entries in the root of the mount.
+fn check(mountpoint: &str) -> Result<usize, String> {
+    let fs_type = try!(statfs_type(mountpoint)
+                           .map_err(|e| format!("cannot stat {}: {}", mountpoint, e)));
+    if fs_type != FUSE_SUPER_MAGIC {
+        return Err(format!("{} is not a FUSE mount", mountpoint));
+    }
+    let entries = try!(fs::read_dir(mountpoint)
+                           .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+                           .map_err(|e| format!("cannot list {}: {}", mountpoint, e)));
+    if entries.is_empty() {
+        return Err(format!("no snapshots found in {}", mountpoint));
+    }
+    Ok(entries.len())
+}
+
+fn statfs_type(path: &str) -> io::Result<i64> {
+    let path = try!(CString::new(path)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)));
+    unsafe {
+        let mut buf: libc::statfs = mem::zeroed();
+        if libc::statfs(path.as_ptr(), &mut buf) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(buf.f_type as i64)
+    }
+}
@@ -0,0 +1,164 @@
+use getopts;
+use ruplicity::{Backup, Snapshot};
+use ruplicity::signatures::{Entry, EntryType};
+use time::Timespec;
+
+use fs::time_to_path;
+use json;
+use time_utils;
+use super::{Local, find_snapshot, open_backup};
+
+
+pub const USAGE: &'static str = "[--path GLOB] [--snapshot RANGE] [--long] [--json] <BACKUP_PATH>";
+
+
+/// Prints the entries of the latest snapshot, or of the snapshots in a range.
+///
+/// Entries are filtered by a glob on their path, where `*` and `?` don't match slashes and `**`
+/// does. A range is a snapshot, or two of them separated by `..`, either possibly missing.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut opts = getopts::Options::new();
+    opts.optopt("p", "path", "print only the entries whose path matches GLOB", "GLOB");
+    opts.optopt("s", "snapshot", "list the snapshots in RANGE, like FROM..TO", "RANGE");
+    opts.optflag("l", "long", "print the type, size and modification time of the entries");
+    opts.optflag("j", "json", "print a JSON object per entry");
+    let matches = try!(opts.parse(args).map_err(|e| e.to_string()));
+    if matches.free.len() != 1 {
+        return Err("expected a backup path".to_owned());
+    }
+    let glob = matches.opt_str("path");
+    let long = matches.opt_present("long");
+    let backup = try!(open_backup(&matches.free[0]));
+    let snapshots = try!(snapshots_in_range(&backup, matches.opt_str("snapshot")));
+    // the snapshot is named only when it's not the only one
+    let named = snapshots.len() > 1;
+    for snapshot in snapshots {
+        let name = time_to_path(snapshot.time());
+        let entries = try!(snapshot.entries().map_err(|e| format!("snapshot {}: {}", name, e)));
+        for entry in entries.as_signature() {
+            let path = entry.path().to_string_lossy().into_owned();
+            if path.is_empty() || glob.as_ref().map_or(false, |glob| !glob_matches(glob, &path)) {
+                continue;
+            }
+            if matches.opt_present("json") {
+                println!("{}", json_line(&name, &path, &entry));
+                continue;
+            }
+            let mut line = String::new();
+            if named {
+                line.push_str(&name);
+                line.push(' ');
+            }
+            if long {
+                let size = entry.size_hint().map_or("-".to_owned(), |sh| sh.1.to_string());
+                let mtime = time_utils::local(entry.mtime()).format("%Y-%m-%d %H:%M:%S");
+                let kind = type_char(entry.entry_type());
+                line.push_str(&format!("{} {:>12} {} {}", kind, size, mtime, path));
+                if let Some(link) = entry.linked_path() {
+                    line.push_str(&format!(" -> {}", link.display()));
+                }
+            } else {
+                line.push_str(&path);
+            }
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+
+/// Returns the snapshots in a range, in time order, or the latest snapshot without a range.
+fn snapshots_in_range<'a>(backup: &'a Backup<Local>,
+                          range: Option<String>)
+                          -> Result<Vec<Snapshot<'a>>, String> {
+    let snapshots = try!(backup.snapshots().map_err(|e| e.to_string())).collect::<Vec<_>>();
+    let range = match range {
+        Some(range) => range,
+        None => {
+            return Ok(snapshots.into_iter().last().into_iter().collect());
+        }
+    };
+    let (from, to) = match range.find("..") {
+        Some(pos) => (&range[..pos], &range[pos + 2..]),
+        None => (&range[..], &range[..]),
+    };
+    let from = try!(bound(backup, from, Timespec::new(i64::min_value(), 0)));
+    let to = try!(bound(backup, to, Timespec::new(i64::max_value(), 0)));
+    let result = snapshots.into_iter()
+                          .filter(|s| from <= s.time() && s.time() <= to)
+                          .collect::<Vec<_>>();
+    if result.is_empty() {
+        return Err(format!("no snapshots in {}", range));
+    }
+    Ok(result)
+}
+
+/// Returns the time of the snapshot at one end of a range, or `default` when it's missing.
+fn bound(backup: &Backup<Local>, name: &str, default: Timespec) -> Result<Timespec, String> {
+    if name.is_empty() {
+        Ok(default)
+    } else {
+        find_snapshot(backup, name).map(|snapshot| snapshot.time())
+    }
+}
+
+/// Returns whether a whole path matches a glob.
+fn glob_matches(glob: &str, path: &str) -> bool {
+    let glob = glob.chars().collect::<Vec<_>>();
+    let path = path.chars().collect::<Vec<_>>();
+    matches_from(&glob, &path)
+}
+
+fn matches_from(glob: &[char], path: &[char]) -> bool {
+    match glob.first() {
+        None => path.is_empty(),
+        Some(&'*') if glob.get(1) == Some(&'*') => {
+            (0..path.len() + 1).any(|skip| matches_from(&glob[2..], &path[skip..]))
+        }
+        Some(&'*') => {
+            let max = path.iter().position(|c| *c == '/').unwrap_or(path.len());
+            (0..max + 1).any(|skip| matches_from(&glob[1..], &path[skip..]))
+        }
+        Some(&'?') => {
+            path.first().map_or(false, |c| *c != '/') && matches_from(&glob[1..], &path[1..])
+        }
+        Some(c) => path.first() == Some(c) && matches_from(&glob[1..], &path[1..]),
+    }
+}
+
+/// Returns a JSON object describing an entry, on a single line.
+fn json_line(snapshot: &str, path: &str, entry: &Entry) -> String {
+    let size = entry.size_hint().map_or("null".to_owned(), |sh| sh.1.to_string());
+    let link = entry.linked_path()
+                    .map_or("null".to_owned(), |link| json::string(&link.to_string_lossy()));
+    format!("{{ \"snapshot\": {}, \"path\": {}, \"type\": {}, \"size\": {}, \"mtime\": {}, \
+             \"link\": {} }}",
+            json::string(snapshot),
+            json::string(path),
+            json::string(type_name(entry.entry_type())),
+            size,
+            entry.mtime().sec,
+            link)
+}
+
+/// Returns the character `ls -l` uses for an entry type.
+fn type_char(entry_type: EntryType) -> char {
+    match entry_type {
+        EntryType::File | EntryType::HardLink => '-',
+        EntryType::Dir => 'd',
+        EntryType::SymLink => 'l',
+        EntryType::Fifo => 'p',
+        EntryType::Unknown(_) => '?',
+    }
+}
+
+fn type_name(entry_type: EntryType) -> &'static str {
+    match entry_type {
+        EntryType::File => "file",
+        EntryType::HardLink => "hardlink",
+        EntryType::Dir => "dir",
+        EntryType::SymLink => "symlink",
+        EntryType::Fifo => "fifo",
+        EntryType::Unknown(_) => "unknown",
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+
+use ruplicity::{Backup, Snapshot};
+use ruplicity::backend::local::LocalBackend;
+
+use error::Error;
+use fs::path_to_times;
+use short_names::ShortNamesBackend;
+use time;
+use time_utils;
+
+mod bench;
+mod compare;
+mod diff;
+mod du;
+mod health;
+mod list;
+
+
+// the backend used to access the backups
+type Local = ShortNamesBackend<LocalBackend>;
+
+/// A subcommand working on a backup without mounting it.
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    run: fn(&[String]) -> Result<(), String>,
+}
+
+const COMMANDS: [Command; 6] = [Command {
+                                    name: "bench",
+                                    usage: bench::USAGE,
+                                    run: bench::run,
+                                },
+                                Command {
+                                    name: "compare",
+                                    usage: compare::USAGE,
+                                    run: compare::run,
+                                },
+                                Command {
+                                    name: "diff",
+                                    usage: diff::USAGE,
+                                    run: diff::run,
+                                },
+                                Command {
+                                    name: "du",
+                                    usage: du::USAGE,
+                                    run: du::run,
+                                },
+                                Command {
+                                    name: "health",
+                                    usage: health::USAGE,
+                                    run: health::run,
+                                },
+                                Command {
+                                    name: "list",
+                                    usage: list::USAGE,
+                                    run: list::run,
+                                }];
+
+
+/// Returns whether the arguments, excluding the program name, start with a subcommand.
+pub fn is_command(args: &[String]) -> bool {
+    args.first().map_or(false, |name| find(name).is_some())
+}
+
+/// Runs the subcommand named by the first argument.
+pub fn run(program: &str, args: &[String]) -> Result<(), String> {
+    let cmd = try!(args.first()
+                       .and_then(|name| find(name))
+                       .ok_or_else(|| "unknown command".to_owned()));
+    let args = &args[1..];
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("Usage: {} {} {}", program, cmd.name, cmd.usage);
+        return Ok(());
+    }
+    (cmd.run)(args).map_err(|e| format!("{}\n\nUsage: {} {} {}", e, program, cmd.name, cmd.usage))
+}
+
+/// Returns the list of the subcommands, for the help message.
+pub fn usage(program: &str) -> String {
+    COMMANDS.iter()
+            .map(|cmd| format!("    {} {} {}", program, cmd.name, cmd.usage))
+            .collect::<Vec<_>>()
+            .join("\n")
+}
+
+
+fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|cmd| cmd.name == name)
+}
+
+/// Opens the backup in the given directory.
+fn open_backup(path: &str) -> Result<Backup<Local>, String> {
+    Backup::new(ShortNamesBackend::new(LocalBackend::new(path))).map_err(|e| {
+        Error::OpenBackup {
+            path: PathBuf::from(path),
+            cause: e,
+        }
+        .to_string()
+    })
+}
+
+/// Finds a snapshot by its directory name in the mount, or by a time as accepted by duplicity's
+/// `--restore-time`, in which case the most recent snapshot taken at or before it is chosen.
+fn find_snapshot<'a>(backup: &'a Backup<Local>, name: &str) -> Result<Snapshot<'a>, String> {
+    let mut snapshots = try!(backup.snapshots().map_err(|e| e.to_string())).collect::<Vec<_>>();
+    let times = path_to_times(name);
+    let exact = snapshots.iter()
+                         .position(|s| times.iter().any(|time| time.sec == s.time().sec));
+    if let Some(pos) = exact {
+        return Ok(snapshots.swap_remove(pos));
+    }
+    let time = try!(time_utils::parse_time_spec(name, time::get_time())
+                        .ok_or_else(|| format!("snapshot {} not found", name)));
+    snapshots.into_iter()
+             .filter(|snapshot| snapshot.time().sec <= time.sec)
+             .max_by_key(|snapshot| snapshot.time().sec)
+             .ok_or_else(|| format!("no snapshot taken at or before {}", name))
+}
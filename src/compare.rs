@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use ruplicity::signatures::EntryType;
+use time::Timespec;
+
+use diff::{self, Diff, EntryInfo};
+
+
+/// Compares the entries of a snapshot against the ones of a live directory.
+///
+/// Snapshots record a range of sizes for files, and times in seconds, so live entries within
+/// those are taken as unchanged. Both the entry lists have to be sorted by path.
+pub fn compare(backed_up: Vec<EntryInfo>,
+               mut live: Vec<EntryInfo>)
+               -> Diff<::std::vec::IntoIter<EntryInfo>, ::std::vec::IntoIter<EntryInfo>> {
+    {
+        let recorded = backed_up.iter()
+                                .map(|entry| (entry.path.as_path(), entry))
+                                .collect::<HashMap<_, _>>();
+        for entry in &mut live {
+            if let Some(recorded) = recorded.get(entry.path.as_path()) {
+                normalize(entry, recorded);
+            }
+        }
+    }
+    diff::diff(backed_up, live)
+}
+
+/// Reads the metadata of a live directory tree, sorted by path as in the snapshots.
+///
+/// Paths are relative to the root, which comes first with an empty path. Symbolic links are not
+/// followed, and sockets are left out, since duplicity doesn't back them up.
+pub fn live_entries(root: &Path) -> io::Result<Vec<EntryInfo>> {
+    let mut result = Vec::new();
+    let metadata = try!(fs::symlink_metadata(root).map_err(|e| with_path(e, root)));
+    try!(add_live_entry(root, PathBuf::new(), &metadata, &mut result));
+    Ok(result)
+}
+
+
+fn add_live_entry(root: &Path,
+                  path: PathBuf,
+                  metadata: &Metadata,
+                  result: &mut Vec<EntryInfo>)
+                  -> io::Result<()> {
+    let file_type = metadata.file_type();
+    let entry_type = if file_type.is_dir() {
+        EntryType::Dir
+    } else if file_type.is_file() {
+        EntryType::File
+    } else if file_type.is_symlink() {
+        EntryType::SymLink
+    } else if file_type.is_fifo() {
+        EntryType::Fifo
+    } else if file_type.is_char_device() {
+        EntryType::Unknown(b'3')
+    } else if file_type.is_block_device() {
+        EntryType::Unknown(b'4')
+    } else {
+        return Ok(());
+    };
+    let full_path = root.join(&path);
+    let link = if entry_type == EntryType::SymLink {
+        Some(try!(fs::read_link(&full_path).map_err(|e| with_path(e, &full_path))))
+    } else {
+        None
+    };
+    let size = if entry_type == EntryType::File {
+        Some(metadata.len())
+    } else {
+        None
+    };
+    result.push(EntryInfo {
+        path: path.clone(),
+        entry_type: entry_type,
+        mtime: Timespec::new(metadata.mtime(), metadata.mtime_nsec() as i32),
+        size: size,
+        min_size: size,
+        mode: Some(metadata.mode() & 0o7777),
+        uid: Some(metadata.uid()),
+        gid: Some(metadata.gid()),
+        link: link,
+    });
+
+    if entry_type == EntryType::Dir {
+        let mut children = Vec::new();
+        for child in try!(fs::read_dir(&full_path).map_err(|e| with_path(e, &full_path))) {
+            let child = try!(child.map_err(|e| with_path(e, &full_path)));
+            let metadata = try!(child.metadata().map_err(|e| with_path(e, &child.path())));
+            children.push((child.file_name(), metadata));
+        }
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, metadata) in children {
+            try!(add_live_entry(root, path.join(name), &metadata, result));
+        }
+    }
+    Ok(())
+}
+
+/// Brings the metadata of a live entry to the precision of the recorded one, where they agree.
+fn normalize(live: &mut EntryInfo, recorded: &EntryInfo) {
+    if live.entry_type != recorded.entry_type {
+        return;
+    }
+    let size_matches = match (live.size, recorded.min_size, recorded.size) {
+        (Some(size), Some(min), Some(max)) => min <= size && size <= max,
+        // only the sizes of files are compared
+        _ => live.entry_type != EntryType::File,
+    };
+    if size_matches {
+        live.size = recorded.size;
+        live.min_size = recorded.min_size;
+    }
+    if live.mtime.sec == recorded.mtime.sec {
+        live.mtime = recorded.mtime;
+    }
+    let mode_matches = match (live.mode, recorded.mode) {
+        (Some(live), Some(recorded)) => live == recorded & 0o7777,
+        _ => false,
+    };
+    if mode_matches {
+        live.mode = recorded.mode;
+    }
+}
+
+fn with_path(e: io::Error, path: &Path) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::symlink;
+    use std::process;
+
+    use backend::memory::{self, BackupBuilder};
+    use diff::Change;
+    use ruplicity::Backup;
+
+    fn backed_up() -> Vec<EntryInfo> {
+        let backend = BackupBuilder::new()
+                          .full(1500000000,
+                                &[memory::Entry::dir("etc"),
+                                  memory::Entry::file("etc/hosts", b"127.0.0.1 localhost\n"),
+                                  memory::Entry::file("etc/passwd", &[0; 1000]),
+                                  memory::Entry::symlink("hosts", "etc/hosts"),
+                                  memory::Entry::file("motd", b"hello\n")])
+                          .build();
+        let backup = Backup::new(backend).unwrap();
+        let snapshot = backup.snapshots().unwrap().next().unwrap();
+        let entries = snapshot.entries().unwrap();
+        let result = entries.as_signature().map(|e| EntryInfo::from(&e)).collect();
+        result
+    }
+
+    /// Returns an entry as read from a live directory matching the snapshot.
+    fn live(entry: &EntryInfo) -> EntryInfo {
+        // the largest size the signature allows, and a time with nanoseconds
+        let size = if entry.entry_type == EntryType::File { entry.size } else { None };
+        EntryInfo {
+            path: entry.path.clone(),
+            entry_type: entry.entry_type,
+            mtime: Timespec::new(entry.mtime.sec, 123456789),
+            size: size,
+            min_size: size,
+            mode: entry.mode.map(|mode| mode & 0o7777),
+            uid: entry.uid,
+            gid: entry.gid,
+            link: entry.link.clone(),
+        }
+    }
+
+    fn find<'a>(entries: &'a mut [EntryInfo], path: &str) -> &'a mut EntryInfo {
+        entries.iter_mut().find(|e| e.path == Path::new(path)).unwrap()
+    }
+
+    #[test]
+    fn matching_live_dir() {
+        let backed_up = backed_up();
+        let current = backed_up.iter().map(live).collect::<Vec<_>>();
+        let changes = compare(backed_up, current).collect::<Vec<_>>();
+        assert!(changes.is_empty(), "{:?}", changes);
+    }
+
+    #[test]
+    fn mismatches() {
+        let backed_up = backed_up();
+        let mut current = backed_up.iter().map(live).collect::<Vec<_>>();
+        find(&mut current, "etc").mode = Some(0o700);
+        find(&mut current, "etc/hosts").mtime.sec += 1;
+        // larger than the range recorded in the signature
+        find(&mut current, "etc/passwd").size = Some(1025);
+        {
+            let motd = find(&mut current, "motd");
+            motd.entry_type = EntryType::Dir;
+            motd.size = None;
+            motd.min_size = None;
+            motd.mode = Some(0o755);
+        }
+        current.retain(|e| e.path != Path::new("hosts"));
+        current.push(EntryInfo {
+            path: PathBuf::from("new"),
+            entry_type: EntryType::File,
+            mtime: Timespec::new(1500003600, 0),
+            size: Some(3),
+            min_size: Some(3),
+            mode: Some(0o644),
+            uid: Some(0),
+            gid: Some(0),
+            link: None,
+        });
+
+        let changes = compare(backed_up, current).collect::<Vec<_>>();
+        let summaries = changes.iter()
+                               .map(|change| {
+                                   let path = change.latest().path.to_string_lossy().into_owned();
+                                   match *change {
+                                       Change::Added(_) => ('A', path),
+                                       Change::Removed(_) => ('D', path),
+                                       Change::Changed(..) => ('M', path),
+                                   }
+                               })
+                               .collect::<Vec<_>>();
+        let expected = [('M', "etc"),
+                        ('M', "etc/hosts"),
+                        ('M', "etc/passwd"),
+                        ('D', "hosts"),
+                        ('M', "motd"),
+                        ('A', "new")];
+        assert_eq!(summaries,
+                   expected.iter()
+                           .map(|&(kind, path)| (kind, path.to_owned()))
+                           .collect::<Vec<_>>());
+        // only the metadata that really differs is reported
+        let suffixes = changes.iter()
+                              .map(|change| change.to_string())
+                              .filter_map(|line| line.find(" (").map(|pos| line[pos..].to_owned()))
+                              .collect::<Vec<_>>();
+        assert_eq!(suffixes[0], " (mode)");
+        assert_eq!(suffixes[1], " (mtime)");
+        assert_eq!(suffixes[2], " (size)");
+        assert!(suffixes[3].starts_with(" (type"), "{}", suffixes[3]);
+    }
+
+    #[test]
+    fn live_directory() {
+        let root = env::temp_dir().join(format!("ruplicity-fuse-compare-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/c"), b"content").unwrap();
+        fs::write(root.join("b"), &[0; 1000][..]).unwrap();
+        symlink("b", root.join("l")).unwrap();
+        let entries = live_entries(&root);
+        fs::remove_dir_all(&root).unwrap();
+
+        let entries = entries.unwrap();
+        let paths = entries.iter().map(|e| e.path.to_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["", "a", "a/c", "b", "l"]);
+        let types = entries.iter().map(|e| e.entry_type).collect::<Vec<_>>();
+        assert_eq!(types,
+                   vec![EntryType::Dir,
+                        EntryType::Dir,
+                        EntryType::File,
+                        EntryType::File,
+                        EntryType::SymLink]);
+        assert_eq!(entries[2].size, Some(7));
+        assert_eq!(entries[3].size, Some(1000));
+        assert_eq!(entries[4].size, None);
+        assert_eq!(entries[4].link, Some(PathBuf::from("b")));
+
+        let error = live_entries(&root).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains(&root.display().to_string()));
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+use ruplicity::{Backend, Backup};
+
+use manifest::Manifest;
+
+
+// the prefix of the files written by duplicity
+const DUPLICITY_PREFIX: &'static str = "duplicity-";
+
+
+/// Looks for the parts of a backup that are not understood, as written by newer duplicity
+/// versions, returning a description of each.
+///
+/// Files named as duplicity files but not recognized as part of any backup set are reported, as
+/// well as unknown fields in the manifests. Both are ignored while mounting, so they may make
+/// the mount incomplete.
+pub fn check<B: Backend>(backup: &Backup<B>, backend: &B) -> io::Result<Vec<String>> {
+    let snapshots = try!(backup.snapshots());
+    let collections = snapshots.as_collections();
+    let mut known = HashSet::new();
+    let mut manifests = Vec::new();
+    for chain in collections.backup_chains() {
+        for set in Some(chain.full_set()).into_iter().chain(chain.inc_sets()) {
+            known.insert(set.manifest_path().to_owned());
+            for num in 1..set.num_volumes() + 1 {
+                if let Some(path) = set.volume_path(num as i32) {
+                    known.insert(path.to_owned());
+                }
+            }
+            if set.is_complete() && !set.is_encrypted() {
+                manifests.push(set.manifest_path().to_owned());
+            }
+        }
+    }
+    for chain in collections.signature_chains() {
+        known.insert(chain.full_signature().file_name.clone());
+        for sig in chain.inc_signatures() {
+            known.insert(sig.file_name.clone());
+        }
+    }
+
+    let mut result = Vec::new();
+    for name in try!(backend.file_names()) {
+        let name = name.as_ref().to_string_lossy().into_owned();
+        if name.starts_with(DUPLICITY_PREFIX) && !known.contains(&name) {
+            result.push(format!("{} is not recognized as part of a backup set", name));
+        }
+    }
+    for path in manifests {
+        let manifest = backend.open_file(Path::new(&path)).and_then(Manifest::from_reader);
+        match manifest {
+            Ok(manifest) => {
+                for key in manifest.unknown_keys {
+                    result.push(format!("manifest {} has unknown field '{}'", path, key));
+                }
+            }
+            Err(e) => {
+                result.push(format!("cannot read manifest {}: {}", path, e));
+            }
+        }
+    }
+    Ok(result)
+}
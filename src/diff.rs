@@ -0,0 +1,282 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+use std::iter::Peekable;
+use std::path::PathBuf;
+
+use ruplicity::signatures::{Entry, EntryType};
+use time::Timespec;
+
+use time_utils;
+
+
+/// The metadata of a snapshot entry, detached from the signatures it comes from.
+///
+/// Entries borrow the signature chain they belong to, and ruplicity doesn't allow two snapshots of
+/// the same chain to be borrowed at the same time, so at least one side of a comparison needs to
+/// be copied out.
+#[derive(Debug, PartialEq)]
+pub struct EntryInfo {
+    pub path: PathBuf,
+    pub entry_type: EntryType,
+    pub mtime: Timespec,
+    /// The upper bound of the size, the only one shown.
+    pub size: Option<u64>,
+    /// The lower bound of the size, since the signatures give a range.
+    pub min_size: Option<u64>,
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub link: Option<PathBuf>,
+}
+
+/// A difference between two snapshots.
+#[derive(Debug)]
+pub enum Change {
+    /// The entry is present only in the new snapshot.
+    Added(EntryInfo),
+    /// The entry is present only in the old snapshot.
+    Removed(EntryInfo),
+    /// The entry is present in both snapshots, but its metadata differs.
+    Changed(EntryInfo, EntryInfo),
+}
+
+/// Iterator over the differences between two snapshots.
+///
+/// Both the entry lists are sorted by path, so they are compared in a single pass.
+pub struct Diff<I: Iterator, J: Iterator> {
+    old: Peekable<I>,
+    new: Peekable<J>,
+}
+
+
+/// Compares the entries of an old snapshot against the ones of a new snapshot.
+///
+/// The entries must be sorted by path, as returned by the snapshot signatures.
+pub fn diff<I, J>(old: I, new: J) -> Diff<I::IntoIter, J::IntoIter>
+    where I: IntoIterator<Item = EntryInfo>,
+          J: IntoIterator<Item = EntryInfo>
+{
+    Diff {
+        old: old.into_iter().peekable(),
+        new: new.into_iter().peekable(),
+    }
+}
+
+
+impl<'a, 'b> From<&'b Entry<'a>> for EntryInfo {
+    fn from(entry: &Entry) -> Self {
+        EntryInfo {
+            path: entry.path().to_path_buf(),
+            entry_type: entry.entry_type(),
+            mtime: entry.mtime(),
+            size: entry.size_hint().map(|sh| sh.1 as u64),
+            min_size: entry.size_hint().map(|sh| sh.0 as u64),
+            mode: entry.mode(),
+            uid: entry.userid(),
+            gid: entry.groupid(),
+            link: entry.linked_path().map(|p| p.to_path_buf()),
+        }
+    }
+}
+
+
+impl<I, J> Iterator for Diff<I, J>
+    where I: Iterator<Item = EntryInfo>,
+          J: Iterator<Item = EntryInfo>
+{
+    type Item = Change;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.old.peek(), self.new.peek()) {
+                (Some(old), Some(new)) => old.path.cmp(&new.path),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => {
+                    return None;
+                }
+            };
+            let change = match order {
+                Ordering::Less => Change::Removed(self.old.next().unwrap()),
+                Ordering::Greater => Change::Added(self.new.next().unwrap()),
+                Ordering::Equal => {
+                    let old = self.old.next().unwrap();
+                    let new = self.new.next().unwrap();
+                    if old == new {
+                        continue;
+                    }
+                    Change::Changed(old, new)
+                }
+            };
+            // the root directory is always present, its changes are not interesting
+            if !change.latest().path.as_os_str().is_empty() {
+                return Some(change);
+            }
+        }
+    }
+}
+
+
+impl Change {
+    /// Returns the entry as it is in the newest snapshot containing it.
+    pub fn latest(&self) -> &EntryInfo {
+        match *self {
+            Change::Added(ref e) |
+            Change::Changed(_, ref e) |
+            Change::Removed(ref e) => e,
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let kind = match *self {
+            Change::Added(_) => 'A',
+            Change::Removed(_) => 'D',
+            Change::Changed(..) => 'M',
+        };
+        let entry = self.latest();
+        let size = entry.size.map_or("-".to_owned(), |s| s.to_string());
+        let mtime = time_utils::local(entry.mtime).format("%Y-%m-%d %H:%M:%S");
+        try!(write!(f, "{} {:>12} {} {}", kind, size, mtime, entry.path.display()));
+        if let Change::Changed(ref old, ref new) = *self {
+            try!(write!(f, " ({})", changed_fields(old, new).join(", ")));
+        }
+        Ok(())
+    }
+}
+
+
+/// Returns the names of the metadata that differ between the two entries.
+fn changed_fields(old: &EntryInfo, new: &EntryInfo) -> Vec<&'static str> {
+    let fields = [("type", old.entry_type != new.entry_type),
+                  ("size", old.size != new.size || old.min_size != new.min_size),
+                  ("mtime", old.mtime != new.mtime),
+                  ("mode", old.mode != new.mode),
+                  ("owner", old.uid != new.uid || old.gid != new.gid),
+                  ("link", old.link != new.link)];
+    fields.iter().filter(|f| f.1).map(|f| f.0).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    use backend::memory::{self, BackupBuilder, MemoryBackend};
+    use ruplicity::Backup;
+
+    const FULL_TIME: i64 = 1500000000;
+    const INC_TIME: i64 = 1500003600;
+
+    fn sample() -> MemoryBackend {
+        BackupBuilder::new()
+            .full(FULL_TIME,
+                  &[memory::Entry::dir("etc"),
+                    memory::Entry::file("etc/hosts", b"127.0.0.1 localhost\n"),
+                    memory::Entry::file("etc/passwd", &[0; 1000]),
+                    memory::Entry::symlink("hosts", "etc/hosts"),
+                    memory::Entry::file("motd", b"hello\n"),
+                    memory::Entry::file("old", b"x")])
+            .inc(INC_TIME,
+                 &[memory::Entry::dir("etc"),
+                   memory::Entry::file("etc/group", b"root:x:0:\n"),
+                   memory::Entry::file("etc/hosts", b"127.0.0.1 localhost\n"),
+                   memory::Entry::file("etc/passwd", &[0; 2000]),
+                   memory::Entry::symlink("hosts", "etc/group"),
+                   memory::Entry::dir("motd")])
+            .build()
+    }
+
+    /// Returns the entries of every snapshot of a backup.
+    fn snapshot_entries(backend: MemoryBackend) -> Vec<Vec<EntryInfo>> {
+        let backup = Backup::new(backend).unwrap();
+        let result = backup.snapshots()
+                           .unwrap()
+                           .map(|snapshot| {
+                               let entries = snapshot.entries().unwrap();
+                               entries.as_signature()
+                                      .map(|e| EntryInfo::from(&e))
+                                      .collect::<Vec<_>>()
+                           })
+                           .collect();
+        result
+    }
+
+    fn summary(change: &Change) -> (char, String, Vec<&'static str>) {
+        let path = change.latest().path.to_string_lossy().into_owned();
+        match *change {
+            Change::Added(_) => ('A', path, Vec::new()),
+            Change::Removed(_) => ('D', path, Vec::new()),
+            Change::Changed(ref old, ref new) => ('M', path, changed_fields(old, new)),
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let mut snapshots = snapshot_entries(sample());
+        let new = snapshots.pop().unwrap();
+        let old = snapshots.pop().unwrap();
+        let changes = diff(old, new).collect::<Vec<_>>();
+        let summaries = changes.iter().map(summary).collect::<Vec<_>>();
+        let kinds = summaries.iter().map(|s| (s.0, s.1.as_str())).collect::<Vec<_>>();
+        assert_eq!(kinds,
+                   vec![('A', "etc/group"),
+                        ('M', "etc/passwd"),
+                        ('M', "hosts"),
+                        ('M', "motd"),
+                        ('D', "old")]);
+        assert_eq!(summaries[1].2, vec!["size", "mtime"]);
+        assert_eq!(summaries[2].2, vec!["mtime", "link"]);
+        // the changed entries are reported as they are in the new snapshot
+        let passwd = &changes[1];
+        assert_eq!(passwd.latest().size, Some(2048));
+        assert_eq!(passwd.latest().mtime, Timespec::new(INC_TIME, 0));
+        match changes[4] {
+            Change::Removed(ref old) => assert_eq!(old.mtime, Timespec::new(FULL_TIME, 0)),
+            ref other => panic!("unexpected change {:?}", other),
+        }
+    }
+
+    #[test]
+    fn type_change() {
+        let mut snapshots = snapshot_entries(sample());
+        let new = snapshots.pop().unwrap();
+        let old = snapshots.pop().unwrap();
+        let motd = diff(old, new).find(|c| c.latest().path == Path::new("motd")).unwrap();
+        match motd {
+            Change::Changed(ref old, ref new) => {
+                assert_eq!(old.entry_type, EntryType::File);
+                assert_eq!(new.entry_type, EntryType::Dir);
+                let fields = changed_fields(old, new);
+                assert_eq!(fields[0], "type");
+                assert!(fields.contains(&"mode"));
+            }
+            other => panic!("unexpected change {:?}", other),
+        }
+    }
+
+    #[test]
+    fn same_snapshot() {
+        let old = snapshot_entries(sample());
+        let new = snapshot_entries(sample());
+        for (old, new) in old.into_iter().zip(new) {
+            assert_eq!(diff(old, new).count(), 0);
+        }
+    }
+
+    #[test]
+    fn display() {
+        time_utils::fixed_time_zone();
+        let mut snapshots = snapshot_entries(sample());
+        let new = snapshots.pop().unwrap();
+        let old = snapshots.pop().unwrap();
+        let lines = diff(old, new).map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(lines[0], format!("A {:>12} 2017-07-14 05:40:00 etc/group", 512));
+        assert_eq!(lines[1],
+                   format!("M {:>12} 2017-07-14 05:40:00 etc/passwd (size, mtime)", 2048));
+        assert_eq!(lines.last().unwrap(),
+                   &format!("D {:>12} 2017-07-14 04:40:00 old", 512));
+    }
+}
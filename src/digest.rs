@@ -0,0 +1,113 @@
+use sha1;
+use sha2;
+use std::fmt::Write;
+use std::io::{self, Read};
+
+
+/// A hash function computed incrementally, as used for the volume hashes of manifests.
+pub trait Digest {
+    /// Adds some data to the hashed content.
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the digest of all the data added.
+    fn finish(self: Box<Self>) -> Vec<u8>;
+}
+
+/// Computes SHA-1, the only hash written by current duplicity versions.
+pub struct Sha1(sha1::Sha1);
+
+/// Computes SHA-256.
+pub struct Sha256(sha2::Sha256);
+
+
+/// Returns the hash function with the name used in manifests, if known.
+///
+/// Names are compared ignoring case and dashes, so `SHA1`, `sha-1` and `Sha1` are the same.
+pub fn from_name(name: &str) -> Option<Box<dyn Digest>> {
+    let name = name.chars().filter(|c| *c != '-').collect::<String>().to_lowercase();
+    match name.as_str() {
+        "sha1" => Some(Box::new(Sha1::new())),
+        "sha256" => Some(Box::new(Sha256::new())),
+        _ => None,
+    }
+}
+
+/// Hashes everything read from a reader, returning the digest as lowercase hexadecimal.
+pub fn hex_digest<R: Read>(mut digest: Box<dyn Digest>, mut reader: R) -> io::Result<String> {
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = try!(reader.read(&mut buffer));
+        if read == 0 {
+            break;
+        }
+        digest.update(&buffer[..read]);
+    }
+    let mut result = String::new();
+    for byte in digest.finish() {
+        let _ = write!(result, "{:02x}", byte);
+    }
+    Ok(result)
+}
+
+
+impl Sha1 {
+    pub fn new() -> Self {
+        Sha1(sha1::Sha1::default())
+    }
+}
+
+impl Digest for Sha1 {
+    fn update(&mut self, data: &[u8]) {
+        sha1::Digest::update(&mut self.0, data);
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        sha1::Digest::finalize(self.0).to_vec()
+    }
+}
+
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Sha256(sha2::Sha256::default())
+    }
+}
+
+impl Digest for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(&mut self.0, data);
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        sha2::Digest::finalize(self.0).to_vec()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex(name: &str, data: &[u8]) -> String {
+        hex_digest(from_name(name).unwrap(), data).unwrap()
+    }
+
+    #[test]
+    fn known_digests() {
+        let million = vec![b'a'; 1000000];
+        let block = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert_eq!(hex("SHA1", b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex("SHA1", b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex("sha-1", block), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+        assert_eq!(hex("SHA1", &million), "34aa973cd4c4daa4f61eeb2bdbad27316534016f");
+        assert_eq!(hex("SHA256", b""),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex("sha256", b"abc"),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hex("SHA-256", block),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+        assert_eq!(hex("SHA256", &million),
+                   "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+        assert!(from_name("MD5").is_none());
+    }
+}
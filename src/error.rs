@@ -0,0 +1,84 @@
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+use std::result;
+
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// An error, along with what was being done and on which object.
+#[derive(Debug)]
+pub enum Error {
+    OpenBackup { path: PathBuf, cause: io::Error },
+    ListSnapshots { cause: io::Error },
+    ReadBackendFile { name: String, cause: io::Error },
+    ListBackendFiles { snapshot: String, cause: io::Error },
+    ReadSignatures { snapshot: String, cause: io::Error },
+    BuildTree { snapshot: String, cause: io::Error },
+    Mount { mountpoint: String, cause: io::Error },
+    WriteTrace { path: String, cause: io::Error },
+    /// An inode of the filesystem that doesn't exist, or no longer does.
+    MissingInode { what: &'static str, ino: u64 },
+}
+
+
+impl Error {
+    /// Returns the kind of the underlying I/O error, or `NotFound` for a missing inode.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.io_error().map_or(io::ErrorKind::NotFound, io::Error::kind)
+    }
+
+    fn io_error(&self) -> Option<&io::Error> {
+        match *self {
+            Error::OpenBackup { ref cause, .. } |
+            Error::ListSnapshots { ref cause } |
+            Error::ReadBackendFile { ref cause, .. } |
+            Error::ListBackendFiles { ref cause, .. } |
+            Error::ReadSignatures { ref cause, .. } |
+            Error::BuildTree { ref cause, .. } |
+            Error::Mount { ref cause, .. } |
+            Error::WriteTrace { ref cause, .. } => Some(cause),
+            Error::MissingInode { .. } => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Error::OpenBackup { ref path, ref cause } => {
+                write!(f, "Can't open the backup in {:?}: {}", path, cause)
+            }
+            Error::ListSnapshots { ref cause } => write!(f, "Can't list the snapshots: {}", cause),
+            Error::ReadBackendFile { ref name, ref cause } => {
+                write!(f, "Can't read the backend file {}: {}", name, cause)
+            }
+            Error::ListBackendFiles { ref snapshot, ref cause } => {
+                write!(f,
+                       "Can't list the backend files of snapshot {}: {}",
+                       snapshot,
+                       cause)
+            }
+            Error::ReadSignatures { ref snapshot, ref cause } => {
+                write!(f, "Can't read the signatures of snapshot {}: {}", snapshot, cause)
+            }
+            Error::BuildTree { ref snapshot, ref cause } => {
+                write!(f, "Can't build the tree of snapshot {}: {}", snapshot, cause)
+            }
+            Error::Mount { ref mountpoint, ref cause } => {
+                write!(f, "Can't mount on {:?}: {}", mountpoint, cause)
+            }
+            Error::WriteTrace { ref path, ref cause } => {
+                write!(f, "Can't write the trace file {}: {}", path, cause)
+            }
+            Error::MissingInode { what, ino } => write!(f, "Can't find {} for ino {}", what, ino),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.io_error().map(|e| e as &(dyn error::Error + 'static))
+    }
+}
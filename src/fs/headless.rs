@@ -0,0 +1,725 @@
+use fuse::{FileAttr, FileType};
+use libc::{EIO, O_RDONLY, c_int};
+use ruplicity::Backend;
+use time::Timespec;
+
+use std::ffi::OsString;
+use std::mem;
+use std::path::{Component, Path};
+use std::result;
+
+use super::RuplicityFs;
+use super::reply::{AttrReply, DataReply, DirectoryReply, EmptyReply, EntryReply, ErrorReply,
+                   OpenReply};
+use logger::RequestScope;
+
+
+/// The result of an operation: the reply or the error number.
+pub type Result<T> = result::Result<T, c_int>;
+
+/// Calls the operations of a filesystem directly, as the kernel would, collecting their replies.
+pub struct Driver<B> {
+    fs: RuplicityFs<B>,
+    last_request: u64,
+}
+
+/// An entry added to a directory reply.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirItem {
+    pub ino: u64,
+    pub offset: u64,
+    pub kind: FileType,
+    pub name: OsString,
+}
+
+// a reply storing its outcome, that turns into EIO if the operation didn't reply, as with fuse
+struct Capture<'a, T: 'a> {
+    out: &'a mut Option<Result<T>>,
+}
+
+// a directory reply filling a buffer of limited size, as fuse does
+struct DirCapture<'a> {
+    out: &'a mut Option<Result<Vec<DirItem>>>,
+    items: Vec<DirItem>,
+    used: usize,
+    size: usize,
+}
+
+
+impl<B: Backend> Driver<B> {
+    pub fn new(fs: RuplicityFs<B>) -> Self {
+        Driver {
+            fs: fs,
+            last_request: 0,
+        }
+    }
+
+    pub fn getattr(&mut self, ino: u64) -> Result<FileAttr> {
+        let _scope = self.request("getattr");
+        run(|reply| self.fs.handle_getattr(ino, reply))
+    }
+
+    pub fn lookup(&mut self, parent: u64, name: &str) -> Result<FileAttr> {
+        let _scope = self.request("lookup");
+        run(|reply| self.fs.handle_lookup(parent, Path::new(name), reply))
+    }
+
+    /// Opens a directory, returning the handle.
+    pub fn opendir(&mut self, ino: u64) -> Result<u64> {
+        let _scope = self.request("opendir");
+        run(|reply| self.fs.handle_opendir(ino, reply)).map(|(fh, _)| fh)
+    }
+
+    /// Reads the entries of an open directory from the given offset, as many as fit in a buffer
+    /// of the given size.
+    pub fn readdir(&mut self, ino: u64, fh: u64, offset: u64, size: usize) -> Result<Vec<DirItem>> {
+        let _scope = self.request("readdir");
+        let mut out = None;
+        self.fs.handle_readdir(ino,
+                               fh,
+                               offset,
+                               DirCapture {
+                                   out: &mut out,
+                                   items: Vec::new(),
+                                   used: 0,
+                                   size: size,
+                               });
+        out.unwrap()
+    }
+
+    pub fn releasedir(&mut self, fh: u64) -> Result<()> {
+        let _scope = self.request("releasedir");
+        run(|reply| self.fs.handle_releasedir(fh, reply))
+    }
+
+    pub fn readlink(&mut self, ino: u64) -> Result<Vec<u8>> {
+        let _scope = self.request("readlink");
+        run(|reply| self.fs.handle_readlink(ino, reply))
+    }
+
+    /// Opens a file with the given flags, returning the handle and the flags of the reply.
+    pub fn open(&mut self, ino: u64, flags: u32) -> Result<(u64, u32)> {
+        let _scope = self.request("open");
+        run(|reply| self.fs.handle_open(ino, flags, reply))
+    }
+
+    pub fn read(&mut self, ino: u64, fh: u64, offset: u64, size: u32) -> Result<Vec<u8>> {
+        let _scope = self.request("read");
+        run(|reply| self.fs.handle_read(ino, fh, offset, size, reply))
+    }
+
+    pub fn release(&mut self, fh: u64) -> Result<()> {
+        let _scope = self.request("release");
+        run(|reply| self.fs.handle_release(fh, reply))
+    }
+
+    /// Lists a whole directory, reading it with buffers of the given size and resuming from the
+    /// offset of the last entry each time, as the kernel does.
+    pub fn list(&mut self, ino: u64, size: usize) -> Result<Vec<DirItem>> {
+        let fh = try!(self.opendir(ino));
+        let mut result = Vec::new();
+        loop {
+            let offset = result.last().map_or(0, |item: &DirItem| item.offset);
+            let items = try!(self.readdir(ino, fh, offset, size));
+            if items.is_empty() {
+                break;
+            }
+            result.extend(items);
+        }
+        try!(self.releasedir(fh));
+        Ok(result)
+    }
+
+    /// Looks up every component of a path relative to the root, returning the last attributes.
+    pub fn resolve(&mut self, path: &str) -> Result<FileAttr> {
+        let mut attr = try!(self.getattr(1));
+        for component in Path::new(path).components() {
+            if let Component::Normal(name) = component {
+                attr = try!(self.lookup(attr.ino, &name.to_string_lossy()));
+            }
+        }
+        Ok(attr)
+    }
+
+    /// Reads a whole file, in chunks of the given size.
+    pub fn read_all(&mut self, ino: u64, size: u32) -> Result<Vec<u8>> {
+        let (fh, _) = try!(self.open(ino, O_RDONLY as u32));
+        let mut result = Vec::new();
+        loop {
+            let chunk = try!(self.read(ino, fh, result.len() as u64, size));
+            if chunk.is_empty() {
+                break;
+            }
+            result.extend(chunk);
+        }
+        try!(self.release(fh));
+        Ok(result)
+    }
+
+    // tags the log messages and the recorded errors with a new request identifier
+    fn request(&mut self, op: &'static str) -> RequestScope {
+        self.last_request += 1;
+        RequestScope::enter(self.last_request, op)
+    }
+}
+
+
+impl<'a, T> Capture<'a, T> {
+    fn done(self, result: Result<T>) {
+        *self.out = Some(result);
+    }
+}
+
+impl<'a, T> Drop for Capture<'a, T> {
+    fn drop(&mut self) {
+        if self.out.is_none() {
+            *self.out = Some(Err(EIO));
+        }
+    }
+}
+
+impl<'a, T> ErrorReply for Capture<'a, T> {
+    fn error(self, err: c_int) {
+        self.done(Err(err));
+    }
+}
+
+impl<'a> AttrReply for Capture<'a, FileAttr> {
+    fn attr(self, _ttl: &Timespec, attr: &FileAttr) {
+        self.done(Ok(*attr));
+    }
+}
+
+impl<'a> EntryReply for Capture<'a, FileAttr> {
+    fn entry(self, _ttl: &Timespec, attr: &FileAttr, _generation: u64) {
+        self.done(Ok(*attr));
+    }
+}
+
+impl<'a> OpenReply for Capture<'a, (u64, u32)> {
+    fn opened(self, fh: u64, flags: u32) {
+        self.done(Ok((fh, flags)));
+    }
+}
+
+impl<'a> DataReply for Capture<'a, Vec<u8>> {
+    fn data(self, data: &[u8]) {
+        self.done(Ok(data.to_vec()));
+    }
+}
+
+impl<'a> EmptyReply for Capture<'a, ()> {
+    fn ok(self) {
+        self.done(Ok(()));
+    }
+}
+
+
+impl<'a> Drop for DirCapture<'a> {
+    fn drop(&mut self) {
+        if self.out.is_none() {
+            *self.out = Some(Err(EIO));
+        }
+    }
+}
+
+impl<'a> ErrorReply for DirCapture<'a> {
+    fn error(self, err: c_int) {
+        *self.out = Some(Err(err));
+    }
+}
+
+impl<'a> DirectoryReply for DirCapture<'a> {
+    fn add(&mut self, ino: u64, offset: u64, kind: FileType, name: &Path) -> bool {
+        // the size of the entry header, followed by the name and aligned to 8 bytes
+        let entry_size = (24 + name.as_os_str().len() + 7) & !7;
+        if self.used + entry_size > self.size {
+            return true;
+        }
+        self.used += entry_size;
+        self.items.push(DirItem {
+            ino: ino,
+            offset: offset,
+            kind: kind,
+            name: name.as_os_str().to_owned(),
+        });
+        false
+    }
+
+    fn ok(mut self) {
+        *self.out = Some(Ok(mem::replace(&mut self.items, Vec::new())));
+    }
+}
+
+
+// runs an operation with a capturing reply, returning its outcome
+fn run<T, F>(f: F) -> Result<T>
+    where F: FnOnce(Capture<T>)
+{
+    let mut out = None;
+    f(Capture { out: &mut out });
+    out.unwrap()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use backend::memory::{BackupBuilder, Entry, MemoryBackend, arb_chain};
+    use fs::{FsOptions, Layout, Ownership, Preload, RuplicityFs, SymlinkRewrite, UnknownEntries,
+             time_to_path};
+    use fs::info::INFO_FILE;
+    use fs::special::DUPLICITY_DIR;
+    use fs::status::{DEGRADED_FILE, ERRORS_FILE, STATS_FILE, STATUS_DIR};
+
+    use fuse::FileType;
+    use libc::{ENOENT, EROFS, O_RDWR};
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+    use ruplicity::Backup;
+    use stats::SharedStats;
+    use std::path::PathBuf;
+    use time::Timespec;
+
+
+    fn options() -> FsOptions {
+        FsOptions {
+            ownership: Ownership::Preserve,
+            layout: Layout::Flat,
+            volatile: false,
+            track_atime: false,
+            unknown_entries: UnknownEntries::Hide,
+            rewrite_symlinks: SymlinkRewrite::None,
+            mountpoint: PathBuf::from("/mnt"),
+            strip_prefix: Vec::new(),
+            add_prefix: Vec::new(),
+            strict: true,
+            check_tree: true,
+            best_effort: false,
+        }
+    }
+
+    fn driver(backend: MemoryBackend) -> Driver<MemoryBackend> {
+        driver_with_layout(backend, Layout::Flat)
+    }
+
+    fn driver_with_layout(backend: MemoryBackend, layout: Layout) -> Driver<MemoryBackend> {
+        driver_with(backend, FsOptions { layout: layout, ..options() })
+    }
+
+    fn driver_with(backend: MemoryBackend, options: FsOptions) -> Driver<MemoryBackend> {
+        let backup = Backup::new(backend.clone()).unwrap();
+        Driver::new(RuplicityFs::new(backup, backend, options).unwrap())
+    }
+
+    fn sample() -> Driver<MemoryBackend> {
+        let files = (0..50)
+                        .map(|i| Entry::file(&format!("dir/file-with-a-long-name-{:02}", i), b"x"))
+                        .collect::<Vec<_>>();
+        let mut entries = vec![Entry::dir("dir"), Entry::symlink("link", "dir")];
+        entries.extend(files);
+        driver(BackupBuilder::new().full(1500000000, &entries).build())
+    }
+
+    fn names(items: &[DirItem]) -> Vec<String> {
+        items.iter().map(|item| item.name.to_string_lossy().into_owned()).collect()
+    }
+
+    fn snapshot_dir(driver: &mut Driver<MemoryBackend>) -> String {
+        let root = driver.list(1, 4096).unwrap();
+        names(&root).into_iter().find(|name| name.starts_with("20")).unwrap()
+    }
+
+    #[test]
+    fn readdir_resumes_from_offset() {
+        let mut driver = sample();
+        let snapshot = snapshot_dir(&mut driver);
+        let dir = driver.resolve(&format!("{}/dir", snapshot)).unwrap();
+        let whole = driver.list(dir.ino, 1 << 16).unwrap();
+        assert_eq!(whole.len(), 52);
+        // directories report their number of children as size
+        assert_eq!(dir.size, 50);
+        // small buffers take many calls, that must neither skip nor repeat entries
+        for size in &[64, 100, 512] {
+            assert_eq!(driver.list(dir.ino, *size).unwrap(), whole);
+        }
+    }
+
+    #[test]
+    fn lookup_agrees_with_getattr() {
+        let mut driver = sample();
+        let snapshot = snapshot_dir(&mut driver);
+        let dir = driver.resolve(&snapshot).unwrap();
+        let mut pending = vec![dir.ino];
+        while let Some(ino) = pending.pop() {
+            for item in driver.list(ino, 4096).unwrap() {
+                if item.name == OsString::from(".") || item.name == OsString::from("..") {
+                    continue;
+                }
+                let attr = driver.lookup(ino, &item.name.to_string_lossy()).unwrap();
+                assert_eq!(attr.ino, item.ino);
+                assert_eq!(attr.kind, item.kind);
+                let same = driver.getattr(item.ino).unwrap();
+                assert_eq!((same.ino, same.kind, same.size), (attr.ino, attr.kind, attr.size));
+                if attr.kind == FileType::Directory {
+                    pending.push(attr.ino);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn symlinks() {
+        let mut driver = sample();
+        let snapshot = snapshot_dir(&mut driver);
+        let link = driver.resolve(&format!("{}/link", snapshot)).unwrap();
+        assert_eq!(link.kind, FileType::Symlink);
+        assert_eq!(driver.readlink(link.ino), Ok(b"dir".to_vec()));
+    }
+
+    #[test]
+    fn rewritten_symlinks() {
+        let entries = [Entry::dir("etc"),
+                       Entry::symlink("etc/rel", "../etc"),
+                       Entry::symlink("etc/abs", "/usr/bin/vi"),
+                       Entry::symlink("top", "/etc")];
+        let backend = BackupBuilder::new().full(1500000000, &entries).build();
+        let snapshot = time_to_path(Timespec::new(1500000000, 0));
+        let expected = [(SymlinkRewrite::None, ["../etc", "/usr/bin/vi", "/etc"]),
+                        (SymlinkRewrite::Relative, ["../etc", "../usr/bin/vi", "etc"]),
+                        (SymlinkRewrite::Prefix,
+                         ["../etc",
+                          &format!("/mnt/{}/usr/bin/vi", snapshot),
+                          &format!("/mnt/{}/etc", snapshot)])];
+        for &(rewrite, ref targets) in &expected {
+            let options = FsOptions { rewrite_symlinks: rewrite, ..options() };
+            let mut driver = driver_with(backend.clone(), options);
+            for (path, target) in ["etc/rel", "etc/abs", "top"].iter().zip(targets.iter()) {
+                let link = driver.resolve(&format!("{}/{}", snapshot, path)).unwrap();
+                assert_eq!(driver.readlink(link.ino), Ok(target.as_bytes().to_vec()));
+            }
+        }
+    }
+
+    #[test]
+    fn prefixes() {
+        let entries = [Entry::dir("etc"),
+                       Entry::dir("home"),
+                       Entry::dir("home/user"),
+                       Entry::file("home/user/a", b"a"),
+                       Entry::symlink("home/user/l", "/home/user/a"),
+                       Entry::symlink("home/user/out", "/etc/passwd"),
+                       Entry::file("var", b"v")];
+        let backend = BackupBuilder::new().full(1500000000, &entries).build();
+        let snapshot = time_to_path(Timespec::new(1500000000, 0));
+        let prefix = vec!["home".to_owned(), "user".to_owned()];
+
+        let strip = FsOptions {
+            strip_prefix: prefix.clone(),
+            rewrite_symlinks: SymlinkRewrite::Relative,
+            ..options()
+        };
+        let mut stripped = driver_with(backend.clone(), strip);
+        let dir = stripped.resolve(&snapshot).unwrap();
+        assert_eq!(names(&stripped.list(dir.ino, 4096).unwrap())[4..], ["a", "l", "out"]);
+        let link = stripped.resolve(&format!("{}/l", snapshot)).unwrap();
+        assert_eq!(stripped.readlink(link.ino), Ok(b"a".to_vec()));
+        // targets outside of the prefix are not in the mount
+        let out = stripped.resolve(&format!("{}/out", snapshot)).unwrap();
+        assert_eq!(stripped.readlink(out.ino), Ok(b"/etc/passwd".to_vec()));
+
+        // the snapshot directory takes the place of the innermost one
+        let add = FsOptions {
+            add_prefix: prefix,
+            rewrite_symlinks: SymlinkRewrite::Prefix,
+            ..options()
+        };
+        let mut added = driver_with(backend, add);
+        let dir = added.resolve(&format!("{}/home/user", snapshot)).unwrap();
+        assert_eq!(names(&added.list(dir.ino, 4096).unwrap())[2..],
+                   [DUPLICITY_DIR, INFO_FILE, "etc", "home", "var"]);
+        let base = format!("{}/home/user/home/user", snapshot);
+        assert!(added.resolve(&format!("{}/a", base)).is_ok());
+        let link = added.resolve(&format!("{}/l", base)).unwrap();
+        assert_eq!(added.readlink(link.ino),
+                   Ok(format!("/mnt/{}/home/user/a", snapshot).into_bytes()));
+        let out = added.resolve(&format!("{}/out", base)).unwrap();
+        assert_eq!(added.readlink(out.ino), Ok(b"/etc/passwd".to_vec()));
+    }
+
+    #[test]
+    fn missing_entries() {
+        let mut driver = sample();
+        let snapshot = snapshot_dir(&mut driver);
+        let dir = driver.resolve(&snapshot).unwrap();
+        assert_eq!(driver.lookup(dir.ino, "missing").map(|a| a.ino), Err(ENOENT));
+        assert_eq!(driver.lookup(1, "missing").map(|a| a.ino), Err(ENOENT));
+    }
+
+    #[test]
+    fn deleted_entries_are_hidden() {
+        let times = [1500000000, 1500003600];
+        let backend = BackupBuilder::new()
+                          .full(times[0],
+                                &[Entry::dir("dir"),
+                                  Entry::file("dir/gone", b"gone"),
+                                  Entry::file("dir/kept", b"kept"),
+                                  Entry::file("old", b"old")])
+                          .inc(times[1], &[Entry::dir("dir"), Entry::file("dir/kept", b"kept")])
+                          .build();
+        let mut driver = driver(backend);
+        let full = time_to_path(Timespec::new(times[0], 0));
+        let inc = time_to_path(Timespec::new(times[1], 0));
+        assert!(driver.resolve(&format!("{}/dir/gone", full)).is_ok());
+        assert!(driver.resolve(&format!("{}/old", full)).is_ok());
+
+        // the incremental signatures record the deletions, which are not entries
+        let snapshot = driver.lookup(1, &inc).unwrap();
+        assert_eq!(driver.lookup(snapshot.ino, "old").map(|attr| attr.ino), Err(ENOENT));
+        let root = names(&driver.list(snapshot.ino, 4096).unwrap());
+        assert!(root.contains(&"dir".to_owned()), "{:?}", root);
+        assert!(!root.contains(&"old".to_owned()), "{:?}", root);
+        let dir = driver.lookup(snapshot.ino, "dir").unwrap();
+        assert_eq!(dir.size, 1);
+        assert_eq!(driver.lookup(dir.ino, "gone").map(|attr| attr.ino), Err(ENOENT));
+        assert_eq!(names(&driver.list(dir.ino, 4096).unwrap()), vec![".", "..", "kept"]);
+    }
+
+    #[test]
+    fn read_only() {
+        let mut driver = sample();
+        let snapshot = snapshot_dir(&mut driver);
+        let info = driver.resolve(&format!("{}/{}", snapshot, INFO_FILE)).unwrap();
+        assert_eq!(driver.open(info.ino, O_RDWR as u32), Err(EROFS));
+        let content = driver.read_all(info.ino, 16).unwrap();
+        assert_eq!(content.len() as u64, info.size);
+    }
+
+    #[test]
+    fn failures_are_recorded() {
+        let mut driver = sample();
+        let snapshot = snapshot_dir(&mut driver);
+        let path = format!("{}/dir/file-with-a-long-name-00", snapshot);
+        let file = driver.resolve(&path).unwrap();
+        let (fh, _) = driver.open(file.ino, O_RDONLY as u32).unwrap();
+        assert_eq!(driver.read(file.ino, fh, 0, 16), Err(EIO));
+        let errors = driver.resolve(&format!("{}/{}", STATUS_DIR, ERRORS_FILE)).unwrap();
+        let content = String::from_utf8(driver.read_all(errors.ino, 4096).unwrap()).unwrap();
+        let expected = format!(" read Input/output error (os error {}): Can't read ino {}:",
+                               EIO,
+                               file.ino);
+        assert!(content.lines().any(|line| line.contains(&expected)), "{}", content);
+    }
+
+    #[test]
+    fn stats_file() {
+        let mut driver = sample();
+        let traffic = SharedStats::default();
+        traffic.lock().unwrap().bytes_read.insert(PathBuf::from("vol1"), 42);
+        driver.fs.show_traffic(traffic);
+        let snapshot = snapshot_dir(&mut driver);
+        driver.resolve(&format!("{}/dir", snapshot)).unwrap();
+        let stats = driver.resolve(&format!("{}/{}", STATUS_DIR, STATS_FILE)).unwrap();
+        let content = String::from_utf8(driver.read_all(stats.ino, 4096).unwrap()).unwrap();
+        assert!(content.starts_with("heap: "), "{}", content);
+        assert!(content.contains(" bytes, snapshot trees: "), "{}", content);
+        assert!(content.contains("\nbackend: listings: 0, names listed: 0, files opened: 0, bytes \
+                                  read: 42\n  vol1: 42 bytes\n"),
+                "{}",
+                content);
+    }
+
+    #[test]
+    fn snapshot_failures_are_recorded() {
+        let mut backend = BackupBuilder::new()
+                              .full(1500000000, &[Entry::file("a", b"a")])
+                              .inc(1500003600, &[Entry::file("b", b"b")])
+                              .build();
+        backend.remove(Path::new("duplicity-new-signatures.20170714T024000Z.to.\
+                                  20170714T034000Z.sigtar"))
+               .unwrap();
+        let inc = time_to_path(Timespec::new(1500003600, 0));
+        let mut driver = driver(backend);
+        let snapshot = driver.lookup(1, &inc).unwrap();
+        assert_eq!(driver.lookup(snapshot.ino, "b").map(|attr| attr.ino), Err(EIO));
+        let errors = driver.resolve(&format!("{}/{}", STATUS_DIR, ERRORS_FILE)).unwrap();
+        let content = String::from_utf8(driver.read_all(errors.ino, 4096).unwrap()).unwrap();
+        let expected = format!(" lookup Input/output error (os error {}): Can't build the tree of \
+                                snapshot {}:",
+                               EIO,
+                               inc);
+        assert!(content.lines().any(|line| line.contains(&expected)), "{}", content);
+    }
+
+    #[test]
+    fn restore_cost() {
+        let times = [1500000000, 1500003600, 1500007200];
+        let backend = BackupBuilder::new()
+                          .full(times[0], &[Entry::file("a", b"a")])
+                          .inc(times[1], &[Entry::file("a", b"b")])
+                          .inc(times[2], &[Entry::file("a", b"c")])
+                          .build();
+        let mut driver = driver(backend);
+        let expected = ["{ \"deltas\": 0, \"volumes\": 1 }", "{ \"deltas\": 2, \"volumes\": 3 }"];
+        for (time, expected) in [times[0], times[2]].iter().zip(expected.iter()) {
+            let path = format!("{}/{}", time_to_path(Timespec::new(*time, 0)), INFO_FILE);
+            let info = driver.resolve(&path).unwrap();
+            let content = String::from_utf8(driver.read_all(info.ino, 4096).unwrap()).unwrap();
+            assert!(content.contains(&format!("\"restore\": {}", expected)), "{}", content);
+        }
+    }
+
+    #[test]
+    fn cached_attributes_follow_access_times() {
+        let backend = BackupBuilder::new().full(1500000000, &[Entry::file("a", b"a")]).build();
+        let mut driver = driver_with(backend, FsOptions { track_atime: true, ..options() });
+        let snapshot = snapshot_dir(&mut driver);
+        let file = driver.resolve(&format!("{}/a", snapshot)).unwrap();
+        assert_eq!(driver.getattr(file.ino).map(|attr| attr.atime), Ok(file.atime));
+        let (fh, _) = driver.open(file.ino, O_RDONLY as u32).unwrap();
+        driver.release(fh).unwrap();
+        let opened = driver.getattr(file.ino).unwrap();
+        assert!(opened.atime > file.atime);
+        assert_eq!(opened.mtime, file.mtime);
+    }
+
+    #[test]
+    fn layouts() {
+        let times = [1500000000, 1500003600, 1500007200];
+        let backend = BackupBuilder::new()
+                          .full(times[0], &[Entry::file("a", b"a")])
+                          .inc(times[1], &[Entry::file("a", b"a"), Entry::file("b", b"b")])
+                          .full(times[2], &[Entry::file("c", b"c")])
+                          .build();
+        let dirs = times.iter().map(|t| time_to_path(Timespec::new(*t, 0))).collect::<Vec<_>>();
+
+        let mut by_chain = driver_with_layout(backend.clone(), Layout::ByChain);
+        let root = names(&by_chain.list(1, 4096).unwrap());
+        assert_eq!(root, vec![".", "..", &dirs[0], &dirs[2], STATUS_DIR]);
+        let chain = by_chain.resolve(&dirs[0]).unwrap();
+        assert_eq!(names(&by_chain.list(chain.ino, 4096).unwrap())[2..], dirs[..2]);
+        assert!(by_chain.resolve(&format!("{}/{}/b", dirs[0], dirs[1])).is_ok());
+        assert!(by_chain.resolve(&format!("{}/{}/c", dirs[2], dirs[2])).is_ok());
+
+        // the latest snapshot takes the place of the root
+        let mut single = driver_with_layout(backend, Layout::SingleSnapshot);
+        let root = names(&single.list(1, 4096).unwrap());
+        assert_eq!(root, vec![".", "..", DUPLICITY_DIR, INFO_FILE, "c", STATUS_DIR]);
+        assert_eq!(single.resolve("c").map(|attr| attr.kind), Ok(FileType::RegularFile));
+        let errors = format!("{}/{}", STATUS_DIR, ERRORS_FILE);
+        assert_eq!(single.resolve(&errors).map(|attr| attr.kind), Ok(FileType::RegularFile));
+        assert_eq!(single.lookup(1, "a").map(|attr| attr.ino), Err(ENOENT));
+    }
+
+    #[test]
+    fn preload_in_parallel() {
+        let times = [1500000000, 1500003600, 1500007200];
+        let backend = BackupBuilder::new()
+                          .full(times[0], &[Entry::file("a", b"a")])
+                          .inc(times[1], &[Entry::file("a", b"a"), Entry::file("b", b"b")])
+                          .full(times[2], &[Entry::dir("c"), Entry::file("c/d", b"d")])
+                          .build();
+        let mut driver = driver(backend.clone());
+        driver.fs.preload(Preload::All, 2, || Ok(backend.clone())).unwrap();
+        assert!(driver.fs.trees.iter().all(Option::is_some));
+        assert_eq!(driver.fs.preloaded_sids.len(), times.len());
+
+        // the trees get inodes of their own, after the directories
+        let mut ranges = driver.fs.trees.iter().filter_map(|tree| {
+            tree.as_ref().and_then(|tree| tree.inodes())
+        }).collect::<Vec<_>>();
+        ranges.sort();
+        assert!(ranges[0].0 > driver.fs.dirs.last_ino());
+        assert!(ranges.windows(2).all(|pair| pair[0].1 < pair[1].0));
+        for (time, path) in times.iter().zip(&["a", "b", "c/d"]) {
+            let path = format!("{}/{}", time_to_path(Timespec::new(*time, 0)), path);
+            assert_eq!(driver.resolve(&path).map(|attr| attr.kind), Ok(FileType::RegularFile));
+        }
+    }
+
+    #[test]
+    fn best_effort() {
+        let mut backend = BackupBuilder::new()
+                              .full(1500000000, &[Entry::file("a", b"a")])
+                              .inc(1500003600, &[Entry::file("b", b"b")])
+                              .build();
+        backend.remove(Path::new("duplicity-new-signatures.20170714T024000Z.to.\
+                                  20170714T034000Z.sigtar"))
+               .unwrap();
+        let inc = time_to_path(Timespec::new(1500003600, 0));
+        let open = || Ok(backend.clone());
+        assert!(driver(backend.clone()).fs.preload(Preload::All, 2, open).is_err());
+
+        let mut driver = driver_with(backend.clone(),
+                                     FsOptions { best_effort: true, ..options() });
+        let status = driver.resolve(STATUS_DIR).unwrap();
+        assert_eq!(driver.lookup(status.ino, DEGRADED_FILE).map(|attr| attr.ino), Err(ENOENT));
+        driver.fs.preload(Preload::All, 2, open).unwrap();
+        assert!(driver.fs.is_degraded());
+        let root = names(&driver.list(1, 4096).unwrap());
+        assert_eq!(root.iter().filter(|name| name.starts_with("20")).count(), 1);
+        assert_eq!(driver.lookup(1, &inc).map(|attr| attr.ino), Err(ENOENT));
+        let degraded = driver.resolve(&format!("{}/{}", STATUS_DIR, DEGRADED_FILE)).unwrap();
+        assert_eq!(driver.read_all(degraded.ino, 4096).unwrap(),
+                   format!("{}\n", inc).into_bytes());
+    }
+
+    #[test]
+    fn volatile_holds_back_uploads() {
+        let mut backend = BackupBuilder::new()
+                              .full(1500000000, &[Entry::file("a", b"a")])
+                              .inc(1500003600, &[Entry::file("b", b"b")])
+                              .build();
+        backend.remove(Path::new("duplicity-inc.20170714T024000Z.to.20170714T034000Z.manifest"))
+               .unwrap();
+        let inc = time_to_path(Timespec::new(1500003600, 0));
+        assert!(driver(backend.clone()).lookup(1, &inc).is_ok());
+
+        let mut driver = driver_with(backend.clone(), FsOptions { volatile: true, ..options() });
+        driver.fs.preload(Preload::Latest, 2, || Ok(backend.clone())).unwrap();
+        let root = names(&driver.list(1, 4096).unwrap());
+        assert_eq!(root.iter().filter(|name| name.starts_with("20")).count(), 1);
+        assert_eq!(driver.lookup(1, &inc).map(|attr| attr.ino), Err(ENOENT));
+    }
+
+    fn entry_kind(entry: &Entry) -> FileType {
+        match *entry {
+            Entry::Dir(_) => FileType::Directory,
+            Entry::File(..) => FileType::RegularFile,
+            Entry::Symlink(..) => FileType::Symlink,
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn entries_reachable_from_root((snapshots, backend) in arb_chain(),
+                                       visits in prop::collection::vec(any::<Index>(), 1..8)) {
+            let mut driver = driver(backend);
+            let mut names = names(&driver.list(1, 4096).unwrap());
+            names.retain(|name| name.starts_with("20"));
+            prop_assert_eq!(names.len(), snapshots.len());
+
+            // the trees are built in the order the snapshots are visited
+            for visit in visits {
+                let sid = visit.index(snapshots.len());
+                for entry in &snapshots[sid] {
+                    let path = format!("{}/{}", names[sid], entry.path());
+                    let attr = driver.resolve(&path).unwrap();
+                    prop_assert_eq!(attr.kind, entry_kind(entry));
+                    prop_assert_eq!(driver.getattr(attr.ino).map(|a| a.kind), Ok(attr.kind));
+                }
+            }
+
+            // no inode is given to two entries, or to an entry and the directories before them
+            let mut ranges = driver.fs.trees.iter().filter_map(|tree| {
+                tree.as_ref().and_then(|tree| tree.inodes())
+            }).collect::<Vec<_>>();
+            ranges.sort();
+            let mut last = driver.fs.dirs.last_ino();
+            for (first, end) in ranges {
+                prop_assert!(first > last, "{} overlaps the inodes up to {}", first, last);
+                last = end;
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt::Write;
+
+use ruplicity::Snapshot;
+use ruplicity::collections::Collections;
+use time::Timespec;
+
+use json;
+use time_utils;
+
+
+/// Name of the file describing a snapshot.
+pub const INFO_FILE: &'static str = ".info.json";
+
+
+/// Position of a snapshot inside its backup chain.
+struct ChainPosition {
+    /// Index of the chain in the backup, starting from zero.
+    chain: usize,
+    /// Index of the snapshot inside the chain; zero is the full snapshot.
+    position: usize,
+    /// Time of the full snapshot starting the chain.
+    full_time: Timespec,
+    /// Number of snapshots in the chain.
+    len: usize,
+    /// Number of volumes read to restore the snapshot, from the full one up to it.
+    volumes: usize,
+}
+
+
+/// Returns a JSON document describing a snapshot.
+pub fn snapshot_info(snapshot: &Snapshot,
+                     collections: &Collections,
+                     sid: usize,
+                     num_entries: usize)
+                     -> String {
+    let set = snapshot.as_backup_set();
+    let volumes = (1..set.num_volumes() as i32 + 1)
+                      .filter_map(|num| set.volume_path(num))
+                      .map(json::string)
+                      .collect::<Vec<_>>();
+
+    let mut result = String::new();
+    result.push_str("{\n");
+    let _ = write!(result, "  \"time\": {},\n", json::string(&format_time(snapshot.time())));
+    let _ = write!(result, "  \"timestamp\": {},\n", snapshot.time().sec);
+    let _ = write!(result,
+                   "  \"type\": {},\n",
+                   json::string(if snapshot.is_full() { "full" } else { "incremental" }));
+    if let Some(pos) = chain_position(collections, sid) {
+        let _ = write!(result,
+                       "  \"chain\": {{ \"index\": {}, \"position\": {}, \"length\": {}, \
+                        \"full_time\": {} }},\n",
+                       pos.chain,
+                       pos.position,
+                       pos.len,
+                       json::string(&format_time(pos.full_time)));
+        // every incremental snapshot after the full one is a delta to apply
+        let _ = write!(result,
+                       "  \"restore\": {{ \"deltas\": {}, \"volumes\": {} }},\n",
+                       pos.position,
+                       pos.volumes);
+    }
+    let _ = write!(result, "  \"manifest\": {},\n", json::string(set.manifest_path()));
+    let _ = write!(result, "  \"volumes\": [{}],\n", volumes.join(", "));
+    let _ = write!(result, "  \"entries\": {}\n", num_entries);
+    result.push_str("}\n");
+    result
+}
+
+
+/// Finds the chain containing the snapshot with the given id.
+fn chain_position(collections: &Collections, sid: usize) -> Option<ChainPosition> {
+    let mut first_sid = 0;
+    for (index, chain) in collections.backup_chains().enumerate() {
+        let len = chain.inc_sets().len() + 1;
+        if sid < first_sid + len {
+            let position = sid - first_sid;
+            let deltas = chain.inc_sets().take(position).map(|set| set.num_volumes());
+            let volumes = chain.full_set().num_volumes() + deltas.sum::<usize>();
+            return Some(ChainPosition {
+                chain: index,
+                position: position,
+                full_time: chain.full_set().end_time(),
+                len: len,
+                volumes: volumes,
+            });
+        }
+        first_sid += len;
+    }
+    None
+}
+
+fn format_time(ts: Timespec) -> String {
+    time_utils::utc(ts).format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
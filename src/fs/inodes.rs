@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+
+/// The inode allocation of a filesystem, shared with whoever reports it.
+pub type SharedInodeMap = Arc<Mutex<InodeMap>>;
+
+/// Hands out inodes in increasing order, never reusing them.
+///
+/// Every part of the filesystem takes its inodes from here when it is created, so the ones added
+/// later, like the trees of snapshots browsed for the first time, don't renumber the others.
+#[derive(Debug)]
+pub struct InodeAllocator {
+    next: u64,
+}
+
+/// The blocks of inodes reserved for each snapshot, all of the same size.
+///
+/// Blocks are allocated in snapshot order, so they are sorted by inode as well.
+#[derive(Debug)]
+pub struct SnapshotBlocks {
+    size: u64,
+    firsts: Vec<u64>,
+}
+
+/// Describes how the inodes are allocated, to debug inode mapping problems.
+#[derive(Debug, Default)]
+pub struct InodeMap {
+    /// The directory name and the inode of each snapshot, by snapshot id.
+    pub snapshots: Vec<(String, u64)>,
+    /// The first and last inodes reserved for the special entries of each snapshot, by snapshot
+    /// id.
+    pub special: Vec<(u64, u64)>,
+    /// The first and last inodes reserved for the status directory and its files.
+    pub status: (u64, u64),
+    /// The first and last inodes reserved for the directories and aliases above the snapshots.
+    pub dirs: (u64, u64),
+    /// The first and last inodes of each loaded snapshot tree, by snapshot id.
+    pub trees: BTreeMap<usize, Option<(u64, u64)>>,
+}
+
+
+impl InodeAllocator {
+    /// Starts allocating after the root directory, which is always inode 1.
+    pub fn new() -> Self {
+        InodeAllocator { next: 2 }
+    }
+
+    /// Returns the inode the next allocation starts from.
+    pub fn next_ino(&self) -> u64 {
+        self.next
+    }
+
+    /// Reserves a block of inodes, returning the first one.
+    pub fn allocate(&mut self, count: u64) -> u64 {
+        let first = self.next;
+        self.next += count;
+        first
+    }
+
+    /// Reserves the inodes from the next one up to `last`, already numbered by the caller.
+    pub fn claim(&mut self, last: u64) {
+        assert!(last + 1 >= self.next, "inode {} was already allocated", last);
+        self.next = last + 1;
+    }
+}
+
+
+impl SnapshotBlocks {
+    pub fn new(size: u64) -> Self {
+        SnapshotBlocks {
+            size: size,
+            firsts: Vec::new(),
+        }
+    }
+
+    /// Reserves the block of the next snapshot.
+    pub fn push(&mut self, allocator: &mut InodeAllocator) {
+        self.firsts.push(allocator.allocate(self.size));
+    }
+
+    /// Returns the first and the last inodes of the block of a snapshot.
+    pub fn range(&self, sid: usize) -> (u64, u64) {
+        let first = self.firsts[sid];
+        (first, first + self.size - 1)
+    }
+
+    /// Returns the snapshot id and the offset in its block of an inode.
+    pub fn find(&self, ino: u64) -> Option<(usize, u64)> {
+        let sid = match self.firsts.binary_search(&ino) {
+            Ok(sid) => sid,
+            Err(0) => {
+                return None;
+            }
+            Err(next) => next - 1,
+        };
+        let offset = ino - self.firsts[sid];
+        if offset < self.size {
+            Some((sid, offset))
+        } else {
+            None
+        }
+    }
+}
+
+
+impl Display for InodeMap {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        try!(writeln!(f, "snapshots: {}", self.snapshots.len()));
+        for (sid, &(ref name, ino)) in self.snapshots.iter().enumerate() {
+            let special = Range(self.special.get(sid).cloned());
+            try!(writeln!(f, "  #{} {}: ino {}, special {}", sid, name, ino, special));
+        }
+        try!(writeln!(f, "status entries: {}", Range(Some(self.status))));
+        try!(writeln!(f, "root directories: {}", Range(Some(self.dirs))));
+        try!(writeln!(f, "loaded trees: {}", self.trees.len()));
+        for (sid, inodes) in &self.trees {
+            let name = self.snapshots.get(*sid).map_or("?", |s| s.0.as_str());
+            try!(writeln!(f, "  #{} {}: {}", sid, name, Range(*inodes)));
+        }
+        Ok(())
+    }
+}
+
+
+// an inclusive range of inodes, possibly empty
+struct Range(Option<(u64, u64)>);
+
+impl Display for Range {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0 {
+            Some((first, last)) if first <= last => {
+                write!(f, "inodes {}-{} ({} nodes)", first, last, last - first + 1)
+            }
+            _ => write!(f, "no inodes"),
+        }
+    }
+}
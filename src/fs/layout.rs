@@ -0,0 +1,451 @@
+use std::io;
+use std::str::FromStr;
+
+use ruplicity::{Backend, Backup};
+use time::Timespec;
+
+use super::time_to_path;
+use time_utils;
+
+
+/// How the snapshots are arranged below the mount root.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Layout {
+    /// Every snapshot is a directory of the root, named after its time.
+    Flat,
+    /// Snapshots are grouped by date, as `YYYY/MM/DD/HH-MM-SS`.
+    ByDate,
+    /// Snapshots are grouped by chain, in a directory named after the full snapshot starting it.
+    ByChain,
+    /// Like `Flat`, with the addition of symbolic links addressing the snapshots by position.
+    ///
+    /// Counting from the most recent, `full.N` points to the N-th full snapshot, `inc.N` to the
+    /// N-th incremental snapshot and `-N` to the N-th snapshot of any kind.
+    Indexed,
+    /// Only the most recent snapshot is shown, in place of the root directory.
+    SingleSnapshot,
+}
+
+/// Decides where the snapshots appear below the mount root.
+///
+/// Every `Layout` is implemented by one of these, so that a new layout doesn't need changes to
+/// the directories or to the filesystem operations.
+pub trait Arrangement {
+    /// Returns the path of a snapshot relative to the mount root, given all the snapshots.
+    ///
+    /// The snapshot is left out if `None`, and shown in place of the root if empty.
+    fn snapshot_path(&self, sid: usize, snapshots: &[SnapshotInfo]) -> Option<Vec<String>>;
+
+    /// Returns the symbolic links to add to the root directory, with the snapshot each points to.
+    fn aliases(&self, _snapshots: &[SnapshotInfo]) -> Vec<(String, usize)> {
+        Vec::new()
+    }
+}
+
+/// What an arrangement knows about a snapshot.
+#[derive(Copy, Clone, Debug)]
+pub struct SnapshotInfo {
+    pub time: Timespec,
+    pub is_full: bool,
+    /// The time of the full snapshot starting the chain.
+    pub chain_time: Timespec,
+}
+
+/// The directories leading from the mount root to the snapshots.
+///
+/// The root has inode 1, the other directories get a contiguous block of inodes, followed by
+/// the aliases.
+#[derive(Debug)]
+pub struct RootDirs {
+    first_ino: u64,
+    // the root directory comes first
+    dirs: Vec<RootDir>,
+    aliases: Vec<String>,
+    snapshot_parents: Vec<u64>,
+    snapshot_paths: Vec<Option<String>>,
+    root_snapshot: Option<usize>,
+}
+
+/// A directory containing snapshots or other directories.
+#[derive(Debug)]
+pub struct RootDir {
+    /// The inode of the parent directory.
+    pub parent: u64,
+    /// The name and the content of each child, in readdir order.
+    pub children: Vec<(String, RootChild)>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RootChild {
+    /// A directory, with its inode.
+    Dir(u64),
+    /// A snapshot, with its id.
+    Snapshot(usize),
+    /// A symbolic link to a snapshot, with its inode.
+    Alias(u64),
+}
+
+
+/// Adds directories between each snapshot of another arrangement and its parent.
+///
+/// The snapshot takes the place of the innermost directory, so that its entries are shown below
+/// the path they were backed up from.
+pub struct Prefixed {
+    inner: Box<dyn Arrangement>,
+    prefix: Vec<String>,
+}
+
+struct Flat;
+struct ByDate;
+struct ByChain;
+struct Indexed;
+struct SingleSnapshot;
+
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(Layout::Flat),
+            "by-date" => Ok(Layout::ByDate),
+            "by-chain" => Ok(Layout::ByChain),
+            "indexed" => Ok(Layout::Indexed),
+            "single-snapshot" => Ok(Layout::SingleSnapshot),
+            _ => {
+                Err(format!("unknown layout '{}', expected 'flat', 'by-date', 'by-chain', \
+                             'indexed' or 'single-snapshot'",
+                            s))
+            }
+        }
+    }
+}
+
+impl Layout {
+    /// Returns the arrangement implementing the layout.
+    pub fn arrangement(&self) -> Box<dyn Arrangement> {
+        match *self {
+            Layout::Flat => Box::new(Flat),
+            Layout::ByDate => Box::new(ByDate),
+            Layout::ByChain => Box::new(ByChain),
+            Layout::Indexed => Box::new(Indexed),
+            Layout::SingleSnapshot => Box::new(SingleSnapshot),
+        }
+    }
+}
+
+
+impl Arrangement for Flat {
+    fn snapshot_path(&self, sid: usize, snapshots: &[SnapshotInfo]) -> Option<Vec<String>> {
+        Some(vec![time_to_path(snapshots[sid].time)])
+    }
+}
+
+impl Arrangement for ByDate {
+    fn snapshot_path(&self, sid: usize, snapshots: &[SnapshotInfo]) -> Option<Vec<String>> {
+        let time = snapshots[sid].time;
+        let local = time_utils::local(time);
+        let mut result = ["%Y", "%m", "%d"]
+                             .iter()
+                             .map(|format| local.format(format).to_string())
+                             .collect::<Vec<_>>();
+        result.push(time_utils::format_local_unambiguous(time, "%H-%M-%S"));
+        Some(result)
+    }
+}
+
+impl Arrangement for ByChain {
+    fn snapshot_path(&self, sid: usize, snapshots: &[SnapshotInfo]) -> Option<Vec<String>> {
+        let snapshot = &snapshots[sid];
+        Some(vec![time_to_path(snapshot.chain_time), time_to_path(snapshot.time)])
+    }
+}
+
+impl Arrangement for Indexed {
+    fn snapshot_path(&self, sid: usize, snapshots: &[SnapshotInfo]) -> Option<Vec<String>> {
+        Flat.snapshot_path(sid, snapshots)
+    }
+
+    fn aliases(&self, snapshots: &[SnapshotInfo]) -> Vec<(String, usize)> {
+        // aliases are numbered starting from the most recent snapshot
+        let mut result = Vec::new();
+        let (mut fulls, mut incs) = (0, 0);
+        for (index, (sid, snapshot)) in snapshots.iter().enumerate().rev().enumerate() {
+            let name = if snapshot.is_full {
+                fulls += 1;
+                format!("full.{}", fulls - 1)
+            } else {
+                incs += 1;
+                format!("inc.{}", incs - 1)
+            };
+            result.push((name, sid));
+            result.push((format!("-{}", index), sid));
+        }
+        result
+    }
+}
+
+impl Arrangement for SingleSnapshot {
+    fn snapshot_path(&self, sid: usize, snapshots: &[SnapshotInfo]) -> Option<Vec<String>> {
+        if sid + 1 == snapshots.len() {
+            Some(Vec::new())
+        } else {
+            None
+        }
+    }
+}
+
+impl Prefixed {
+    pub fn new(inner: Box<dyn Arrangement>, prefix: Vec<String>) -> Self {
+        Prefixed {
+            inner: inner,
+            prefix: prefix,
+        }
+    }
+}
+
+impl Arrangement for Prefixed {
+    fn snapshot_path(&self, sid: usize, snapshots: &[SnapshotInfo]) -> Option<Vec<String>> {
+        self.inner.snapshot_path(sid, snapshots).map(|mut path| {
+            path.extend(self.prefix.iter().cloned());
+            path
+        })
+    }
+
+    fn aliases(&self, snapshots: &[SnapshotInfo]) -> Vec<(String, usize)> {
+        self.inner.aliases(snapshots)
+    }
+}
+
+
+impl RootDirs {
+    /// Builds the directories leading to the snapshots of a backup.
+    pub fn new<B: Backend>(arrangement: &dyn Arrangement,
+                           backup: &Backup<B>,
+                           first_ino: u64)
+                           -> io::Result<Self> {
+        let mut result = RootDirs {
+            first_ino: first_ino,
+            dirs: vec![RootDir {
+                           parent: 1,
+                           children: Vec::new(),
+                       }],
+            aliases: Vec::new(),
+            snapshot_parents: Vec::new(),
+            snapshot_paths: Vec::new(),
+            root_snapshot: None,
+        };
+        let mut snapshots: Vec<SnapshotInfo> = Vec::new();
+        for snapshot in try!(backup.snapshots()) {
+            let chain_time = match snapshots.last() {
+                Some(last) if !snapshot.is_full() => last.chain_time,
+                _ => snapshot.time(),
+            };
+            snapshots.push(SnapshotInfo {
+                time: snapshot.time(),
+                is_full: snapshot.is_full(),
+                chain_time: chain_time,
+            });
+        }
+        for sid in 0..snapshots.len() {
+            let path = arrangement.snapshot_path(sid, &snapshots);
+            let mut parent = 1;
+            match path.as_ref().and_then(|path| path.split_last()) {
+                Some((name, dirs)) => {
+                    for dir in dirs {
+                        parent = result.find_or_add_dir(parent, dir.clone());
+                    }
+                    result.dir_mut(parent).children.push((name.clone(), RootChild::Snapshot(sid)));
+                }
+                None if path.is_some() => {
+                    result.root_snapshot = Some(sid);
+                }
+                None => {}
+            }
+            result.snapshot_parents.push(parent);
+            result.snapshot_paths.push(path.map(|path| path.join("/")));
+        }
+        for (name, sid) in arrangement.aliases(&snapshots) {
+            // snapshots out of the layout, or in place of the root, can't be pointed to
+            if let Some(path) = result.snapshot_paths[sid].clone() {
+                if !path.is_empty() {
+                    result.add_alias(name, path);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the last inode reserved for directories and aliases.
+    pub fn last_ino(&self) -> u64 {
+        self.first_alias_ino() + self.aliases.len() as u64 - 1
+    }
+
+    /// Returns whether an inode is one of these directories.
+    pub fn is_dir(&self, ino: u64) -> bool {
+        ino == 1 || (ino >= self.first_ino && ino < self.first_alias_ino())
+    }
+
+    /// Returns whether an inode is an alias.
+    pub fn is_alias(&self, ino: u64) -> bool {
+        ino >= self.first_alias_ino() && ino <= self.last_ino()
+    }
+
+    /// Returns the target of an alias, relative to the directory containing it.
+    pub fn alias_target(&self, ino: u64) -> Option<&str> {
+        if !self.is_alias(ino) {
+            return None;
+        }
+        self.aliases.get((ino - self.first_alias_ino()) as usize).map(|t| t.as_str())
+    }
+
+    pub fn dir(&self, ino: u64) -> Option<&RootDir> {
+        if !self.is_dir(ino) {
+            return None;
+        }
+        self.dirs.get(self.index_from_ino(ino))
+    }
+
+    /// Returns the inode of the directory containing a snapshot.
+    pub fn snapshot_parent(&self, sid: usize) -> u64 {
+        self.snapshot_parents[sid]
+    }
+
+    /// Returns the path of a snapshot relative to the mount root, empty if the snapshot is in place
+    /// of the root, or `None` if it is left out of the layout.
+    pub fn snapshot_path(&self, sid: usize) -> Option<&str> {
+        self.snapshot_paths[sid].as_ref().map(|path| path.as_str())
+    }
+
+    /// Returns the snapshot shown in place of the root directory, if any.
+    pub fn root_snapshot(&self) -> Option<usize> {
+        self.root_snapshot
+    }
+
+    fn find_or_add_dir(&mut self, parent: u64, name: String) -> u64 {
+        let existing = self.dir_mut(parent)
+                           .children
+                           .iter()
+                           .find(|child| child.0 == name)
+                           .map(|child| child.1);
+        if let Some(RootChild::Dir(ino)) = existing {
+            return ino;
+        }
+        let ino = self.ino_from_index(self.dirs.len());
+        self.dirs.push(RootDir {
+            parent: parent,
+            children: Vec::new(),
+        });
+        self.dir_mut(parent).children.push((name, RootChild::Dir(ino)));
+        ino
+    }
+
+    /// Adds an alias in the root directory.
+    fn add_alias(&mut self, name: String, target: String) {
+        let ino = self.first_alias_ino() + self.aliases.len() as u64;
+        self.aliases.push(target);
+        self.dirs[0].children.push((name, RootChild::Alias(ino)));
+    }
+
+    fn first_alias_ino(&self) -> u64 {
+        self.first_ino + self.dirs.len() as u64 - 1
+    }
+
+    fn dir_mut(&mut self, ino: u64) -> &mut RootDir {
+        let index = self.index_from_ino(ino);
+        &mut self.dirs[index]
+    }
+
+    fn index_from_ino(&self, ino: u64) -> usize {
+        if ino == 1 {
+            0
+        } else {
+            (ino - self.first_ino) as usize + 1
+        }
+    }
+
+    fn ino_from_index(&self, index: usize) -> u64 {
+        if index == 0 {
+            1
+        } else {
+            self.first_ino + index as u64 - 1
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // one snapshot per hour, full or incremental as given
+    fn snapshots(fulls: &[bool]) -> Vec<SnapshotInfo> {
+        let mut result: Vec<SnapshotInfo> = Vec::new();
+        for (index, &is_full) in fulls.iter().enumerate() {
+            let time = Timespec::new(1500000000 + 3600 * index as i64, 0);
+            let chain_time = match result.last() {
+                Some(last) if !is_full => last.chain_time,
+                _ => time,
+            };
+            result.push(SnapshotInfo {
+                time: time,
+                is_full: is_full,
+                chain_time: chain_time,
+            });
+        }
+        result
+    }
+
+    fn aliases(names: &[(&str, usize)]) -> Vec<(String, usize)> {
+        names.iter().map(|&(name, sid)| (name.to_owned(), sid)).collect()
+    }
+
+    #[test]
+    fn indexed_aliases() {
+        let snapshots = snapshots(&[true, false, false, true, false]);
+        let expected = aliases(&[("inc.0", 4),
+                                 ("-0", 4),
+                                 ("full.0", 3),
+                                 ("-1", 3),
+                                 ("inc.1", 2),
+                                 ("-2", 2),
+                                 ("inc.2", 1),
+                                 ("-3", 1),
+                                 ("full.1", 0),
+                                 ("-4", 0)]);
+        assert_eq!(Indexed.aliases(&snapshots), expected);
+        for sid in 0..snapshots.len() {
+            assert_eq!(Indexed.snapshot_path(sid, &snapshots),
+                       Some(vec![time_to_path(snapshots[sid].time)]));
+        }
+    }
+
+    #[test]
+    fn indexed_aliases_single_full() {
+        let snapshots = snapshots(&[true]);
+        assert_eq!(Indexed.aliases(&snapshots), aliases(&[("full.0", 0), ("-0", 0)]));
+        assert_eq!(Indexed.aliases(&[]), Vec::new());
+    }
+
+    #[test]
+    fn prefixed_single_snapshot() {
+        let snapshots = snapshots(&[true, false, false]);
+        let prefix = vec!["home".to_owned(), "user".to_owned()];
+        let arrangement = Prefixed::new(Layout::SingleSnapshot.arrangement(), prefix.clone());
+        // only the latest snapshot is shown, below the prefix instead of in place of the root
+        assert_eq!(arrangement.snapshot_path(0, &snapshots), None);
+        assert_eq!(arrangement.snapshot_path(1, &snapshots), None);
+        assert_eq!(arrangement.snapshot_path(2, &snapshots), Some(prefix));
+        assert_eq!(arrangement.aliases(&snapshots), Vec::new());
+    }
+
+    #[test]
+    fn prefixed_keeps_inner_aliases() {
+        let snapshots = snapshots(&[true, false]);
+        let prefix = vec!["data".to_owned()];
+        let arrangement = Prefixed::new(Layout::Indexed.arrangement(), prefix);
+        assert_eq!(arrangement.snapshot_path(1, &snapshots),
+                   Some(vec![time_to_path(snapshots[1].time), "data".to_owned()]));
+        assert_eq!(arrangement.aliases(&snapshots), Indexed.aliases(&snapshots));
+    }
+}
@@ -1,17 +1,63 @@
+#[cfg(test)]
+pub mod headless;
+mod info;
+mod inodes;
+mod layout;
+mod reply;
+mod special;
+mod status;
 mod tree;
 
-use fuse::{FileAttr, FileType, Filesystem, ReplyAttr, ReplyDirectory, ReplyData, ReplyEntry, Request};
-use libc::{ENOENT, ENOSYS};
+pub use self::inodes::SharedInodeMap;
+pub use self::layout::Layout;
+pub use self::tree::SnapshotTree;
+
+use fuse::{FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyDirectory, ReplyData,
+           ReplyEmpty, ReplyEntry, ReplyLock, ReplyOpen, ReplyWrite, Request};
+use libc::{EACCES, EBADF, EINTR, EINVAL, EIO, ENOENT, ENOSYS, EOPNOTSUPP, EROFS, ETIMEDOUT,
+           F_RDLCK, F_UNLCK, O_ACCMODE, O_RDONLY, O_TRUNC, c_int};
+use chrono::{DateTime, NaiveDateTime};
 use time::{self, Timespec};
 use ruplicity::{Backend, Backup, Snapshot};
-use ruplicity::signatures::{Entry as SigEntry, EntryType};
+use ruplicity::signatures::{Entry as SigEntry, EntryType, SnapshotEntries};
 
-use std::collections::HashMap;
-use std::io;
-use std::path::Path;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::{self, Read};
+use std::iter;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-use self::tree::SnapshotTree;
+use self::info::INFO_FILE;
+use self::inodes::{InodeAllocator, InodeMap};
+use self::layout::{Prefixed, RootChild, RootDirs};
+use self::reply::{AttrReply, DataReply, DirectoryReply, EmptyReply, EntryReply, OpenReply};
+use self::special::{DUPLICITY_DIR, SpecialInos, SpecialKind, SpecialNode};
+use self::status::{DEGRADED_FILE, ERRORS_FILE, LATENCY_FILE, SCRUB_REPORT_FILE, STATS_FILE,
+                   STATUS_DIR, StatusInos};
+use self::tree::NodeEntry;
+use error::{self, Error};
+use heap::{self, Usage};
+use latency::SharedLatencies;
+use logger;
 use path_utils::path2bytes;
+use scrub::SharedScrubReport;
+use stats::SharedStats;
+use time_utils;
+use trace::Span;
+use verify;
+
+// special entries present in every snapshot directory, in readdir order
+const SNAPSHOT_SPECIALS: [(SpecialKind, &'static str); 2] = [(SpecialKind::DuplicityDir,
+                                                              DUPLICITY_DIR),
+                                                             (SpecialKind::Info, INFO_FILE)];
+
+// the format of the snapshot directory names
+const PATH_FORMAT: &'static str = "%Y-%m-%d_%H-%M-%S";
 
 // 1 hour time-to-live
 const TTL: Timespec = Timespec {
@@ -19,99 +65,598 @@ const TTL: Timespec = Timespec {
     nsec: 0,
 };
 
+// the flag to open files bypassing the page cache, for contents that change under the same size
+const FOPEN_DIRECT_IO: u32 = 1;
+
+// how long the list of backend files is trusted in volatile mode, in seconds
+const VOLATILE_LISTING_AGE: i64 = 1;
+
+// how many attributes of snapshot entries are cached
+const ATTR_CACHE_SIZE: usize = 4096;
+
+
+/// Options controlling how the backup is presented.
+#[derive(Debug)]
+pub struct FsOptions {
+    /// Owner of the files in the mount.
+    pub ownership: Ownership,
+    /// How the snapshots are arranged in the root directory.
+    pub layout: Layout,
+    /// The backend may change while mounted: don't let the kernel cache entries, check that
+    /// snapshots still exist before using them and hold back the ones still being uploaded.
+    pub volatile: bool,
+    /// Report the time files and directories were last opened while mounted, instead of their
+    /// modification time.
+    pub track_atime: bool,
+    /// How the entries of unknown type are presented.
+    pub unknown_entries: UnknownEntries,
+    /// How the absolute targets of symbolic links are rewritten.
+    pub rewrite_symlinks: SymlinkRewrite,
+    /// The absolute path of the mount, for the symbolic links rewritten with a prefix.
+    pub mountpoint: PathBuf,
+    /// The path components removed from the entries, whose tree is shown starting from them.
+    pub strip_prefix: Vec<String>,
+    /// The directories added between each snapshot and its entries, usually the path the backup
+    /// was made from.
+    pub add_prefix: Vec<String>,
+    /// Report errors instead of made up values, and the exact error numbers.
+    pub strict: bool,
+    /// Verify every snapshot tree after building it, refusing to serve the inconsistent ones.
+    pub check_tree: bool,
+    /// Leave out the snapshots that can't be loaded instead of failing, listing them in the
+    /// `DEGRADED` file of the status directory.
+    pub best_effort: bool,
+}
+
+/// Determines the owner reported for files and directories.
+#[derive(Copy, Clone, Debug)]
+pub enum Ownership {
+    /// Use the user and group ids recorded in the backup.
+    Preserve,
+    /// Report every entry as owned by the given user and group ids.
+    Fixed {
+        uid: u32,
+        gid: u32,
+    },
+}
+
+/// Which snapshots to load when mounting, instead of when they are first browsed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Preload {
+    /// All the snapshots.
+    All,
+    /// Only the most recent snapshot.
+    Latest,
+}
+
+/// Determines what happens to the entries having a type ruplicity doesn't know.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UnknownEntries {
+    /// Leave them out of the directory listings.
+    Hide,
+    /// Show them as empty regular files.
+    Show,
+    /// Show them as empty regular files that can't be opened.
+    Fail,
+}
+
+/// Determines how the absolute targets of symbolic links are rewritten, since they point to the
+/// live system instead of the backup.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SymlinkRewrite {
+    /// Report them as recorded.
+    None,
+    /// Make them relative to the link, so that they point inside the same snapshot.
+    Relative,
+    /// Prepend the path of the snapshot in the mount.
+    Prefix,
+}
 
 pub struct RuplicityFs<B> {
     backup: Backup<B>,
+    // the backups opened to preload the signatures in parallel, and the one holding the
+    // signatures of each preloaded snapshot
+    preloaded: Vec<Backup<B>>,
+    preloaded_sids: HashMap<usize, usize>,
+    options: FsOptions,
+    backend: B,
     snapshots: SnapshotsInos,
+    special: SpecialInos,
+    status: StatusInos,
+    dirs: RootDirs,
     trees: Vec<Option<SnapshotTree>>,
-    last_ino: u64,
+    sizes: Vec<Option<u64>>,
+    special_sizes: HashMap<u64, u64>,
+    handles: HashMap<u64, Vec<u8>>,
+    listings: HashMap<u64, Vec<DirEntry>>,
+    // the time each inode was last opened, used when tracking access times
+    access_times: HashMap<u64, Timespec>,
+    // the attributes of the snapshot entries last reported
+    attrs: HashMap<u64, FileAttr>,
+    ttl: Timespec,
+    // the backend files, with the time they were listed, used in volatile mode
+    live_files: Option<(Timespec, HashSet<PathBuf>)>,
+    // the snapshots left out in best-effort mode
+    skipped: BTreeSet<usize>,
+    // the snapshots held back in volatile mode, because they are still being uploaded
+    pending: BTreeSet<usize>,
+    // the snapshots whose tree failed the check, refused without building it again
+    inconsistent: BTreeSet<usize>,
+    // the results of the volumes checked in the background, if scrubbing
+    scrub_report: Option<SharedScrubReport>,
+    // the data read from the backend, if counted
+    traffic: Option<SharedStats>,
+    latencies: SharedLatencies,
+    inode_map: SharedInodeMap,
+    last_fh: u64,
+    inodes: InodeAllocator,
+    // called when the filesystem is dropped, that is when the session ends
+    on_unmount: Option<Box<dyn FnMut() + Send>>,
 }
 
+/// An entry of a directory listing.
+struct DirEntry {
+    ino: u64,
+    kind: FileType,
+    name: PathBuf,
+}
+
+/// The inodes of the snapshot directories, mapped in both directions.
+///
+/// Snapshot inodes are not contiguous, since each one is followed by the special entries of the
+/// snapshot, so they are looked up instead of computed.
 struct SnapshotsInos {
-    paths: HashMap<String, usize>,
+    inos: Vec<u64>,
+    sids: HashMap<u64, usize>,
 }
 
+/// The error of a snapshot tree failing the check, told apart from the ones loading it.
+#[derive(Debug)]
+struct InconsistentTree;
+
 
 impl<B: Backend> RuplicityFs<B> {
     /// Creates a new Filesystem instance for a duplicity backup.
-    pub fn new(backup: Backup<B>) -> io::Result<Self> {
-        let spaths = try!(SnapshotsInos::new(&backup));
-        let last_ino = spaths.last_ino();
+    ///
+    /// The given backend must point to the same backup files, and is used to access them
+    /// directly.
+    pub fn new(backup: Backup<B>, backend: B, options: FsOptions) -> io::Result<Self> {
+        // every snapshot gets its inodes as it is added, in a block following the ones already
+        // given out, so that snapshots appearing later don't renumber the others
+        let mut inodes = InodeAllocator::new();
+        let status = StatusInos::new(&mut inodes);
+        let names = try!(backup.snapshots()).map(|s| time_to_path(s.time())).collect::<Vec<_>>();
+        let mut spaths = SnapshotsInos::new();
+        let mut special = SpecialInos::new();
+        for _ in &names {
+            spaths.push(&mut inodes);
+            special.push(&mut inodes);
+        }
+        let first_dir_ino = inodes.next_ino();
+        let mut arrangement = options.layout.arrangement();
+        if !options.add_prefix.is_empty() {
+            arrangement = Box::new(Prefixed::new(arrangement, options.add_prefix.clone()));
+        }
+        let dirs = try!(RootDirs::new(&*arrangement, &backup, first_dir_ino));
+        inodes.claim(dirs.last_ino());
+        let inode_map = InodeMap {
+            snapshots: names.into_iter()
+                            .enumerate()
+                            .map(|(sid, name)| (name, spaths.ino_from_sid(sid)))
+                            .collect(),
+            special: (0..spaths.len()).map(|sid| special.range(sid)).collect(),
+            status: (status.dir_ino(), status.last_ino()),
+            dirs: (first_dir_ino, dirs.last_ino()),
+            ..InodeMap::default()
+        };
+        // changing access times are visible only if the kernel doesn't cache the attributes
+        let ttl = if options.volatile || options.track_atime {
+            Timespec::new(0, 0)
+        } else {
+            TTL
+        };
+        let mut pending = BTreeSet::new();
+        if options.volatile {
+            for (sid, snapshot) in try!(backup.snapshots()).enumerate() {
+                let problems = verify::upload_problems(&snapshot, &backend);
+                if !problems.is_empty() {
+                    info!("Holding back snapshot {}, not completely uploaded: {}",
+                          time_to_path(snapshot.time()),
+                          problems.join(", "));
+                    pending.insert(sid);
+                }
+            }
+        }
         let trees = (0..spaths.len()).map(|_| None).collect();
+        let sizes = (0..spaths.len()).map(|_| None).collect();
 
         Ok(RuplicityFs {
             backup: backup,
+            preloaded: Vec::new(),
+            preloaded_sids: HashMap::new(),
+            options: options,
+            backend: backend,
             snapshots: spaths,
-            last_ino: last_ino,
+            special: special,
+            status: status,
+            dirs: dirs,
+            inodes: inodes,
             trees: trees,
+            sizes: sizes,
+            special_sizes: HashMap::new(),
+            handles: HashMap::new(),
+            listings: HashMap::new(),
+            access_times: HashMap::new(),
+            attrs: HashMap::new(),
+            ttl: ttl,
+            live_files: None,
+            skipped: BTreeSet::new(),
+            inconsistent: BTreeSet::new(),
+            pending: pending,
+            inode_map: Arc::new(Mutex::new(inode_map)),
+            last_fh: 0,
+            scrub_report: None,
+            traffic: None,
+            latencies: SharedLatencies::default(),
+            on_unmount: None,
         })
     }
 
-    /// getattr for the root directory.
-    fn getattr_root(&mut self, reply: ReplyAttr) {
-        let ts = time::get_time();
-        let attr = FileAttr {
-            ino: 1,
-            size: 0,
-            blocks: 0,
-            atime: ts,
-            mtime: ts,
-            ctime: ts,
-            crtime: ts,
-            kind: FileType::Directory,
-            perm: 0o555,
-            nlink: 0,
-            uid: 0,
-            gid: 0,
-            rdev: 0,
-            flags: 0,
+    /// Returns the description of the allocated inodes, updated as snapshot trees are loaded.
+    pub fn inode_map(&self) -> SharedInodeMap {
+        self.inode_map.clone()
+    }
+
+    /// Returns the latencies of the requests served, updated as more are.
+    pub fn latencies(&self) -> SharedLatencies {
+        self.latencies.clone()
+    }
+
+    /// Loads the signatures and builds the trees of some snapshots, so that browsing them doesn't
+    /// have to wait.
+    ///
+    /// Up to `threads` signature chains are loaded at the same time. Backups can't be shared
+    /// between threads, so each thread opens its own on the backend returned by `open`, and the
+    /// snapshots it loads are then served from it.
+    pub fn preload<F>(&mut self, which: Preload, threads: usize, open: F) -> io::Result<()>
+        where F: Fn() -> io::Result<B> + Sync,
+              B: Send
+    {
+        // the snapshots still being uploaded can't be loaded yet
+        let shown = (0..self.snapshots.len())
+                        .filter(|sid| !self.pending.contains(sid))
+                        .collect::<Vec<_>>();
+        let first = match which {
+            Preload::All => 0,
+            Preload::Latest => shown.len().saturating_sub(1),
         };
-        reply.attr(&TTL, &attr);
+        let wanted = shown[first..]
+                         .iter()
+                         .cloned()
+                         .filter(|sid| self.trees[*sid].is_none() && !self.is_left_out(*sid))
+                         .collect::<HashSet<_>>();
+        // the snapshots of a chain share its signatures, so they are loaded together
+        let mut chains = BTreeMap::new();
+        let mut times = Vec::new();
+        let mut chain = 0;
+        for (sid, snapshot) in try!(self.backup.snapshots()).enumerate() {
+            if snapshot.is_full() {
+                chain = sid;
+            }
+            times.push(snapshot.time());
+            if wanted.contains(&sid) {
+                info!("Loading snapshot {}", time_to_path(snapshot.time()));
+                chains.entry(chain).or_insert_with(Vec::new).push(sid);
+            }
+        }
+        if chains.is_empty() {
+            return Ok(());
+        }
+        let threads = cmp::max(1, cmp::min(threads, chains.len()));
+        let queue = Mutex::new(chains.into_iter().map(|(_, sids)| sids).collect::<Vec<_>>());
+        let results = {
+            let (options, snapshots, times, queue, open) = (&self.options,
+                                                            &self.snapshots,
+                                                            &times,
+                                                            &queue,
+                                                            &open);
+            thread::scope(|scope| {
+                let workers = (0..threads)
+                                  .map(|_| {
+                                      scope.spawn(move || {
+                                          load_chains(queue, open, options, snapshots, times)
+                                      })
+                                  })
+                                  .collect::<Vec<_>>();
+                workers.into_iter()
+                       .map(|worker| worker.join().expect("preload thread panicked"))
+                       .collect::<Vec<_>>()
+            })
+        };
+        for result in results {
+            let (backup, trees) = try!(result);
+            self.preloaded.push(backup);
+            let index = self.preloaded.len() - 1;
+            let mut used = false;
+            for (sid, tree) in trees {
+                match tree {
+                    Ok(mut tree) => {
+                        self.preloaded_sids.insert(sid, index);
+                        used = true;
+                        // the inodes are taken from the allocator only now
+                        tree.shift(self.inodes.next_ino() - 1);
+                        try!(self.add_tree(sid, tree));
+                    }
+                    // the snapshot is left out in best-effort mode
+                    Err(e) => {
+                        if !self.options.best_effort {
+                            return Err(e);
+                        }
+                        self.skip_snapshot(sid, &e);
+                    }
+                }
+            }
+            if !used {
+                self.preloaded.pop();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether some snapshots are left out because they can't be loaded.
+    pub fn is_degraded(&self) -> bool {
+        !self.skipped.is_empty()
+    }
+
+    /// Sets a function to be called when the session ends, including when the filesystem is
+    /// unmounted externally.
+    pub fn on_unmount<F: FnMut() + Send + 'static>(&mut self, f: F) {
+        self.on_unmount = Some(Box::new(f));
+    }
+
+    /// Shows the report of a scrubber in the status directory.
+    pub fn show_scrub_report(&mut self, report: SharedScrubReport) {
+        self.scrub_report = Some(report);
+    }
+
+    /// Shows the traffic with the backend in the stats file of the status directory.
+    pub fn show_traffic(&mut self, stats: SharedStats) {
+        self.traffic = Some(stats);
+    }
+
+    // The operations served through fuse, taking any reply implementation, so that they can be
+    // driven without a mount.
+
+    fn handle_getattr<R: AttrReply>(&mut self, ino: u64, reply: R) {
+        let ino = self.resolve_root(ino);
+        if self.is_stale(ino) {
+            reply_error!(reply, ENOENT, "Ino {} is in a snapshot no longer in the backend", ino);
+            return;
+        }
+        if self.dirs.is_dir(ino) || ino == self.status.dir_ino() {
+            self.getattr_dir(ino, reply);
+        } else if self.status.is_status(ino) {
+            self.getattr_status_file(ino, reply);
+        } else if self.dirs.is_alias(ino) {
+            let attr = self.attr_alias(ino);
+            reply.attr(&self.ttl, &attr);
+        } else if self.snapshots.is_snapshot(ino) {
+            self.getattr_snapshot(ino, reply);
+        } else if self.special.is_special(ino) {
+            self.getattr_special(ino, reply);
+        } else {
+            self.getattr_entry(ino, reply);
+        }
+    }
+
+    fn handle_readdir<R: DirectoryReply>(&mut self, ino: u64, fh: u64, offset: u64, reply: R) {
+        let ino = self.resolve_root(ino);
+        if self.is_stale(ino) {
+            reply_error!(reply, ENOENT, "Ino {} is in a snapshot no longer in the backend", ino);
+            return;
+        }
+        self.readdir_listing(ino, fh, offset, reply);
+    }
+
+    fn handle_opendir<R: OpenReply>(&mut self, ino: u64, reply: R) {
+        let ino = self.resolve_root(ino);
+        self.accessed(ino);
+        self.last_fh += 1;
+        reply.opened(self.last_fh, 0);
+    }
+
+    fn handle_releasedir<R: EmptyReply>(&mut self, fh: u64, reply: R) {
+        self.listings.remove(&fh);
+        reply.ok();
+    }
+
+    fn handle_lookup<R: EntryReply>(&mut self, parent: u64, name: &Path, reply: R) {
+        // the status directory stays in the root, even when a snapshot is shown in its place
+        if parent == 1 && name == Path::new(STATUS_DIR) {
+            let attr = self.attr_dir(self.status.dir_ino());
+            reply.entry(&self.ttl, &attr, 0);
+            return;
+        }
+        let parent = self.resolve_root(parent);
+        if self.is_stale(parent) {
+            reply_error!(reply, ENOENT, "Ino {} is in a snapshot no longer in the backend", parent);
+            return;
+        }
+        if self.dirs.is_dir(parent) {
+            self.lookup_dir(parent, name, reply);
+        } else if self.status.is_status(parent) {
+            self.lookup_status(parent, name, reply);
+        } else if let Some(reply) = self.lookup_special(parent, name, reply) {
+            self.lookup_entry(parent, name, reply);
+        }
+    }
+
+    fn handle_readlink<R: DataReply>(&mut self, ino: u64, reply: R) {
+        if self.is_stale(ino) {
+            reply_error!(reply, ENOENT, "Ino {} is in a snapshot no longer in the backend", ino);
+            return;
+        }
+        if let Some(target) = self.dirs.alias_target(ino) {
+            reply.data(target.as_bytes());
+        } else {
+            self.readlink_entry(ino, reply);
+        }
+    }
+
+    fn handle_open<R: OpenReply>(&mut self, ino: u64, flags: u32, reply: R) {
+        if self.is_stale(ino) {
+            reply_error!(reply, ENOENT, "Ino {} is in a snapshot no longer in the backend", ino);
+            return;
+        }
+        if flags as c_int & O_ACCMODE != O_RDONLY || flags as c_int & O_TRUNC != 0 {
+            refuse_read_only("open for writing", ino);
+            reply.error(EROFS);
+        } else if self.special.is_special(ino) {
+            self.accessed(ino);
+            self.open_special(ino, reply);
+        } else if self.status.is_status(ino) {
+            self.accessed(ino);
+            self.open_status_file(ino, reply);
+        } else if let Some(raw) = self.refused_type(ino) {
+            reply_error!(reply,
+                         EOPNOTSUPP,
+                         "Refusing open on ino {}: the entry has unknown type {}",
+                         ino,
+                         raw);
+        } else {
+            self.accessed(ino);
+            reply.opened(0, 0);
+        }
+    }
+
+    fn handle_read<R: DataReply>(&mut self, ino: u64, fh: u64, offset: u64, size: u32, reply: R) {
+        if self.is_stale(ino) {
+            reply_error!(reply, ENOENT, "Ino {} is in a snapshot no longer in the backend", ino);
+            return;
+        }
+        if self.special.is_special(ino) || self.status.is_status(ino) {
+            self.read_special(fh, offset, size, reply);
+        } else {
+            // the content is not available
+            let errno = if self.options.strict { EIO } else { ENOSYS };
+            reply_error!(reply, errno, "Can't read ino {}: the content is not available", ino);
+        }
+    }
+
+    fn handle_release<R: EmptyReply>(&mut self, fh: u64, reply: R) {
+        self.handles.remove(&fh);
+        reply.ok();
+    }
+
+    /// getattr for the root directory and the ones leading to the snapshots.
+    fn getattr_dir<R: AttrReply>(&mut self, ino: u64, reply: R) {
+        let attr = self.attr_dir(ino);
+        reply.attr(&self.ttl, &attr);
     }
 
     /// getattr for a snapshot directory.
-    fn getattr_snapshot(&mut self, ino: u64, reply: ReplyAttr) {
-        let snapshot = try_or_log!(self.snapshot_from_sid(self.snapshots.sid_from_ino(ino)));
-        let attr = self.attr_snapshot(&snapshot, ino);
-        reply.attr(&TTL, &attr);
+    fn getattr_snapshot<R: AttrReply>(&mut self, ino: u64, reply: R) {
+        let sid = self.snapshots.sid_from_ino(ino);
+        let size = self.loaded_size(sid);
+        let snapshot = try_or_errno!(self.snapshot_from_sid(sid),
+                                     reply,
+                                     "Can't load snapshot #{}",
+                                     sid);
+        let attr = self.attr_snapshot(&snapshot, ino, size);
+        reply.attr(&self.snapshot_ttl(sid), &attr);
     }
 
     /// getattr for a backup entry.
-    fn getattr_entry(&mut self, ino: u64, reply: ReplyAttr) {
-        let (tree, sid) = unwrap_opt_or_error!(self.find_tree_with_ino(ino),
-                                               reply,
-                                               ENOENT,
-                                               "Can't find tree for ino {}",
-                                               ino);
-        let entry = unwrap_opt_or_error!(tree.find_node(ino),
+    fn getattr_entry<R: AttrReply>(&mut self, ino: u64, reply: R) {
+        if let Some(attr) = self.cached_attr(ino) {
+            reply.attr(&self.ttl, &attr);
+            return;
+        }
+        let attr = {
+            let (tree, sid) = unwrap_opt_or_error!(self.find_tree_with_ino(ino),
+                                                   reply,
+                                                   ENOENT,
+                                                   "Can't find tree for ino {}",
+                                                   ino);
+            let node = unwrap_opt_or_error!(tree.find_node(ino),
+                                            reply,
+                                            ENOENT,
+                                            "Can't find entry for ino {}",
+                                            ino);
+            let snapshot = try_or_errno!(self.snapshot_from_sid(sid),
                                          reply,
-                                         ENOENT,
-                                         "Can't find entry for ino {}",
-                                         ino);
-        let snapshot = try_or_log!(self.snapshot_from_sid(sid));
-        let entries = try_or_log!(snapshot.entries());
-        let attr = self.attr_entry(entry.as_path_entry(entries.as_signature()).as_signature(),
-                                   ino);
-        reply.attr(&TTL, &attr);
-    }
-
-    /// readdir for the root directory.
-    fn readdir_root(&mut self, mut offset: u64, mut reply: ReplyDirectory) {
-        // offset is the last returned offset
-        if offset == 0 {
-            // assume first two replies does fit in the buffer
-            reply.add(1, 0, FileType::Directory, &Path::new("."));
-            reply.add(1, 1, FileType::Directory, &Path::new(".."));
-            offset += 1;
-        }
-
-        debug!("Skip first {} snapshots", offset - 1);
-        let snapshots = try_or_log!(self.backup.snapshots()).skip(offset as usize - 1);
-        for snapshot in snapshots {
-            offset += 1;
-            trace!("Add snapshot for offset {}", offset);
-            let path = time_to_path(snapshot.time());
-            if reply.add(offset, offset, FileType::Directory, &Path::new(&path)) {
+                                         "Can't load snapshot #{}",
+                                         sid);
+            let entries = try_or_error!(snapshot.entries(),
+                                        reply,
+                                        EIO,
+                                        "Can't read the signatures of snapshot {}",
+                                        time_to_path(snapshot.time()));
+            // bound here, since the temporaries of the last expression outlive the signatures
+            let entry = node.as_path_entry(entries.as_signature());
+            let num_children = self.shown_children(&node,
+                                                   entry.as_signature(),
+                                                   entries.as_signature());
+            try_or_error!(self.attr_entry(entry.as_signature(), ino, num_children),
+                          reply,
+                          EIO,
+                          "Can't report the attributes of ino {}",
+                          ino)
+        };
+        self.cache_attr(attr);
+        reply.attr(&self.ttl, &attr);
+    }
+
+    /// getattr for the files of the status directory.
+    fn getattr_status_file<R: AttrReply>(&mut self, ino: u64, reply: R) {
+        if !self.is_status_file_shown(ino) {
+            reply_error!(reply, ENOENT, "Status file ino {} is not present", ino);
+            return;
+        }
+        let attr = self.attr_status_file(ino);
+        reply.attr(&self.ttl, &attr);
+    }
+
+    /// getattr for a special entry.
+    fn getattr_special<R: AttrReply>(&mut self, ino: u64, reply: R) {
+        let node = unwrap_opt_or_error!(self.special.node_from_ino(ino),
+                                        reply,
+                                        ENOENT,
+                                        "Can't find special entry for ino {}",
+                                        ino);
+        let attr = try_or_error!(self.attr_special(node),
+                                 reply,
+                                 EIO,
+                                 "Can't read {:?} of snapshot {}",
+                                 node.kind,
+                                 self.snapshot_name(node.sid));
+        reply.attr(&self.ttl, &attr);
+    }
+
+    /// readdir for any directory.
+    ///
+    /// The whole listing is computed on the first call for a directory handle, and kept until the
+    /// handle is released, so that the following calls can resume from the given offset in
+    /// constant time.
+    fn readdir_listing<R: DirectoryReply>(&mut self, ino: u64, fh: u64, offset: u64, mut reply: R) {
+        if !self.listings.contains_key(&fh) {
+            let listing = match self.list_dir(ino) {
+                Ok(listing) => listing,
+                Err(e) => {
+                    reply_error!(reply, errno(e.kind()), "Can't list directory ino {}: {}", ino, e);
+                    return;
+                }
+            };
+            self.listings.insert(fh, listing);
+        }
+        let listing = &self.listings[&fh];
+        // offset is the one returned with the last entry, that is the index of the next one
+        for (index, entry) in listing.iter().enumerate().skip(offset as usize) {
+            trace!("Add ino {} for path {:?} with ftype {:?}",
+                   entry.ino,
+                   entry.name,
+                   entry.kind);
+            if reply.add(entry.ino, index as u64 + 1, entry.kind, &entry.name) {
                 // the buffer is full, need to return
                 break;
             }
@@ -119,123 +664,441 @@ impl<B: Backend> RuplicityFs<B> {
         reply.ok();
     }
 
-    /// readdir for snapshot contents.
-    fn readdir_snapshot(&mut self, ino: u64, mut offset: u64, mut reply: ReplyDirectory) {
-        // offset is the last returned offset
-        if offset == 0 {
-            // assume first two replies does fit in the buffer
-            reply.add(ino, 0, FileType::Directory, &Path::new("."));
-            reply.add(1, 1, FileType::Directory, &Path::new(".."));
-            offset += 1;
+    /// Returns all the entries of a directory, including `.` and `..`.
+    fn list_dir(&mut self, ino: u64) -> error::Result<Vec<DirEntry>> {
+        if self.dirs.is_dir(ino) {
+            self.list_root_dir(ino)
+        } else if self.snapshots.is_snapshot(ino) {
+            self.list_snapshot(ino)
+        } else if self.special.is_special(ino) {
+            self.list_special(ino)
+        } else if self.status.is_status(ino) {
+            Ok(self.list_status(ino))
+        } else {
+            self.list_entry(ino)
+        }
+    }
+
+    /// Lists the root directory and the ones leading to the snapshots.
+    fn list_root_dir(&self, ino: u64) -> error::Result<Vec<DirEntry>> {
+        let dir = try!(self.dirs.dir(ino).ok_or(Error::MissingInode {
+            what: "directory",
+            ino: ino,
+        }));
+        let mut result = vec![DirEntry::dir(ino, "."), DirEntry::dir(dir.parent, "..")];
+        for &(ref name, child) in &dir.children {
+            let (child_ino, kind) = match child {
+                RootChild::Snapshot(sid) if self.is_left_out(sid) => {
+                    continue;
+                }
+                RootChild::Dir(ino) => (ino, FileType::Directory),
+                RootChild::Snapshot(sid) => (self.snapshots.ino_from_sid(sid), FileType::Directory),
+                RootChild::Alias(ino) => (ino, FileType::Symlink),
+            };
+            result.push(DirEntry {
+                ino: child_ino,
+                kind: kind,
+                name: PathBuf::from(name),
+            });
+        }
+        if ino == 1 {
+            result.push(DirEntry::dir(self.status.dir_ino(), STATUS_DIR));
         }
+        Ok(result)
+    }
 
+    /// Lists the contents of a snapshot.
+    fn list_snapshot(&mut self, ino: u64) -> error::Result<Vec<DirEntry>> {
         let sid = self.snapshots.sid_from_ino(ino);
-        let (tree, snapshot) = try_or_log!(self.tree_for_snapshot(sid));
-        let entries = try_or_log!(snapshot.entries());
-        for (offset, entry) in tree.children(entries.as_signature())
-                                   .enumerate()
-                                   .skip(offset as usize - 1) {
-            let offset = offset as u64 + 2;
-            let ftype = from_entry_type(entry.as_signature().entry_type());
+        let mut result = vec![DirEntry::dir(ino, "."),
+                              DirEntry::dir(self.dirs.snapshot_parent(sid), "..")];
+        for &(kind, name) in &SNAPSHOT_SPECIALS {
+            let special_ino = self.special.ino_from_node(SpecialNode {
+                sid: sid,
+                kind: kind,
+            });
+            result.push(DirEntry {
+                ino: special_ino,
+                kind: if kind.is_dir() {
+                    FileType::Directory
+                } else {
+                    FileType::RegularFile
+                },
+                name: PathBuf::from(name),
+            });
+        }
+
+        let name = self.snapshot_name(sid);
+        // the tree keeps self borrowed
+        let hide_unknown = self.options.unknown_entries == UnknownEntries::Hide;
+        let (tree, snapshot) = try!(self.tree_for_snapshot(sid).map_err(|e| {
+            Error::BuildTree {
+                snapshot: name.clone(),
+                cause: e,
+            }
+        }));
+        let entries = try!(snapshot.entries().map_err(|e| {
+            Error::ReadSignatures {
+                snapshot: name,
+                cause: e,
+            }
+        }));
+        for entry in tree.children(entries.as_signature()) {
             let path = unwrap_opt_or_continue!(entry.path());
-            trace!("Add ino {} for path {:?} with ftype {:?}",
-                   entry.ino(),
-                   path,
-                   ftype);
-            if reply.add(entry.ino(), offset, ftype, path) {
-                // the buffer is full, need to return
-                break;
+            if hide_unknown && is_unknown(entry.as_signature().entry_type()) {
+                continue;
             }
+            result.push(DirEntry {
+                ino: entry.ino(),
+                kind: from_entry_type(entry.as_signature().entry_type()),
+                name: path.to_path_buf(),
+            });
         }
-        reply.ok();
+        // a snapshot in place of the root has the status directory as well
+        if self.dirs.root_snapshot() == Some(sid) {
+            result.push(DirEntry::dir(self.status.dir_ino(), STATUS_DIR));
+        }
+        Ok(result)
     }
 
-    /// readdir for an entry.
-    fn readdir_entry(&mut self, ino: u64, mut offset: u64, mut reply: ReplyDirectory) {
-        let (tree, sid) = unwrap_opt_or_error!(self.find_tree_with_ino(ino),
-                                               reply,
-                                               ENOENT,
-                                               "Can't find tree for ino {}",
-                                               ino);
-        let parent_entry = unwrap_opt_or_error!(tree.find_node(ino),
-                                                reply,
-                                                ENOENT,
-                                                "Can't find entry for ino {}",
-                                                ino);
-        // offset is the last returned offset
-        if offset == 0 {
-            // assume first two replies does fit in the buffer
-            reply.add(ino, 0, FileType::Directory, &Path::new("."));
-            reply.add(parent_entry.parent(),
-                      1,
-                      FileType::Directory,
-                      &Path::new(".."));
-            offset += 1;
-        }
-        let snapshot = try_or_log!(self.snapshot_from_sid(sid));
-        let entries = try_or_log!(snapshot.entries());
-        for (offset, entry) in parent_entry.children(entries.as_signature())
-                                           .enumerate()
-                                           .skip(offset as usize - 1) {
-            let offset = offset as u64 + 2;
-            let ftype = from_entry_type(entry.as_signature().entry_type());
+    /// Lists the contents of a backed up directory.
+    fn list_entry(&self, ino: u64) -> error::Result<Vec<DirEntry>> {
+        let (tree, sid) = try!(self.find_tree_with_ino(ino).ok_or(Error::MissingInode {
+            what: "tree",
+            ino: ino,
+        }));
+        let parent_entry = try!(tree.find_node(ino).ok_or(Error::MissingInode {
+            what: "entry",
+            ino: ino,
+        }));
+        let mut result = vec![DirEntry::dir(ino, "."), DirEntry::dir(parent_entry.parent(), "..")];
+        let snapshot = try!(self.snapshot_from_sid(sid).map_err(|e| {
+            Error::ListBackendFiles {
+                snapshot: self.snapshot_name(sid),
+                cause: e,
+            }
+        }));
+        let entries = try!(snapshot.entries().map_err(|e| {
+            Error::ReadSignatures {
+                snapshot: time_to_path(snapshot.time()),
+                cause: e,
+            }
+        }));
+        for entry in parent_entry.children(entries.as_signature()) {
             let path = unwrap_opt_or_continue!(entry.path());
-            trace!("Add ino {} for path {:?} with ftype {:?}",
-                   entry.ino(),
-                   path,
-                   ftype);
-            if reply.add(entry.ino(), offset, ftype, path) {
-                // the buffer is full, need to return
-                break;
+            if self.is_hidden(entry.as_signature()) {
+                continue;
             }
+            result.push(DirEntry {
+                ino: entry.ino(),
+                kind: from_entry_type(entry.as_signature().entry_type()),
+                name: path.to_path_buf(),
+            });
         }
-        reply.ok();
+        Ok(result)
     }
 
-    /// lookup for snapshots.
-    fn lookup_snapshot(&mut self, name: &Path, reply: ReplyEntry) {
-        let sid = unwrap_opt_or_error!(self.snapshots.sid_from_path(name),
-                                       reply,
-                                       ENOENT,
-                                       "Can't find snapshot for path {:?}",
-                                       name);
-        let snapshot = try_or_log!(self.snapshot_from_sid(sid));
-        let attr = self.attr_snapshot(&snapshot, self.snapshots.ino_from_sid(sid));
-        reply.entry(&TTL, &attr, 0);
+    /// Lists the `.duplicity` directory.
+    fn list_special(&self, ino: u64) -> error::Result<Vec<DirEntry>> {
+        let node = try!(self.special.node_from_ino(ino).ok_or(Error::MissingInode {
+            what: "special entry",
+            ino: ino,
+        }));
+        let mut result = vec![DirEntry::dir(ino, "."),
+                              DirEntry::dir(self.snapshots.ino_from_sid(node.sid), "..")];
+        let children = try!(self.special_children(node.sid).map_err(|e| {
+            Error::ListBackendFiles {
+                snapshot: self.snapshot_name(node.sid),
+                cause: e,
+            }
+        }));
+        for (child, name) in children {
+            result.push(DirEntry {
+                ino: self.special.ino_from_node(child),
+                kind: FileType::RegularFile,
+                name: PathBuf::from(name),
+            });
+        }
+        Ok(result)
     }
 
-    /// lookup for snapshot entries.
-    fn lookup_entry(&mut self, parent: u64, name: &Path, reply: ReplyEntry) {
-        let (tree, sid) = unwrap_opt_or_error!(self.find_tree_with_ino(parent),
-                                               reply,
-                                               ENOENT,
-                                               "Can't find tree for ino {}",
-                                               parent);
-        let parent_entry = unwrap_opt_or_error!(tree.find_node(parent),
-                                                reply,
-                                                ENOENT,
-                                                "Can't find entry for ino {}",
-                                                parent);
-        let snapshot = try_or_log!(self.snapshot_from_sid(sid));
-        let entries = try_or_log!(snapshot.entries());
-        let entry = parent_entry.children(entries.as_signature()).find(|entry| {
-            match entry.path() {
-                Some(path) => path == name,
-                None => false,
+    /// Lists the status directory.
+    fn list_status(&self, ino: u64) -> Vec<DirEntry> {
+        let mut result = vec![DirEntry::dir(ino, "."), DirEntry::dir(1, "..")];
+        let files = [(self.status.errors_ino(), ERRORS_FILE),
+                     (self.status.latency_ino(), LATENCY_FILE),
+                     (self.status.stats_ino(), STATS_FILE),
+                     (self.status.degraded_ino(), DEGRADED_FILE),
+                     (self.status.scrub_report_ino(), SCRUB_REPORT_FILE)];
+        for &(ino, name) in &files {
+            if self.is_status_file_shown(ino) {
+                result.push(DirEntry {
+                    ino: ino,
+                    kind: FileType::RegularFile,
+                    name: PathBuf::from(name),
+                });
             }
-        });
-        let entry = unwrap_opt_or_error!(entry,
+        }
+        result
+    }
+
+    /// Returns whether a file of the status directory is present.
+    fn is_status_file_shown(&self, ino: u64) -> bool {
+        if ino == self.status.degraded_ino() {
+            !self.skipped.is_empty()
+        } else if ino == self.status.scrub_report_ino() {
+            self.scrub_report.is_some()
+        } else {
+            true
+        }
+    }
+
+    /// lookup for the children of the root directory and the ones leading to the snapshots.
+    fn lookup_dir<R: EntryReply>(&mut self, parent: u64, name: &Path, reply: R) {
+        let child = {
+            let dir = unwrap_opt_or_error!(self.dirs.dir(parent),
+                                           reply,
+                                           ENOENT,
+                                           "Can't find directory for ino {}",
+                                           parent);
+            // the snapshots left out are not found either
+            dir.children
+               .iter()
+               .find(|child| Path::new(&child.0) == name)
+               .map(|child| child.1)
+               .filter(|child| match *child {
+                   RootChild::Snapshot(sid) => !self.is_left_out(sid),
+                   _ => true,
+               })
+        };
+        let child = unwrap_opt_or_error!(child,
                                          reply,
                                          ENOENT,
-                                         "Can't find path '{:?}' in parent {}",
+                                         "Can't find path {:?} in parent {}",
                                          name,
                                          parent);
-        let attr = self.attr_entry(entry.as_signature(), entry.ino());
-        reply.entry(&TTL, &attr, 0);
+        let (attr, ttl) = match child {
+            RootChild::Dir(ino) => (self.attr_dir(ino), self.ttl),
+            RootChild::Alias(ino) => (self.attr_alias(ino), self.ttl),
+            RootChild::Snapshot(sid) => {
+                if self.is_stale(self.snapshots.ino_from_sid(sid)) {
+                    reply_error!(reply,
+                                 ENOENT,
+                                 "Snapshot {} is no longer in the backend",
+                                 self.snapshot_name(sid));
+                    return;
+                }
+                let size = self.loaded_size(sid);
+                let snapshot = try_or_errno!(self.snapshot_from_sid(sid),
+                                             reply,
+                                             "Can't load snapshot #{}",
+                                             sid);
+                (self.attr_snapshot(&snapshot, self.snapshots.ino_from_sid(sid), size),
+                 self.snapshot_ttl(sid))
+            }
+        };
+        reply.entry(&ttl, &attr, 0);
+    }
+
+    /// lookup for snapshot entries.
+    fn lookup_entry<R: EntryReply>(&mut self, parent: u64, name: &Path, reply: R) {
+        // the parent can be looked up before the snapshot is ever listed
+        if self.snapshots.is_snapshot(parent) {
+            let sid = self.snapshots.sid_from_ino(parent);
+            try_or_error!(self.tree_for_snapshot(sid).map(|_| ()),
+                          reply,
+                          EIO,
+                          "Can't build the tree of snapshot {}",
+                          self.snapshot_name(sid));
+        }
+        let attr = {
+            let (tree, sid) = unwrap_opt_or_error!(self.find_tree_with_ino(parent),
+                                                   reply,
+                                                   ENOENT,
+                                                   "Can't find tree for ino {}",
+                                                   parent);
+            let parent_entry = unwrap_opt_or_error!(tree.find_node(parent),
+                                                    reply,
+                                                    ENOENT,
+                                                    "Can't find entry for ino {}",
+                                                    parent);
+            let snapshot = try_or_errno!(self.snapshot_from_sid(sid),
+                                         reply,
+                                         "Can't load snapshot #{}",
+                                         sid);
+            let entries = try_or_error!(snapshot.entries(),
+                                        reply,
+                                        EIO,
+                                        "Can't read the signatures of snapshot {}",
+                                        time_to_path(snapshot.time()));
+            let entry = parent_entry.children(entries.as_signature()).find(|entry| {
+                match entry.path() {
+                    Some(path) => path == name && !self.is_hidden(entry.as_signature()),
+                    None => false,
+                }
+            });
+            let entry = unwrap_opt_or_error!(entry,
+                                             reply,
+                                             ENOENT,
+                                             "Can't find path '{:?}' in parent {}",
+                                             name,
+                                             parent);
+            let node = unwrap_opt_or_error!(tree.find_node(entry.ino()),
+                                            reply,
+                                            ENOENT,
+                                            "Can't find entry for ino {}",
+                                            entry.ino());
+            let num_children = self.shown_children(&node,
+                                                   entry.as_signature(),
+                                                   entries.as_signature());
+            try_or_error!(self.attr_entry(entry.as_signature(), entry.ino(), num_children),
+                          reply,
+                          EIO,
+                          "Can't report the attributes of ino {}",
+                          entry.ino())
+        };
+        // the entry is likely to be stat'ed next
+        self.cache_attr(attr);
+        reply.entry(&self.ttl, &attr, 0);
+    }
+
+    /// lookup for special entries.
+    ///
+    /// Returns the reply back if the name does not refer to a special entry.
+    fn lookup_special<R: EntryReply>(&mut self, parent: u64, name: &Path, reply: R) -> Option<R> {
+        let node = if self.snapshots.is_snapshot(parent) {
+            let kind = SNAPSHOT_SPECIALS.iter()
+                                        .find(|special| Path::new(special.1) == name)
+                                        .map(|special| special.0);
+            match kind {
+                Some(kind) => {
+                    SpecialNode {
+                        sid: self.snapshots.sid_from_ino(parent),
+                        kind: kind,
+                    }
+                }
+                None => {
+                    return Some(reply);
+                }
+            }
+        } else {
+            let dir = match self.special.node_from_ino(parent) {
+                Some(dir) => dir,
+                None => {
+                    return Some(reply);
+                }
+            };
+            let children = match self.special_children(dir.sid) {
+                Ok(children) => children,
+                Err(e) => {
+                    reply_error!(reply,
+                                 EIO,
+                                 "Can't list the backend files of snapshot {}: {}",
+                                 self.snapshot_name(dir.sid),
+                                 e);
+                    return None;
+                }
+            };
+            match children.into_iter().find(|c| Path::new(&c.1) == name) {
+                Some((child, _)) => child,
+                None => {
+                    reply_error!(reply,
+                                 ENOENT,
+                                 "Can't find path '{:?}' in parent {}",
+                                 name,
+                                 parent);
+                    return None;
+                }
+            }
+        };
+        match self.attr_special(node) {
+            Ok(attr) => reply.entry(&self.ttl, &attr, 0),
+            Err(e) => {
+                reply_error!(reply,
+                             EIO,
+                             "Can't read {:?} of snapshot {}: {}",
+                             node.kind,
+                             self.snapshot_name(node.sid),
+                             e)
+            }
+        }
+        None
+    }
+
+    /// lookup for the files of the status directory.
+    fn lookup_status<R: EntryReply>(&mut self, parent: u64, name: &Path, reply: R) {
+        let ino = if parent != self.status.dir_ino() {
+            None
+        } else if name == Path::new(ERRORS_FILE) {
+            Some(self.status.errors_ino())
+        } else if name == Path::new(LATENCY_FILE) {
+            Some(self.status.latency_ino())
+        } else if name == Path::new(STATS_FILE) {
+            Some(self.status.stats_ino())
+        } else if name == Path::new(DEGRADED_FILE) {
+            Some(self.status.degraded_ino())
+        } else if name == Path::new(SCRUB_REPORT_FILE) {
+            Some(self.status.scrub_report_ino())
+        } else {
+            None
+        };
+        match ino {
+            Some(ino) if self.is_status_file_shown(ino) => {
+                let attr = self.attr_status_file(ino);
+                reply.entry(&self.ttl, &attr, 0);
+            }
+            _ => {
+                reply_error!(reply,
+                             ENOENT,
+                             "Can't find path '{:?}' in the status directory",
+                             name)
+            }
+        }
+    }
+
+    /// open for the files of the status directory.
+    ///
+    /// The content is taken when the file is opened, and read bypassing the page cache since it
+    /// can change without its size changing.
+    fn open_status_file<R: OpenReply>(&mut self, ino: u64, reply: R) {
+        let content = self.status_content(ino);
+        self.last_fh += 1;
+        self.handles.insert(self.last_fh, content);
+        reply.opened(self.last_fh, FOPEN_DIRECT_IO);
+    }
+
+    /// open for special files.
+    fn open_special<R: OpenReply>(&mut self, ino: u64, reply: R) {
+        let node = unwrap_opt_or_error!(self.special.node_from_ino(ino),
+                                        reply,
+                                        ENOENT,
+                                        "Can't find special entry for ino {}",
+                                        ino);
+        let content = try_or_error!(self.special_content(node),
+                                    reply,
+                                    EIO,
+                                    "Can't read {:?} of snapshot {}",
+                                    node.kind,
+                                    self.snapshot_name(node.sid));
+        self.last_fh += 1;
+        self.handles.insert(self.last_fh, content);
+        reply.opened(self.last_fh, 0);
+    }
+
+    /// read for special files.
+    fn read_special<R: DataReply>(&mut self, fh: u64, offset: u64, size: u32, reply: R) {
+        let content = unwrap_opt_or_error!(self.handles.get(&fh),
+                                           reply,
+                                           ENOENT,
+                                           "Can't find open file handle {}",
+                                           fh);
+        let start = cmp::min(offset as usize, content.len());
+        let end = cmp::min(start + size as usize, content.len());
+        reply.data(&content[start..end]);
     }
 
     /// readlink for entry
-    fn readlink_entry(&mut self, ino: u64, reply: ReplyData) {
+    fn readlink_entry<R: DataReply>(&mut self, ino: u64, reply: R) {
         let (tree, sid) = unwrap_opt_or_error!(self.find_tree_with_ino(ino),
                                                reply,
                                                ENOENT,
@@ -246,95 +1109,694 @@ impl<B: Backend> RuplicityFs<B> {
                                          ENOENT,
                                          "Can't find entry for ino {}",
                                          ino);
-        let snapshot = try_or_log!(self.snapshot_from_sid(sid));
-        let entries = try_or_log!(snapshot.entries());
+        let snapshot = try_or_errno!(self.snapshot_from_sid(sid),
+                                     reply,
+                                     "Can't load snapshot #{}",
+                                     sid);
+        let entries = try_or_error!(snapshot.entries(),
+                                    reply,
+                                    EIO,
+                                    "Can't read the signatures of snapshot {}",
+                                    time_to_path(snapshot.time()));
         let pentry = entry.as_path_entry(entries.as_signature());
         match pentry.as_signature().linked_path() {
             Some(path) => {
-                reply.data(path2bytes(path).unwrap_or(&[]));
+                let path = self.rewrite_symlink(sid, pentry.as_signature().path(), path);
+                reply.data(path2bytes(&path).unwrap_or(&[]));
             }
             None => {
-                reply.error(ENOSYS);
+                reply_error!(reply, self.unsupported(), "Ino {} is not a symbolic link", ino);
             }
         }
     }
 
-    /// Returns attributes for a snapshot.
-    fn attr_snapshot(&self, snapshot: &Snapshot, ino: u64) -> FileAttr {
-        let ts = snapshot.time();
+    /// Returns attributes for the root directory, the ones leading to the snapshots and the
+    /// status directory.
+    fn attr_dir(&self, ino: u64) -> FileAttr {
+        let ts = time::get_time();
+        let (uid, gid) = self.owner(Some(0), Some(0));
+        // the number of children, as for the directories of the snapshots
+        let size = match self.dirs.dir(ino) {
+            Some(dir) => {
+                let shown = dir.children
+                               .iter()
+                               .filter(|child| match child.1 {
+                                   RootChild::Snapshot(sid) => !self.is_left_out(sid),
+                                   _ => true,
+                               })
+                               .count();
+                // the root has the status directory as well
+                if ino == 1 { shown + 1 } else { shown }
+            }
+            None if ino == self.status.dir_ino() => self.list_status(ino).len() - 2,
+            None => 0,
+        };
         FileAttr {
             ino: ino,
-            size: 0,
+            size: size as u64,
             blocks: 0,
-            atime: ts,
+            atime: self.atime(ino, ts),
             mtime: ts,
             ctime: ts,
             crtime: ts,
             kind: FileType::Directory,
             perm: 0o555,
-            nlink: 0,
-            uid: 0,
-            gid: 0,
+            nlink: self.nlink(FileType::Directory),
+            uid: uid,
+            gid: gid,
             rdev: 0,
             flags: 0,
         }
     }
 
-    /// Returns attributes for an entry
-    fn attr_entry(&self, entry: &SigEntry, ino: u64) -> FileAttr {
-        let ts = entry.mtime();
+    /// Returns attributes for a file of the status directory.
+    fn attr_status_file(&self, ino: u64) -> FileAttr {
+        let ts = time::get_time();
+        let size = self.status_content(ino).len() as u64;
+        let (uid, gid) = self.owner(Some(0), Some(0));
         FileAttr {
             ino: ino,
-            size: entry.size_hint().map_or(0, |sh| sh.1 as u64),
-            blocks: 0,
-            atime: ts,
+            size: size,
+            blocks: size_to_blocks(size),
+            atime: self.atime(ino, ts),
             mtime: ts,
             ctime: ts,
             crtime: ts,
-            kind: from_entry_type(entry.entry_type()),
-            perm: entry.mode().map_or(0o777, |p| p as u16),
-            nlink: 0,
-            uid: entry.userid().unwrap_or(100),
-            gid: entry.groupid().unwrap_or(100),
+            kind: FileType::RegularFile,
+            perm: 0o444,
+            nlink: self.nlink(FileType::RegularFile),
+            uid: uid,
+            gid: gid,
             rdev: 0,
             flags: 0,
         }
     }
 
-    fn snapshot_from_sid(&self, sid: usize) -> io::Result<Snapshot> {
-        match try!(self.backup.snapshots()).nth(sid) {
-            Some(s) => Ok(s),
-            None => Err(io::Error::new(io::ErrorKind::NotFound, "Snapshot not found")),
+    /// Returns the content of a file in the status directory.
+    fn status_content(&self, ino: u64) -> Vec<u8> {
+        if ino == self.status.degraded_ino() {
+            let names = self.skipped.iter().map(|sid| self.snapshot_name(*sid)).collect::<Vec<_>>();
+            status::degraded_content(&names)
+        } else if ino == self.status.scrub_report_ino() {
+            self.scrub_report.as_ref().map_or(Vec::new(), |report| report.lock().unwrap().content())
+        } else if ino == self.status.latency_ino() {
+            self.latencies.lock().unwrap().content()
+        } else if ino == self.status.stats_ino() {
+            match self.traffic {
+                Some(ref traffic) => {
+                    status::stats_content(&heap::stats(), Some(&*traffic.lock().unwrap()))
+                }
+                None => status::stats_content(&heap::stats(), None),
+            }
+        } else {
+            status::errors_content()
         }
     }
 
-    #[allow(dead_code)]
-    fn snapshot_from_ino(&self, ino: u64) -> io::Result<Snapshot> {
-        self.snapshot_from_sid(self.snapshots.sid_from_ino(ino))
+    /// Returns attributes for a symbolic link to a snapshot.
+    fn attr_alias(&self, ino: u64) -> FileAttr {
+        let size = self.dirs.alias_target(ino).map_or(0, |t| t.len() as u64);
+        FileAttr {
+            size: size,
+            kind: FileType::Symlink,
+            perm: 0o777,
+            nlink: self.nlink(FileType::Symlink),
+            ..self.attr_dir(ino)
+        }
     }
 
-    fn tree_for_snapshot(&mut self, sid: usize) -> io::Result<(&SnapshotTree, Snapshot)> {
-        // check if already present
-        if self.trees[sid].is_some() {
-            return Ok((self.trees[sid].as_ref().unwrap(),
-                       try!(self.snapshot_from_sid(sid))));
+    /// Returns attributes for a snapshot.
+    fn attr_snapshot(&self, snapshot: &Snapshot, ino: u64, size: u64) -> FileAttr {
+        let ts = snapshot.time();
+        let (uid, gid) = self.owner(Some(0), Some(0));
+        FileAttr {
+            ino: ino,
+            size: size,
+            blocks: size_to_blocks(size),
+            atime: self.atime(ino, ts),
+            mtime: ts,
+            ctime: ts,
+            crtime: ts,
+            kind: FileType::Directory,
+            perm: 0o555,
+            nlink: self.nlink(FileType::Directory),
+            uid: uid,
+            gid: gid,
+            rdev: 0,
+            flags: 0,
         }
+    }
 
-        // build the tree and recurse
-        {
-            let tree = {
-                let ino = self.snapshots.ino_from_sid(sid);
-                let snapshot = try!(self.snapshot_from_sid(sid));
-                try!(SnapshotTree::new(&snapshot, ino, self.last_ino + 1))
-            };
-            let opt_tree = &mut self.trees[sid];
-            // update the last ino
-            if let Some((_, last)) = tree.inodes() {
-                self.last_ino = last;
+    /// Returns the target of a symbolic link, rewritten according to the options if absolute.
+    ///
+    /// Absolute targets are taken as relative to the path the backup was made from, given by the
+    /// added prefix, and lose the stripped prefix. Those outside of either prefix point to no
+    /// entry of the snapshot, and are left as they are.
+    fn rewrite_symlink(&self, sid: usize, link: &Path, target: &Path) -> PathBuf {
+        let mut relative = match target.strip_prefix("/") {
+            Ok(relative) => relative,
+            Err(_) => {
+                return target.to_path_buf();
+            }
+        };
+        for prefix in &[&self.options.add_prefix, &self.options.strip_prefix] {
+            let prefix = prefix.iter().collect::<PathBuf>();
+            relative = match relative.strip_prefix(&prefix) {
+                Ok(relative) => relative,
+                Err(_) => {
+                    return target.to_path_buf();
+                }
+            };
+        }
+        match self.options.rewrite_symlinks {
+            SymlinkRewrite::None => target.to_path_buf(),
+            SymlinkRewrite::Relative => {
+                // up to the snapshot root, from the directory containing the link
+                let depth = link.components()
+                                .filter(|c| match *c {
+                                    Component::Normal(_) => true,
+                                    _ => false,
+                                })
+                                .count() -
+                            self.options.strip_prefix.len();
+                let mut result = iter::repeat("..")
+                                     .take(depth.saturating_sub(1))
+                                     .collect::<PathBuf>();
+                result.push(relative);
+                if result.as_os_str().is_empty() {
+                    result.push(".");
+                }
+                result
+            }
+            SymlinkRewrite::Prefix => {
+                match self.dirs.snapshot_path(sid) {
+                    Some(path) => self.options.mountpoint.join(path).join(relative),
+                    None => target.to_path_buf(),
+                }
+            }
+        }
+    }
+
+    /// Returns the attributes of a snapshot entry, if they are in the cache.
+    fn cached_attr(&self, ino: u64) -> Option<FileAttr> {
+        // the access time may have changed in the meantime
+        self.attrs.get(&ino).map(|attr| FileAttr { atime: self.atime(ino, attr.mtime), ..*attr })
+    }
+
+    /// Keeps the attributes of a snapshot entry, so that its next getattr doesn't need the tree
+    /// and the signatures.
+    ///
+    /// Entries never change while mounted, so there is nothing to invalidate. When the cache is
+    /// full it is emptied, since most stats are for the few entries accessed last.
+    fn cache_attr(&mut self, attr: FileAttr) {
+        if self.attrs.len() >= ATTR_CACHE_SIZE {
+            self.attrs.clear();
+        }
+        self.attrs.insert(attr.ino, attr);
+    }
+
+    /// Returns attributes for an entry.
+    ///
+    /// The size of directories is the number of children they list. In strict mode, fails if some
+    /// of the attributes are not recorded in the signatures.
+    fn attr_entry(&self,
+                  entry: &SigEntry,
+                  ino: u64,
+                  num_children: usize)
+                  -> io::Result<FileAttr> {
+        let ts = entry.mtime();
+        let kind = from_entry_type(entry.entry_type());
+        let strict = self.options.strict;
+        let preserve = match self.options.ownership {
+            Ownership::Preserve => true,
+            Ownership::Fixed { .. } => false,
+        };
+        if strict && preserve && (entry.userid().is_none() || entry.groupid().is_none()) {
+            return Err(missing_metadata("owner"));
+        }
+        let (uid, gid) = self.owner(entry.userid(), entry.groupid());
+        let perm = match entry.mode() {
+            Some(mode) => mode as u16,
+            None if strict => {
+                return Err(missing_metadata("mode"));
+            }
+            None => 0o777,
+        };
+        let size = match entry.size_hint() {
+            // the content of unknown entries can't be meaningful
+            _ if is_unknown(entry.entry_type()) => 0,
+            _ if kind == FileType::Directory => num_children as u64,
+            Some(sh) => sh.1 as u64,
+            None if strict && kind == FileType::RegularFile => {
+                return Err(missing_metadata("size"));
+            }
+            None => 0,
+        };
+        Ok(FileAttr {
+            ino: ino,
+            size: size,
+            blocks: 0,
+            atime: self.atime(ino, ts),
+            mtime: ts,
+            ctime: ts,
+            crtime: ts,
+            kind: kind,
+            perm: perm,
+            nlink: self.nlink(kind),
+            uid: uid,
+            gid: gid,
+            rdev: 0,
+            flags: 0,
+        })
+    }
+
+    fn snapshot_from_sid(&self, sid: usize) -> io::Result<Snapshot> {
+        // preloaded snapshots come with their signatures already loaded
+        let backup = match self.preloaded_sids.get(&sid) {
+            Some(&index) => &self.preloaded[index],
+            None => &self.backup,
+        };
+        match try!(backup.snapshots()).nth(sid) {
+            Some(s) => Ok(s),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "Snapshot not found")),
+        }
+    }
+
+    /// Returns the directory name of a snapshot, for log messages.
+    fn snapshot_name(&self, sid: usize) -> String {
+        self.snapshot_from_sid(sid).map_or(format!("#{}", sid), |s| time_to_path(s.time()))
+    }
+
+    /// Returns attributes for a special entry.
+    fn attr_special(&mut self, node: SpecialNode) -> io::Result<FileAttr> {
+        let ino = self.special.ino_from_node(node);
+        let (kind, perm, size) = if node.kind.is_dir() {
+            (FileType::Directory, 0o555, 0)
+        } else {
+            (FileType::RegularFile, 0o444, try!(self.special_size(node)))
+        };
+        let ts = try!(self.snapshot_from_sid(node.sid)).time();
+        let (uid, gid) = self.owner(Some(0), Some(0));
+        Ok(FileAttr {
+            ino: ino,
+            size: size,
+            blocks: size_to_blocks(size),
+            atime: self.atime(ino, ts),
+            mtime: ts,
+            ctime: ts,
+            crtime: ts,
+            kind: kind,
+            perm: perm,
+            nlink: self.nlink(kind),
+            uid: uid,
+            gid: gid,
+            rdev: 0,
+            flags: 0,
+        })
+    }
+
+    /// Returns how many children of an entry are listed, which is none for anything but
+    /// directories.
+    fn shown_children(&self,
+                      node: &NodeEntry,
+                      entry: &SigEntry,
+                      entries: SnapshotEntries)
+                      -> usize {
+        if entry.entry_type() != EntryType::Dir {
+            return 0;
+        }
+        node.children(entries)
+            .filter(|child| child.path().is_some() && !self.is_hidden(child.as_signature()))
+            .count()
+    }
+
+    /// Returns whether an entry is left out because of its unknown type.
+    fn is_hidden(&self, entry: &SigEntry) -> bool {
+        self.options.unknown_entries == UnknownEntries::Hide && is_unknown(entry.entry_type())
+    }
+
+    /// Returns the raw type of a backup entry, if it is unknown and it can't be opened.
+    fn refused_type(&self, ino: u64) -> Option<u8> {
+        if self.options.unknown_entries != UnknownEntries::Fail {
+            return None;
+        }
+        let (tree, sid) = try_opt!(self.find_tree_with_ino(ino));
+        let node = try_opt!(tree.find_node(ino));
+        let snapshot = try_opt!(self.snapshot_from_sid(sid).ok());
+        let entries = try_opt!(snapshot.entries().ok());
+        match node.as_path_entry(entries.as_signature()).as_signature().entry_type() {
+            EntryType::Unknown(raw) => Some(raw),
+            _ => None,
+        }
+    }
+
+    /// Records an access to an inode, if access times are tracked.
+    fn accessed(&mut self, ino: u64) {
+        if self.options.track_atime {
+            self.access_times.insert(ino, time::get_time());
+        }
+    }
+
+    /// Returns the access time to be reported for an inode, `default` if it wasn't accessed.
+    fn atime(&self, ino: u64, default: Timespec) -> Timespec {
+        self.access_times.get(&ino).cloned().unwrap_or(default)
+    }
+
+    /// Returns the number of links to be reported for an entry of the given type.
+    ///
+    /// Zero tells the tools relying on the number of subdirectories not to, but in strict mode the
+    /// minimum values allowed by POSIX are used.
+    fn nlink(&self, kind: FileType) -> u32 {
+        match kind {
+            _ if !self.options.strict => 0,
+            FileType::Directory => 2,
+            _ => 1,
+        }
+    }
+
+    /// Returns the error for an operation that is not possible on an entry, `EINVAL` in strict
+    /// mode and `ENOSYS` otherwise.
+    fn unsupported(&self) -> c_int {
+        if self.options.strict {
+            EINVAL
+        } else {
+            ENOSYS
+        }
+    }
+
+    /// Returns the user and group ids to be reported for an entry with the given ids.
+    fn owner(&self, uid: Option<u32>, gid: Option<u32>) -> (u32, u32) {
+        match self.options.ownership {
+            Ownership::Preserve => (uid.unwrap_or(100), gid.unwrap_or(100)),
+            Ownership::Fixed { uid, gid } => (uid, gid),
+        }
+    }
+
+    /// Returns the special files of a snapshot, along with their names.
+    fn special_children(&self, sid: usize) -> io::Result<Vec<(SpecialNode, String)>> {
+        let mut result = Vec::new();
+        for kind in &[SpecialKind::Manifest, SpecialKind::Signature] {
+            let node = SpecialNode {
+                sid: sid,
+                kind: *kind,
+            };
+            if let Some(name) = try!(self.special_file_name(node)) {
+                result.push((node, name));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the name of the backend file exposed by a special entry, if present.
+    fn special_file_name(&self, node: SpecialNode) -> io::Result<Option<String>> {
+        let snapshot = try!(self.snapshot_from_sid(node.sid));
+        let name = match node.kind {
+            SpecialKind::DuplicityDir | SpecialKind::Info => None,
+            SpecialKind::Manifest => {
+                let path = snapshot.as_backup_set().manifest_path();
+                if path.is_empty() {
+                    None
+                } else {
+                    Some(path.to_owned())
+                }
+            }
+            SpecialKind::Signature => {
+                let snapshots = try!(self.backup.snapshots());
+                let time = snapshot.time();
+                let name = snapshots.as_collections()
+                                    .signature_chains()
+                                    .flat_map(|chain| {
+                                        iter::once(chain.full_signature())
+                                            .chain(chain.inc_signatures())
+                                    })
+                                    .find(|sig| sig.time == time)
+                                    .map(|sig| sig.file_name.clone());
+                name
+            }
+        };
+        Ok(name)
+    }
+
+    /// Returns the content of a special file.
+    fn special_content(&self, node: SpecialNode) -> error::Result<Vec<u8>> {
+        if node.kind == SpecialKind::Info {
+            return self.snapshot_info(node.sid).map(String::into_bytes).map_err(|e| {
+                Error::ReadSignatures {
+                    snapshot: self.snapshot_name(node.sid),
+                    cause: e,
+                }
+            });
+        }
+        let name = try!(self.special_file_name(node).map_err(|e| {
+            Error::ListBackendFiles {
+                snapshot: self.snapshot_name(node.sid),
+                cause: e,
+            }
+        }));
+        match name {
+            Some(name) => self.read_backend_file(&name),
+            None => {
+                Err(Error::MissingInode {
+                    what: "backend file",
+                    ino: self.special.ino_from_node(node),
+                })
+            }
+        }
+    }
+
+    /// Returns the JSON description of a snapshot.
+    fn snapshot_info(&self, sid: usize) -> io::Result<String> {
+        let snapshot = try!(self.snapshot_from_sid(sid));
+        let num_entries = try!(snapshot.entries()).as_signature().count();
+        let snapshots = try!(self.backup.snapshots());
+        Ok(info::snapshot_info(&snapshot, snapshots.as_collections(), sid, num_entries))
+    }
+
+    /// Returns the size of a special file, reading it from the backend the first time.
+    fn special_size(&mut self, node: SpecialNode) -> io::Result<u64> {
+        let ino = self.special.ino_from_node(node);
+        if let Some(size) = self.special_sizes.get(&ino) {
+            return Ok(*size);
+        }
+        let size = if node.kind == SpecialKind::Info {
+            try!(self.snapshot_info(node.sid)).len() as u64
+        } else {
+            match try!(self.special_file_name(node)) {
+                Some(name) => {
+                    try!(self.backend
+                             .open_file(Path::new(&name))
+                             .and_then(|mut file| io::copy(&mut file, &mut io::sink()))
+                             .map_err(|e| {
+                                 io::Error::new(e.kind(), format!("backend file {}: {}", name, e))
+                             }))
+                }
+                None => 0,
+            }
+        };
+        self.special_sizes.insert(ino, size);
+        Ok(size)
+    }
+
+    /// Reads the whole content of a backend file.
+    fn read_backend_file(&self, name: &str) -> error::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        try!(self.backend
+                 .open_file(Path::new(name))
+                 .and_then(|mut file| file.read_to_end(&mut content))
+                 .map_err(|e| {
+                     Error::ReadBackendFile {
+                         name: name.to_owned(),
+                         cause: e,
+                     }
+                 }));
+        Ok(content)
+    }
+
+    /// Returns the estimated size in bytes of the files backed up in a snapshot, or zero if its
+    /// signatures haven't been loaded yet.
+    ///
+    /// Listing the snapshots must not download their signatures, so the size is known only after
+    /// the snapshot is browsed.
+    fn loaded_size(&self, sid: usize) -> u64 {
+        self.sizes[sid].unwrap_or(0)
+    }
+
+    /// Returns how long the kernel can cache the attributes of a snapshot directory.
+    ///
+    /// They are not cached until the size is known, so that it shows as soon as the snapshot is
+    /// browsed instead of staying zero.
+    fn snapshot_ttl(&self, sid: usize) -> Timespec {
+        if self.sizes[sid].is_some() {
+            self.ttl
+        } else {
+            Timespec::new(0, 0)
+        }
+    }
+
+    /// Returns an estimate in bytes of the files backed up in a snapshot.
+    ///
+    /// Duplicity manifests record no sizes, only the paths and the hashes of the volumes, so this
+    /// is the sum of the upper bounds of the file sizes hinted by the signatures, and not the
+    /// size of the volumes. It is cached for subsequent calls.
+    fn estimated_size(&mut self, sid: usize) -> io::Result<u64> {
+        if let Some(size) = self.sizes[sid] {
+            return Ok(size);
+        }
+        let size = {
+            let snapshot = try!(self.snapshot_from_sid(sid));
+            let entries = try!(snapshot.entries());
+            entries.as_signature()
+                   .filter(|entry| entry.entry_type() == EntryType::File)
+                   .fold(0, |acc, entry| acc + entry.size_hint().map_or(0, |sh| sh.1 as u64))
+        };
+        self.sizes[sid] = Some(size);
+        Ok(size)
+    }
+
+    #[allow(dead_code)]
+    fn snapshot_from_ino(&self, ino: u64) -> io::Result<Snapshot> {
+        self.snapshot_from_sid(self.snapshots.sid_from_ino(ino))
+    }
+
+    fn tree_for_snapshot(&mut self, sid: usize) -> io::Result<(&SnapshotTree, Snapshot)> {
+        // check if already present
+        if self.trees[sid].is_some() {
+            return Ok((self.trees[sid].as_ref().unwrap(),
+                       try!(self.snapshot_from_sid(sid))));
+        }
+
+        if self.is_left_out(sid) {
+            return Err(not_found(format!("snapshot {} is left out", self.snapshot_name(sid))));
+        }
+
+        if self.inconsistent.contains(&sid) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, InconsistentTree));
+        }
+
+        // build the tree and recurse
+        let tree = match self.build_tree(sid) {
+            Ok(tree) => tree,
+            Err(e) => {
+                if e.get_ref().map_or(false, |inner| inner.is::<InconsistentTree>()) {
+                    self.inconsistent.insert(sid);
+                }
+                if self.options.best_effort {
+                    self.skip_snapshot(sid, &e);
+                }
+                return Err(e);
+            }
+        };
+        try!(self.add_tree(sid, tree));
+        self.tree_for_snapshot(sid)
+    }
+
+    /// Loads the signatures of a snapshot and builds its tree, numbered from the next free inode.
+    fn build_tree(&self, sid: usize) -> io::Result<SnapshotTree> {
+        let snapshot = try!(self.snapshot_from_sid(sid));
+        load_tree(&snapshot,
+                  &self.options,
+                  self.snapshots.ino_from_sid(sid),
+                  self.inodes.next_ino())
+    }
+
+    /// Adds the tree of a snapshot, claiming its inodes.
+    fn add_tree(&mut self, sid: usize, tree: SnapshotTree) -> io::Result<()> {
+        if let Some((_, last)) = tree.inodes() {
+            self.inodes.claim(last);
+        }
+        self.inode_map.lock().unwrap().trees.insert(sid, tree.inodes());
+        self.trees[sid] = Some(tree);
+        // the signatures are loaded now, so the size comes at no additional cost
+        self.estimated_size(sid).map(|_| ())
+    }
+
+    /// Returns whether a snapshot is missing from the root, because it can't be loaded or it is
+    /// still being uploaded.
+    fn is_left_out(&self, sid: usize) -> bool {
+        self.skipped.contains(&sid) || self.pending.contains(&sid)
+    }
+
+    /// Leaves out a snapshot that can't be loaded, in best-effort mode.
+    fn skip_snapshot(&mut self, sid: usize, e: &io::Error) {
+        if self.skipped.is_empty() {
+            warn!("Showing a partial view of the backup: the snapshots that can't be loaded are \
+                   left out, and listed in {}/{}",
+                  STATUS_DIR,
+                  DEGRADED_FILE);
+        }
+        info!("Leaving out snapshot {}: {}", self.snapshot_name(sid), e);
+        self.skipped.insert(sid);
+    }
+
+    /// Returns the inode of the snapshot shown in place of the root directory, if the layout has
+    /// one and the inode is the root, or the inode itself otherwise.
+    ///
+    /// The status directory is still looked up in the root, and listed along with the snapshot.
+    fn resolve_root(&self, ino: u64) -> u64 {
+        match self.dirs.root_snapshot() {
+            Some(sid) if ino == 1 => self.snapshots.ino_from_sid(sid),
+            _ => ino,
+        }
+    }
+
+    /// Returns whether an inode belongs to a snapshot that is no longer in the backend.
+    ///
+    /// This is checked in volatile mode only, by looking for the snapshot manifest.
+    fn is_stale(&mut self, ino: u64) -> bool {
+        if !self.options.volatile {
+            return false;
+        }
+        let sid = if self.snapshots.is_snapshot(ino) {
+            self.snapshots.sid_from_ino(ino)
+        } else if let Some(node) = self.special.node_from_ino(ino) {
+            node.sid
+        } else {
+            match self.find_tree_with_ino(ino) {
+                Some((_, sid)) => sid,
+                None => {
+                    return false;
+                }
+            }
+        };
+        let manifest = match self.snapshot_from_sid(sid) {
+            Ok(snapshot) => PathBuf::from(snapshot.as_backup_set().manifest_path()),
+            Err(_) => {
+                return true;
+            }
+        };
+        if manifest.as_os_str().is_empty() {
+            // incomplete snapshots have nothing to check against
+            return false;
+        }
+        match self.live_files() {
+            Ok(files) => {
+                let stale = !files.contains(&manifest);
+                if stale {
+                    info!("Snapshot {} disappeared from the backend", sid);
+                }
+                stale
+            }
+            Err(e) => {
+                error!("Can't list backend files: {}", e);
+                false
             }
-            *opt_tree = Some(tree);
         }
-        self.tree_for_snapshot(sid)
+    }
+
+    /// Returns the files present in the backend, listing them again if the last listing is old.
+    fn live_files(&mut self) -> io::Result<&HashSet<PathBuf>> {
+        let now = time::get_time();
+        let expired = self.live_files.as_ref().map_or(true, |live| {
+            (now - live.0).num_seconds() >= VOLATILE_LISTING_AGE
+        });
+        if expired {
+            let files = try!(self.backend.file_names())
+                            .into_iter()
+                            .map(|name| name.as_ref().to_path_buf())
+                            .collect();
+            self.live_files = Some((now, files));
+        }
+        Ok(&self.live_files.as_ref().unwrap().1)
     }
 
     /// Returns the tree having that inode and the corresponding snapshot id.
@@ -361,88 +1823,475 @@ impl<B: Backend> RuplicityFs<B> {
     }
 }
 
+impl<B> Drop for RuplicityFs<B> {
+    fn drop(&mut self) {
+        if let Some(ref mut on_unmount) = self.on_unmount {
+            on_unmount();
+        }
+    }
+}
+
 impl<B: Backend> Filesystem for RuplicityFs<B> {
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        if ino == 1 {
-            self.getattr_root(reply);
-        } else if self.snapshots.is_snapshot(ino) {
-            self.getattr_snapshot(ino, reply);
+    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        log_request!(self, req, "getattr");
+        self.handle_getattr(ino, reply);
+    }
+
+    fn readdir(&mut self, req: &Request, ino: u64, fh: u64, offset: u64, reply: ReplyDirectory) {
+        log_request!(self, req, "readdir");
+        self.handle_readdir(ino, fh, offset, reply);
+    }
+
+    fn opendir(&mut self, req: &Request, ino: u64, _flags: u32, reply: ReplyOpen) {
+        log_request!(self, req, "opendir");
+        self.handle_opendir(ino, reply);
+    }
+
+    fn releasedir(&mut self, req: &Request, _ino: u64, fh: u64, _flags: u32, reply: ReplyEmpty) {
+        log_request!(self, req, "releasedir");
+        self.handle_releasedir(fh, reply);
+    }
+
+    fn lookup(&mut self, req: &Request, parent: u64, name: &Path, reply: ReplyEntry) {
+        log_request!(self, req, "lookup");
+        self.handle_lookup(parent, name, reply);
+    }
+
+    fn readlink(&mut self, req: &Request, ino: u64, reply: ReplyData) {
+        log_request!(self, req, "readlink");
+        self.handle_readlink(ino, reply);
+    }
+
+    fn open(&mut self, req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
+        log_request!(self, req, "open");
+        self.handle_open(ino, flags, reply);
+    }
+
+    fn read(&mut self,
+            req: &Request,
+            ino: u64,
+            fh: u64,
+            offset: u64,
+            size: u32,
+            reply: ReplyData) {
+        log_request!(self, req, "read");
+        self.handle_read(ino, fh, offset, size, reply);
+    }
+
+    fn release(&mut self,
+               req: &Request,
+               _ino: u64,
+               fh: u64,
+               _flags: u32,
+               _lock_owner: u64,
+               _flush: bool,
+               reply: ReplyEmpty) {
+        log_request!(self, req, "release");
+        self.handle_release(fh, reply);
+    }
+
+    fn setattr(&mut self,
+               req: &Request,
+               ino: u64,
+               _mode: Option<u32>,
+               _uid: Option<u32>,
+               _gid: Option<u32>,
+               size: Option<u64>,
+               _atime: Option<Timespec>,
+               _mtime: Option<Timespec>,
+               _fh: Option<u64>,
+               _crtime: Option<Timespec>,
+               _chgtime: Option<Timespec>,
+               _bkuptime: Option<Timespec>,
+               _flags: Option<u32>,
+               reply: ReplyAttr) {
+        log_request!(self, req, "setattr");
+        if size.is_some() {
+            refuse_read_only("truncate", ino);
         } else {
-            self.getattr_entry(ino, reply);
+            refuse_read_only("setattr", ino);
         }
+        reply.error(EROFS);
     }
 
-    fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: u64, reply: ReplyDirectory) {
-        if ino == 1 {
-            self.readdir_root(offset, reply);
-        } else if self.snapshots.is_snapshot(ino) {
-            self.readdir_snapshot(ino, offset, reply);
+    fn write(&mut self,
+             req: &Request,
+             ino: u64,
+             _fh: u64,
+             _offset: u64,
+             _data: &[u8],
+             _flags: u32,
+             reply: ReplyWrite) {
+        log_request!(self, req, "write");
+        refuse_read_only("write", ino);
+        reply.error(EROFS);
+    }
+
+    fn mknod(&mut self,
+             req: &Request,
+             parent: u64,
+             _name: &Path,
+             _mode: u32,
+             _rdev: u32,
+             reply: ReplyEntry) {
+        log_request!(self, req, "mknod");
+        refuse_read_only("mknod", parent);
+        reply.error(EROFS);
+    }
+
+    fn mkdir(&mut self, req: &Request, parent: u64, _name: &Path, _mode: u32, reply: ReplyEntry) {
+        log_request!(self, req, "mkdir");
+        refuse_read_only("mkdir", parent);
+        reply.error(EROFS);
+    }
+
+    fn unlink(&mut self, req: &Request, parent: u64, _name: &Path, reply: ReplyEmpty) {
+        log_request!(self, req, "unlink");
+        refuse_read_only("unlink", parent);
+        reply.error(EROFS);
+    }
+
+    fn rmdir(&mut self, req: &Request, parent: u64, _name: &Path, reply: ReplyEmpty) {
+        log_request!(self, req, "rmdir");
+        refuse_read_only("rmdir", parent);
+        reply.error(EROFS);
+    }
+
+    fn symlink(&mut self,
+               req: &Request,
+               parent: u64,
+               _name: &Path,
+               _link: &Path,
+               reply: ReplyEntry) {
+        log_request!(self, req, "symlink");
+        refuse_read_only("symlink", parent);
+        reply.error(EROFS);
+    }
+
+    fn rename(&mut self,
+              req: &Request,
+              parent: u64,
+              _name: &Path,
+              _newparent: u64,
+              _newname: &Path,
+              reply: ReplyEmpty) {
+        log_request!(self, req, "rename");
+        refuse_read_only("rename", parent);
+        reply.error(EROFS);
+    }
+
+    fn link(&mut self,
+            req: &Request,
+            ino: u64,
+            _newparent: u64,
+            _newname: &Path,
+            reply: ReplyEntry) {
+        log_request!(self, req, "link");
+        refuse_read_only("link", ino);
+        reply.error(EROFS);
+    }
+
+    fn create(&mut self,
+              req: &Request,
+              parent: u64,
+              _name: &Path,
+              _mode: u32,
+              _flags: u32,
+              reply: ReplyCreate) {
+        log_request!(self, req, "create");
+        refuse_read_only("create", parent);
+        reply.error(EROFS);
+    }
+
+    // getxattr and listxattr are left to the default ENOSYS reply: fuse 0.2 doesn't pass the size
+    // of the caller's buffer to getxattr, and gives listxattr only a ReplyEmpty, so neither a
+    // value nor a list of names can be returned the way the kernel expects. The restore cost of
+    // a file is reported by the info file of its snapshot instead.
+    fn setxattr(&mut self,
+                req: &Request,
+                ino: u64,
+                _name: &OsStr,
+                _value: &[u8],
+                _flags: u32,
+                _position: u32,
+                reply: ReplyEmpty) {
+        log_request!(self, req, "setxattr");
+        refuse_read_only("setxattr", ino);
+        reply.error(EROFS);
+    }
+
+    fn removexattr(&mut self, req: &Request, ino: u64, _name: &OsStr, reply: ReplyEmpty) {
+        log_request!(self, req, "removexattr");
+        refuse_read_only("removexattr", ino);
+        reply.error(EROFS);
+    }
+
+    fn flush(&mut self, req: &Request, _ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        log_request!(self, req, "flush");
+        // nothing is ever written, so there is nothing to flush
+        reply.ok();
+    }
+
+    fn fsync(&mut self, req: &Request, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        log_request!(self, req, "fsync");
+        reply.ok();
+    }
+
+    fn getlk(&mut self,
+             req: &Request,
+             _ino: u64,
+             _fh: u64,
+             _lock_owner: u64,
+             start: u64,
+             end: u64,
+             _typ: u32,
+             pid: u32,
+             reply: ReplyLock) {
+        log_request!(self, req, "getlk");
+        // the filesystem is immutable, so locks never conflict
+        reply.locked(start, end, F_UNLCK as u32, pid);
+    }
+
+    fn setlk(&mut self,
+             req: &Request,
+             ino: u64,
+             _fh: u64,
+             _lock_owner: u64,
+             _start: u64,
+             _end: u64,
+             typ: u32,
+             _pid: u32,
+             _sleep: bool,
+             reply: ReplyEmpty) {
+        log_request!(self, req, "setlk");
+        if typ == F_RDLCK as u32 || typ == F_UNLCK as u32 {
+            reply.ok();
         } else {
-            self.readdir_entry(ino, offset, reply);
+            reply_error!(reply, EBADF, "Refusing write lock on read-only ino {}", ino);
         }
     }
+}
 
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &Path, reply: ReplyEntry) {
-        if parent == 1 {
-            self.lookup_snapshot(name, reply);
-        } else {
-            self.lookup_entry(parent, name, reply);
+
+impl FromStr for Preload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Preload::All),
+            "latest" => Ok(Preload::Latest),
+            _ => Err(format!("unknown preload '{}', expected 'all' or 'latest'", s)),
         }
     }
+}
 
-    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
-        self.readlink_entry(ino, reply);
+impl FromStr for SymlinkRewrite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SymlinkRewrite::None),
+            "relative" => Ok(SymlinkRewrite::Relative),
+            "prefix" => Ok(SymlinkRewrite::Prefix),
+            _ => {
+                Err(format!("unknown rewrite '{}', expected 'none', 'relative' or 'prefix'",
+                            s))
+            }
+        }
     }
 }
 
+impl FromStr for UnknownEntries {
+    type Err = String;
 
-impl SnapshotsInos {
-    /// Creates a new Filesystem instance for a duplicity backup.
-    pub fn new<B: Backend>(backup: &Backup<B>) -> io::Result<Self> {
-        let mut spaths = HashMap::new();
-        for (count, snapshot) in try!(backup.snapshots()).enumerate() {
-            let path = time_to_path(snapshot.time());
-            spaths.insert(path, count);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hide" => Ok(UnknownEntries::Hide),
+            "show" => Ok(UnknownEntries::Show),
+            "fail" => Ok(UnknownEntries::Fail),
+            _ => Err(format!("unknown policy '{}', expected 'hide', 'show' or 'fail'", s)),
         }
-        Ok(SnapshotsInos { paths: spaths })
     }
+}
 
-    pub fn len(&self) -> usize {
-        self.paths.len()
+
+impl DirEntry {
+    fn dir(ino: u64, name: &str) -> Self {
+        DirEntry {
+            ino: ino,
+            kind: FileType::Directory,
+            name: PathBuf::from(name),
+        }
     }
+}
 
-    #[allow(dead_code)]
-    pub fn is_empty(&self) -> bool {
-        self.paths.is_empty()
+
+impl SnapshotsInos {
+    pub fn new() -> Self {
+        SnapshotsInos {
+            inos: Vec::new(),
+            sids: HashMap::new(),
+        }
     }
 
-    pub fn sid_from_path(&self, name: &Path) -> Option<usize> {
-        self.paths.get(name.to_str().unwrap()).map(Clone::clone)
+    /// Reserves the inode of the next snapshot.
+    pub fn push(&mut self, allocator: &mut InodeAllocator) {
+        let ino = allocator.allocate(1);
+        self.sids.insert(ino, self.inos.len());
+        self.inos.push(ino);
     }
 
-    pub fn sid_from_ino(&self, ino: u64) -> usize {
-        assert!(ino >= 2);
-        ino as usize - 2
+    pub fn len(&self) -> usize {
+        self.inos.len()
     }
 
-    pub fn ino_from_sid(&self, sid: usize) -> u64 {
-        sid as u64 + 2
+    pub fn sid_from_ino(&self, ino: u64) -> usize {
+        *self.sids.get(&ino).expect("not a snapshot inode")
     }
 
-    pub fn last_ino(&self) -> u64 {
-        self.ino_from_sid(self.paths.len())
+    pub fn ino_from_sid(&self, sid: usize) -> u64 {
+        self.inos[sid]
     }
 
     /// Returns whether an inode is a snapshot.
     pub fn is_snapshot(&self, ino: u64) -> bool {
-        ino >= 2 && ino < self.ino_from_sid(self.paths.len())
+        self.sids.contains_key(&ino)
+    }
+}
+
+
+impl fmt::Display for InconsistentTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("inconsistent snapshot tree")
+    }
+}
+
+impl ::std::error::Error for InconsistentTree {}
+
+
+/// Returns the directory name of a snapshot taken at the given time.
+///
+/// The name is in local time, followed by the UTC offset in the hour repeated when DST ends, to
+/// tell apart the snapshots taken at the same wall clock time.
+pub fn time_to_path(time: Timespec) -> String {
+    time_utils::format_local_unambiguous(time, PATH_FORMAT)
+}
+
+/// Returns the times a snapshot directory name may refer to, to the second.
+///
+/// A name in the repeated hour of DST without an offset refers to two times.
+pub fn path_to_times(name: &str) -> Vec<Timespec> {
+    let with_offset = format!("{}%z", PATH_FORMAT);
+    if let Ok(time) = DateTime::parse_from_str(name, &with_offset) {
+        return vec![Timespec::new(time.timestamp(), 0)];
+    }
+    match NaiveDateTime::parse_from_str(name, PATH_FORMAT) {
+        Ok(time) => time_utils::from_local(&time),
+        Err(_) => Vec::new(),
     }
 }
 
+/// Loads the signatures of a snapshot and builds its tree, numbered from `first_ino`.
+fn load_tree(snapshot: &Snapshot,
+             options: &FsOptions,
+             ino: u64,
+             first_ino: u64)
+             -> io::Result<SnapshotTree> {
+    let _span = Span::new("fs", "load snapshot tree");
+    // load the signatures first, so they are not accounted to the tree
+    try!(heap::account(Usage::Signatures, || snapshot.entries().map(|_| ())));
+    let tree = try!(heap::account(Usage::Trees, || {
+        SnapshotTree::with_prefix(snapshot, &options.strip_prefix, ino, first_ino)
+    }));
+    if options.check_tree {
+        let entries = try!(snapshot.entries());
+        try!(tree.check(entries.as_signature()).map_err(|e| {
+            error!("Inconsistent tree for snapshot {}: {}",
+                   time_to_path(snapshot.time()),
+                   e);
+            io::Error::new(io::ErrorKind::InvalidData, InconsistentTree)
+        }));
+    }
+    Ok(tree)
+}
 
-fn time_to_path(time: Timespec) -> String {
-    let time = time::at(time);
-    time::strftime("%Y-%m-%d_%H-%M-%S", &time).unwrap()
+/// Loads the chains of snapshots taken from a queue until it is empty, from a backup of its own.
+///
+/// The trees are numbered from inode 1, and have to be shifted to the inodes they get.
+/// `times` are the times of all the snapshots, to check that the backup opened here has the
+/// same ones.
+fn load_chains<B, F>(queue: &Mutex<Vec<Vec<usize>>>,
+                     open: &F,
+                     options: &FsOptions,
+                     snapshots: &SnapshotsInos,
+                     times: &[Timespec])
+                     -> io::Result<(Backup<B>, Vec<(usize, io::Result<SnapshotTree>)>)>
+    where B: Backend,
+          F: Fn() -> io::Result<B>
+{
+    let backup = try!(open().and_then(Backup::new));
+    let mut trees = Vec::new();
+    loop {
+        let chain = match queue.lock().unwrap().pop() {
+            Some(chain) => chain,
+            None => break,
+        };
+        for sid in chain {
+            let tree = backup.snapshots()
+                             .and_then(|mut all| {
+                                 all.nth(sid)
+                                    .filter(|snapshot| snapshot.time() == times[sid])
+                                    .ok_or_else(|| {
+                                        not_found(format!("snapshot {} changed while loading",
+                                                          time_to_path(times[sid])))
+                                    })
+                             })
+                             .and_then(|snapshot| {
+                                 load_tree(&snapshot, options, snapshots.ino_from_sid(sid), 1)
+                             });
+            trees.push((sid, tree));
+        }
+    }
+    Ok((backup, trees))
+}
+
+fn missing_metadata(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("the {} is not recorded in the signatures", what))
+}
+
+/// Returns the error code replied for a failure of the given kind.
+pub fn errno(kind: io::ErrorKind) -> c_int {
+    match kind {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::TimedOut => ETIMEDOUT,
+        io::ErrorKind::Interrupted => EINTR,
+        _ => EIO,
+    }
+}
+
+fn not_found(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, msg)
+}
+
+/// Logs a refused modification request, recording it along with the other failures.
+fn refuse_read_only(op: &str, ino: u64) {
+    let msg = format!("Refusing {} on ino {}: this is a read-only backup view", op, ino);
+    info!("{}", msg);
+    logger::record_error(EROFS, msg);
+}
+
+/// Returns the number of 512 bytes blocks needed to store `size` bytes.
+fn size_to_blocks(size: u64) -> u64 {
+    (size + 511) / 512
+}
+
+fn is_unknown(et: EntryType) -> bool {
+    match et {
+        EntryType::Unknown(_) => true,
+        _ => false,
+    }
 }
 
 fn from_entry_type(et: EntryType) -> FileType {
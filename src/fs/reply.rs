@@ -0,0 +1,95 @@
+use fuse::{FileAttr, FileType, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
+           ReplyOpen};
+use libc::c_int;
+use time::Timespec;
+
+use std::path::Path;
+
+
+/// A reply to a filesystem operation, that can fail with an error number.
+///
+/// The fuse reply types implement these traits by forwarding to their own methods.
+pub trait ErrorReply {
+    fn error(self, err: c_int);
+}
+
+pub trait AttrReply: ErrorReply {
+    fn attr(self, ttl: &Timespec, attr: &FileAttr);
+}
+
+pub trait EntryReply: ErrorReply {
+    fn entry(self, ttl: &Timespec, attr: &FileAttr, generation: u64);
+}
+
+pub trait DirectoryReply: ErrorReply {
+    /// Adds an entry to the listing, returning true if there's no room for it.
+    fn add(&mut self, ino: u64, offset: u64, kind: FileType, name: &Path) -> bool;
+    fn ok(self);
+}
+
+pub trait OpenReply: ErrorReply {
+    fn opened(self, fh: u64, flags: u32);
+}
+
+pub trait DataReply: ErrorReply {
+    fn data(self, data: &[u8]);
+}
+
+pub trait EmptyReply: ErrorReply {
+    fn ok(self);
+}
+
+
+macro_rules! impl_error_reply(
+    ($($ty:ty),+) => (
+        $(
+            impl ErrorReply for $ty {
+                fn error(self, err: c_int) {
+                    <$ty>::error(self, err)
+                }
+            }
+        )+
+    )
+);
+
+impl_error_reply!(ReplyAttr, ReplyEntry, ReplyDirectory, ReplyOpen, ReplyData, ReplyEmpty);
+
+impl AttrReply for ReplyAttr {
+    fn attr(self, ttl: &Timespec, attr: &FileAttr) {
+        ReplyAttr::attr(self, ttl, attr)
+    }
+}
+
+impl EntryReply for ReplyEntry {
+    fn entry(self, ttl: &Timespec, attr: &FileAttr, generation: u64) {
+        ReplyEntry::entry(self, ttl, attr, generation)
+    }
+}
+
+impl DirectoryReply for ReplyDirectory {
+    fn add(&mut self, ino: u64, offset: u64, kind: FileType, name: &Path) -> bool {
+        ReplyDirectory::add(self, ino, offset, kind, name)
+    }
+
+    fn ok(self) {
+        ReplyDirectory::ok(self)
+    }
+}
+
+impl OpenReply for ReplyOpen {
+    fn opened(self, fh: u64, flags: u32) {
+        ReplyOpen::opened(self, fh, flags)
+    }
+}
+
+impl DataReply for ReplyData {
+    fn data(self, data: &[u8]) {
+        ReplyData::data(self, data)
+    }
+}
+
+impl EmptyReply for ReplyEmpty {
+    fn ok(self) {
+        ReplyEmpty::ok(self)
+    }
+}
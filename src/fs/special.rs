@@ -0,0 +1,95 @@
+use super::inodes::{InodeAllocator, SnapshotBlocks};
+
+/// Name of the directory exposing the raw duplicity files of a snapshot.
+pub const DUPLICITY_DIR: &'static str = ".duplicity";
+
+// Number of inodes reserved for the special entries of each snapshot.
+const INOS_PER_SNAPSHOT: u64 = 4;
+
+
+/// Allocates inodes for synthetic entries that are not part of the backup.
+///
+/// Every snapshot gets a block of inodes, one for each `SpecialKind`.
+#[derive(Debug)]
+pub struct SpecialInos {
+    blocks: SnapshotBlocks,
+}
+
+/// A synthetic entry inside a snapshot.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SpecialNode {
+    /// The id of the snapshot containing the entry.
+    pub sid: usize,
+    /// What the entry represents.
+    pub kind: SpecialKind,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SpecialKind {
+    /// The `.duplicity` directory.
+    DuplicityDir,
+    /// The manifest file of the snapshot, inside `.duplicity`.
+    Manifest,
+    /// The signature file of the snapshot, inside `.duplicity`.
+    Signature,
+    /// The `.info.json` file describing the snapshot.
+    Info,
+}
+
+
+impl SpecialInos {
+    pub fn new() -> Self {
+        SpecialInos { blocks: SnapshotBlocks::new(INOS_PER_SNAPSHOT) }
+    }
+
+    /// Reserves the inodes of the special entries of the next snapshot.
+    pub fn push(&mut self, allocator: &mut InodeAllocator) {
+        self.blocks.push(allocator);
+    }
+
+    /// Returns the first and last inodes reserved for the special entries of a snapshot.
+    pub fn range(&self, sid: usize) -> (u64, u64) {
+        self.blocks.range(sid)
+    }
+
+    /// Returns whether an inode is a special entry.
+    pub fn is_special(&self, ino: u64) -> bool {
+        self.blocks.find(ino).is_some()
+    }
+
+    pub fn node_from_ino(&self, ino: u64) -> Option<SpecialNode> {
+        let (sid, offset) = match self.blocks.find(ino) {
+            Some(found) => found,
+            None => {
+                return None;
+            }
+        };
+        let kind = match offset {
+            0 => SpecialKind::DuplicityDir,
+            1 => SpecialKind::Manifest,
+            2 => SpecialKind::Signature,
+            _ => SpecialKind::Info,
+        };
+        Some(SpecialNode {
+            sid: sid,
+            kind: kind,
+        })
+    }
+
+    pub fn ino_from_node(&self, node: SpecialNode) -> u64 {
+        let offset = match node.kind {
+            SpecialKind::DuplicityDir => 0,
+            SpecialKind::Manifest => 1,
+            SpecialKind::Signature => 2,
+            SpecialKind::Info => 3,
+        };
+        self.blocks.range(node.sid).0 + offset
+    }
+}
+
+
+impl SpecialKind {
+    pub fn is_dir(&self) -> bool {
+        *self == SpecialKind::DuplicityDir
+    }
+}
@@ -0,0 +1,120 @@
+use std::io;
+
+use heap::HeapStats;
+use logger::{self, ErrorRecord};
+use stats::TrafficStats;
+use time_utils;
+use super::inodes::InodeAllocator;
+
+/// Name of the directory in the mount root, reporting the state of the filesystem.
+pub const STATUS_DIR: &'static str = ".ruplicity";
+/// Name of the file listing the most recent errors, inside `STATUS_DIR`.
+pub const ERRORS_FILE: &'static str = "errors";
+/// Name of the file with the latencies of the requests served for each operation, inside
+/// `STATUS_DIR`.
+pub const LATENCY_FILE: &'static str = "latency";
+/// Name of the file listing the snapshots left out because they can't be loaded, inside
+/// `STATUS_DIR`. It is present only if there are any.
+pub const DEGRADED_FILE: &'static str = "DEGRADED";
+/// Name of the file with the results of checking the volumes in the background, inside
+/// `STATUS_DIR`. It is present only while scrubbing.
+pub const SCRUB_REPORT_FILE: &'static str = "scrub-report";
+/// Name of the file with the heap usage of the process and the traffic with the backend, inside
+/// `STATUS_DIR`.
+pub const STATS_FILE: &'static str = "stats";
+
+
+/// The inodes of the status directory and of its files.
+#[derive(Debug)]
+pub struct StatusInos {
+    first_ino: u64,
+}
+
+
+impl StatusInos {
+    pub fn new(allocator: &mut InodeAllocator) -> Self {
+        // the directory, the errors, the degraded, the scrub report, the latency and the stats
+        // files
+        StatusInos { first_ino: allocator.allocate(6) }
+    }
+
+    pub fn dir_ino(&self) -> u64 {
+        self.first_ino
+    }
+
+    pub fn errors_ino(&self) -> u64 {
+        self.first_ino + 1
+    }
+
+    pub fn degraded_ino(&self) -> u64 {
+        self.first_ino + 2
+    }
+
+    pub fn scrub_report_ino(&self) -> u64 {
+        self.first_ino + 3
+    }
+
+    pub fn latency_ino(&self) -> u64 {
+        self.first_ino + 4
+    }
+
+    pub fn stats_ino(&self) -> u64 {
+        self.first_ino + 5
+    }
+
+    /// Returns the last inode reserved for the status entries.
+    pub fn last_ino(&self) -> u64 {
+        self.stats_ino()
+    }
+
+    /// Returns whether an inode is one of the status entries.
+    pub fn is_status(&self, ino: u64) -> bool {
+        ino >= self.first_ino && ino <= self.last_ino()
+    }
+}
+
+
+/// Returns the content of the errors file, one line for each of the most recent errors.
+///
+/// Every line starts with the request identifier found in the corresponding log message.
+pub fn errors_content() -> Vec<u8> {
+    let mut result = String::new();
+    for record in logger::recent_errors() {
+        result.push_str(&format_error(&record));
+        result.push('\n');
+    }
+    result.into_bytes()
+}
+
+/// Returns the content of the stats file, with the heap usage split by what it is used for, and
+/// the data read from the backend for each file if it is counted.
+pub fn stats_content(heap: &HeapStats, traffic: Option<&TrafficStats>) -> Vec<u8> {
+    let mut result = format!("heap: {}\n", heap);
+    if let Some(traffic) = traffic {
+        result.push_str(&format!("backend: {}", traffic));
+    }
+    result.into_bytes()
+}
+
+/// Returns the content of the degraded file, the names of the snapshots left out, one per line.
+pub fn degraded_content(names: &[String]) -> Vec<u8> {
+    let mut result = String::new();
+    for name in names {
+        result.push_str(name);
+        result.push('\n');
+    }
+    result.into_bytes()
+}
+
+fn format_error(record: &ErrorRecord) -> String {
+    let ts = time_utils::local(record.time).format("%Y-%m-%d %H:%M:%S");
+    let request = match record.request {
+        Some((id, op)) => format!("#{} {}", id, op),
+        None => "-".to_owned(),
+    };
+    format!("{} {} {}: {}",
+            ts,
+            request,
+            io::Error::from_raw_os_error(record.errno),
+            record.message)
+}
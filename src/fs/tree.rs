@@ -1,12 +1,15 @@
 use std::cmp::Ordering;
 use std::io;
 use std::iter::Peekable;
-use std::path::{Component, Path};
+use std::path::{Path, PathBuf};
+use std::ptr;
 use std::slice;
 
 use ruplicity::Snapshot;
 use ruplicity::signatures::{Entry as SigEntry, SnapshotEntries};
 
+use path_utils::{PathStyle, backup_components};
+
 
 #[derive(Debug)]
 pub struct SnapshotTree {
@@ -15,6 +18,9 @@ pub struct SnapshotTree {
     /// The root node is not used; only its children are.
     root: TreeNode,
     snapshot_ino: u64,
+    style: PathStyle,
+    /// The number of path components stripped from the entries.
+    depth: usize,
 }
 
 pub struct ChildrenIter<'a, 'b> {
@@ -22,18 +28,21 @@ pub struct ChildrenIter<'a, 'b> {
     entry_it: SnapshotEntries<'b>,
     curr_index: usize,
     path_depth: usize,
+    style: PathStyle,
 }
 
 pub struct PathEntry<'a, 'b> {
     node: &'a TreeNode,
     entry: SigEntry<'b>,
     depth: usize,
+    style: PathStyle,
 }
 
 pub struct NodeEntry<'a> {
     node: &'a TreeNode,
     depth: usize,
     parent_ino: u64,
+    style: PathStyle,
 }
 
 
@@ -52,9 +61,34 @@ struct TreeNode {
 
 impl SnapshotTree {
     pub fn new(snapshot: &Snapshot, snapshot_ino: u64, first_ino: u64) -> io::Result<Self> {
+        Self::with_prefix(snapshot, &[], snapshot_ino, first_ino)
+    }
+
+    /// Builds the tree of the entries below a directory, which takes the place of the snapshot
+    /// root.
+    ///
+    /// The tree is empty if the snapshot doesn't contain the directory.
+    pub fn with_prefix(snapshot: &Snapshot,
+                       prefix: &[String],
+                       snapshot_ino: u64,
+                       first_ino: u64)
+                       -> io::Result<Self> {
         let entries = try!(snapshot.entries());
+        let style = PathStyle::detect(|| entries.as_signature().map(|entry| entry.path()));
         let mut entries = entries.as_signature().peekable();
-        let root = match TreeNode::new(0, 0, first_ino - 1, &mut entries) {
+        let mut index = 0;
+        if !prefix.is_empty() {
+            // parents come before their contents, so everything before the prefix is outside it
+            loop {
+                match entries.peek() {
+                    Some(entry) if !has_components(entry.path(), prefix, style) => {}
+                    _ => break,
+                }
+                entries.next();
+                index += 1;
+            }
+        }
+        let root = match TreeNode::new(prefix.len(), index, first_ino - 1, style, &mut entries) {
             Some(node) => node,
             None => {
                 // create a dummy root with empty children
@@ -68,6 +102,8 @@ impl SnapshotTree {
         Ok(SnapshotTree {
             root: root,
             snapshot_ino: snapshot_ino,
+            style: style,
+            depth: prefix.len(),
         })
     }
 
@@ -82,25 +118,45 @@ impl SnapshotTree {
         }
     }
 
+    /// Moves the inodes of all the entries forward by `offset`, as if the tree was built starting
+    /// from a later inode.
+    pub fn shift(&mut self, offset: u64) {
+        fn shift_rec(node: &mut TreeNode, offset: u64) {
+            node.ino += offset;
+            for child in &mut node.children {
+                shift_rec(child, offset);
+            }
+        }
+        shift_rec(&mut self.root, offset);
+    }
+
     pub fn children<'a, 'b>(&'a self, mut entries: SnapshotEntries<'b>) -> ChildrenIter<'a, 'b> {
-        // skip the root
-        entries.next().unwrap();
+        // skip the root, if the snapshot has any entry at all
+        entries.next();
         ChildrenIter {
             tree_it: self.root.children.iter(),
             entry_it: entries,
             curr_index: 0,
-            path_depth: 0,
+            path_depth: self.depth,
+            style: self.style,
         }
     }
 
     pub fn find_node(&self, ino: u64) -> Option<NodeEntry> {
-        fn find_node_rec(node: &TreeNode, ino: u64, depth: usize, parent: u64) -> Option<NodeEntry> {
+        fn find_node_rec(node: &TreeNode,
+                         ino: u64,
+                         depth: usize,
+                         parent: u64,
+                         root_depth: usize,
+                         style: PathStyle)
+                         -> Option<NodeEntry> {
             // check if found
             if node.ino == ino {
                 return Some(NodeEntry {
                     node: node,
                     depth: depth,
                     parent_ino: parent,
+                    style: style,
                 });
             }
             // check if impossible to find
@@ -119,8 +175,13 @@ impl SnapshotTree {
                     Ordering::Equal
                 }
             });
+            // the root node has no inode of its own, its children are in the snapshot directory
+            let parent = if depth == root_depth { parent } else { node.ino };
             match child_index {
-                Ok(index) => find_node_rec(&node.children[index], ino, depth + 1, node.ino),
+                Ok(index) => {
+                    let child = &node.children[index];
+                    find_node_rec(child, ino, depth + 1, parent, root_depth, style)
+                }
                 Err(_) => None,
             }
         }
@@ -128,14 +189,86 @@ impl SnapshotTree {
         if self.snapshot_ino == ino {
             Some(NodeEntry {
                 node: &self.root,
-                depth: 0,
+                depth: self.depth,
                 parent_ino: self.snapshot_ino,
+                style: self.style,
             })
         } else {
             // otherwise search in children
-            find_node_rec(&self.root, ino, 0, self.snapshot_ino)
+            find_node_rec(&self.root,
+                          ino,
+                          self.depth,
+                          self.snapshot_ino,
+                          self.depth,
+                          self.style)
         }
     }
+
+    /// Verifies the invariants the lookups rely on, describing the first one that doesn't hold.
+    ///
+    /// The inodes of every node have to follow the ones of its parent and of its previous sibling
+    /// without gaps, its index has to be the one of an entry directly inside the parent entry,
+    /// and `find_node` has to return it.
+    pub fn check(&self, entries: SnapshotEntries) -> Result<(), String> {
+        let paths = entries.map(|entry| entry.path().to_path_buf()).collect::<Vec<_>>();
+        self.check_children(&self.root, self.depth + 1, self.snapshot_ino, &paths)
+    }
+
+    fn check_children(&self,
+                      node: &TreeNode,
+                      depth: usize,
+                      parent_ino: u64,
+                      paths: &[PathBuf])
+                      -> Result<(), String> {
+        if node.children.is_empty() {
+            return Ok(());
+        }
+        let parent_path = backup_components(&paths[node.index], self.style).collect::<Vec<_>>();
+        let mut next_ino = node.ino + 1;
+        for child in &node.children {
+            if child.ino != next_ino {
+                return Err(format!("ino {} follows ino {}", child.ino, next_ino - 1));
+            }
+            let expected_index = self.root.index + (child.ino - self.root.ino) as usize;
+            if child.index != expected_index {
+                return Err(format!("ino {} has entry index {}, expected {}",
+                                   child.ino,
+                                   child.index,
+                                   expected_index));
+            }
+            let path = try!(paths.get(child.index).ok_or_else(|| {
+                format!("ino {} has entry index {}, but there are {} entries",
+                        child.ino,
+                        child.index,
+                        paths.len())
+            }));
+            let components = backup_components(path, self.style).collect::<Vec<_>>();
+            let inside_parent = components.len() == depth &&
+                                (depth == 1 || parent_path[..] == components[..depth - 1]);
+            if !inside_parent {
+                return Err(format!("ino {} is entry {:?}, which is not inside {:?}",
+                                   child.ino,
+                                   path,
+                                   paths[node.index]));
+            }
+            match self.find_node(child.ino) {
+                Some(ref found) if ptr::eq(found.node, child) && found.parent() == parent_ino => {}
+                _ => {
+                    return Err(format!("find_node doesn't return the node of ino {}", child.ino));
+                }
+            }
+            try!(self.check_children(child, depth + 1, child.ino, paths));
+            next_ino = child.inodes().1 + 1;
+        }
+        Ok(())
+    }
+}
+
+
+/// Returns whether the components of a path are the given ones.
+fn has_components(path: &Path, components: &[String], style: PathStyle) -> bool {
+    let mut path = backup_components(path, style);
+    components.iter().all(|c| path.next().map_or(false, |p| *p == c[..])) && path.next().is_none()
 }
 
 
@@ -143,6 +276,7 @@ impl TreeNode {
     pub fn new(path_depth: usize,
                index: usize,
                ino: u64,
+               style: PathStyle,
                entries: &mut Peekable<SnapshotEntries>)
                -> Option<Self> {
         // need to check if there are more entries
@@ -152,7 +286,7 @@ impl TreeNode {
             TreeNode {
                 index: index,
                 ino: ino,
-                children: Self::new_children(path_depth, index + 1, ino + 1, entries),
+                children: Self::new_children(path_depth, index + 1, ino + 1, style, entries),
             }
         })
     }
@@ -160,6 +294,7 @@ impl TreeNode {
     pub fn new_children(path_depth: usize,
                         index: usize,
                         first_ino: u64,
+                        style: PathStyle,
                         entries: &mut Peekable<SnapshotEntries>)
                         -> Vec<Self> {
         let mut result = Vec::new();
@@ -176,14 +311,14 @@ impl TreeNode {
                         break;
                     }
                 };
-                if !entry.path().components().nth(path_depth).is_some() {
+                if backup_components(entry.path(), style).nth(path_depth).is_none() {
                     // the entry does not belong to the current children
                     // this is because it does not have the 'path-depth' path component, so it must be
                     // a parent directory (different than the current one)
                     break;
                 }
             }
-            let child = match TreeNode::new(path_depth + 1, index, ino, entries) {
+            let child = match TreeNode::new(path_depth + 1, index, ino, style, entries) {
                 Some(child) => child,
                 None => {
                     // failed to create the child, break!
@@ -223,6 +358,7 @@ impl<'a, 'b> Iterator for ChildrenIter<'a, 'b> {
                 node: &child,
                 entry: self.entry_it.nth(skip).unwrap(),
                 depth: self.path_depth,
+                style: self.style,
             }
         })
     }
@@ -235,9 +371,11 @@ impl<'a, 'b> PathEntry<'a, 'b> {
     }
 
     pub fn path(&self) -> Option<&Path> {
-        match self.entry.path().components().nth(self.depth).unwrap() {
-            Component::Normal(p) => Some(p.as_ref()),
-            _ => None,
+        let name = backup_components(self.entry.path(), self.style).nth(self.depth).unwrap();
+        if name == "." || name == ".." {
+            None
+        } else {
+            Some(name.as_ref())
         }
     }
 
@@ -249,13 +387,14 @@ impl<'a, 'b> PathEntry<'a, 'b> {
 
 impl<'a> NodeEntry<'a> {
     pub fn children<'b>(&self, mut entries: SnapshotEntries<'b>) -> ChildrenIter<'a, 'b> {
-        // skip the root
-        entries.next().unwrap();
+        // skip the root, if the snapshot has any entry at all
+        entries.next();
         ChildrenIter {
             tree_it: self.node.children.iter(),
             entry_it: entries,
             curr_index: 0,
             path_depth: self.depth,
+            style: self.style,
         }
     }
 
@@ -264,6 +403,7 @@ impl<'a> NodeEntry<'a> {
             node: self.node,
             entry: entries.nth(self.node.index).unwrap(),
             depth: self.depth,
+            style: self.style,
         }
     }
 
@@ -271,3 +411,65 @@ impl<'a> NodeEntry<'a> {
         self.parent_ino
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use backend::memory::arb_chain;
+
+    use ruplicity::{Backup, SnapshotEntries as Entries};
+    use std::collections::{BTreeMap, BTreeSet};
+
+
+    // collects the inodes and the full paths of the nodes below a node
+    fn walk(tree: &SnapshotTree,
+            node: NodeEntry,
+            prefix: &Path,
+            entries: &Entries,
+            result: &mut BTreeMap<u64, PathBuf>) {
+        for child in node.children(entries.as_signature()) {
+            let path = prefix.join(child.path().unwrap());
+            result.insert(child.ino(), path.clone());
+            walk(tree, tree.find_node(child.ino()).unwrap(), &path, entries, result);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn nodes_match_entries((snapshots, backend) in arb_chain()) {
+            let backup = Backup::new(backend).unwrap();
+            let mut first_ino = 100;
+            for (snapshot, expected) in backup.snapshots().unwrap().zip(&snapshots) {
+                let tree = SnapshotTree::new(&snapshot, 2, first_ino).unwrap();
+                let entries = snapshot.entries().unwrap();
+                prop_assert_eq!(tree.check(entries.as_signature()), Ok(()));
+
+                // every entry is reachable from the snapshot, through a single inode
+                let mut found = BTreeMap::new();
+                walk(&tree, tree.find_node(2).unwrap(), Path::new(""), &entries, &mut found);
+                let paths = found.values().cloned().collect::<BTreeSet<_>>();
+                prop_assert_eq!(found.len(), expected.len());
+                prop_assert_eq!(paths,
+                                expected.iter().map(|e| PathBuf::from(e.path())).collect());
+
+                // and find_node leads back to the entry of that path
+                for (&ino, path) in &found {
+                    let node = tree.find_node(ino).unwrap();
+                    let entry = node.as_path_entry(entries.as_signature());
+                    prop_assert_eq!(entry.as_signature().path(), path.as_path());
+                }
+
+                // the inodes are allocated without gaps from the first one
+                match tree.inodes() {
+                    Some((first, last)) => {
+                        prop_assert_eq!(first, first_ino);
+                        prop_assert_eq!(last + 1 - first, expected.len() as u64);
+                        first_ino = last + 1;
+                    }
+                    None => prop_assert!(expected.is_empty()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static SIGNATURES: AtomicUsize = AtomicUsize::new(0);
+static TREES: AtomicUsize = AtomicUsize::new(0);
+
+thread_local!(static THREAD_LIVE: Cell<isize> = const { Cell::new(0) });
+
+
+/// The global allocator, counting the memory in use.
+pub struct CountingAllocator;
+
+/// What memory is used for.
+#[derive(Copy, Clone, Debug)]
+pub enum Usage {
+    /// The parsed signatures, cached by ruplicity.
+    Signatures,
+    /// The snapshot trees.
+    Trees,
+}
+
+/// A summary of the heap usage.
+#[derive(Debug)]
+pub struct HeapStats {
+    /// Bytes currently allocated.
+    pub live: usize,
+    /// Maximum number of bytes allocated at the same time.
+    pub peak: usize,
+    /// Number of allocations currently live.
+    pub allocations: usize,
+    /// Bytes allocated for the parsed signatures.
+    pub signatures: usize,
+    /// Bytes allocated for the snapshot trees.
+    pub trees: usize,
+}
+
+
+/// Returns the current heap usage.
+pub fn stats() -> HeapStats {
+    HeapStats {
+        live: LIVE.load(Ordering::Relaxed),
+        peak: PEAK.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        signatures: SIGNATURES.load(Ordering::Relaxed),
+        trees: TREES.load(Ordering::Relaxed),
+    }
+}
+
+/// Measures the memory allocated by the current thread while running `f`, and accounts it to the
+/// given usage.
+///
+/// Only the memory still allocated when `f` returns is counted, so whatever `f` builds is
+/// expected to be kept for the lifetime of the process.
+pub fn account<T, F: FnOnce() -> T>(usage: Usage, f: F) -> T {
+    let before = thread_live();
+    let result = f();
+    let delta = thread_live() - before;
+    if delta > 0 {
+        let counter = match usage {
+            Usage::Signatures => &SIGNATURES,
+            Usage::Trees => &TREES,
+        };
+        counter.fetch_add(delta as usize, Ordering::Relaxed);
+    }
+    result
+}
+
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            allocated(layout.size() as isize);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        allocated(-(layout.size() as isize));
+        ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            allocated(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
+
+impl Display for HeapStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f,
+               "{} bytes in {} allocations (peak {} bytes), signatures: {} bytes, snapshot \
+                trees: {} bytes",
+               self.live,
+               self.allocations,
+               self.peak,
+               self.signatures,
+               self.trees)
+    }
+}
+
+
+fn allocated(delta: isize) {
+    let live = if delta >= 0 {
+        LIVE.fetch_add(delta as usize, Ordering::Relaxed) + delta as usize
+    } else {
+        LIVE.fetch_sub(-delta as usize, Ordering::Relaxed) - (-delta as usize)
+    };
+    PEAK.fetch_max(live, Ordering::Relaxed);
+    // the thread local may be already destroyed while the thread exits
+    let _ = THREAD_LIVE.try_with(|t| t.set(t.get() + delta));
+}
+
+fn thread_live() -> isize {
+    THREAD_LIVE.with(|t| t.get())
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+
+// durations up to 2^39 microseconds, about six days, each in the bucket of its bit length
+const BUCKETS: usize = 40;
+
+
+/// Latency histograms shared between a filesystem and whoever reports them.
+pub type SharedLatencies = Arc<Mutex<Latencies>>;
+
+/// The latencies of the requests served by a mount, for each operation.
+#[derive(Debug, Default)]
+pub struct Latencies {
+    ops: BTreeMap<&'static str, Histogram>,
+}
+
+/// Counts durations in buckets growing by powers of two.
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    max: u64,
+}
+
+/// Times a request, recording its latency when dropped.
+pub struct Timer {
+    latencies: SharedLatencies,
+    op: &'static str,
+    start: Instant,
+}
+
+
+impl Latencies {
+    /// Records that serving a request for an operation took the given microseconds.
+    pub fn record(&mut self, op: &'static str, micros: u64) {
+        self.ops.entry(op).or_insert_with(Histogram::new).record(micros);
+    }
+
+    /// Returns the content of the latency file.
+    ///
+    /// A line for each operation served so far tells the number of requests, and upper bounds for
+    /// their median, 99th percentile and maximum latency, in microseconds.
+    pub fn content(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl Display for Latencies {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (op, histogram) in &self.ops {
+            try!(writeln!(f,
+                          "{}: count {}, p50 {}us, p99 {}us, max {}us",
+                          op,
+                          histogram.count,
+                          histogram.quantile(0.5),
+                          histogram.quantile(0.99),
+                          histogram.max));
+        }
+        Ok(())
+    }
+}
+
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram {
+            buckets: [0; BUCKETS],
+            count: 0,
+            max: 0,
+        }
+    }
+
+    pub fn record(&mut self, micros: u64) {
+        let bucket = (64 - micros.leading_zeros() as usize).min(BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.max = self.max.max(micros);
+    }
+
+    /// Returns a duration that the given fraction of the recorded ones don't exceed.
+    ///
+    /// It is the upper end of the bucket reaching the fraction, so it's at most twice the exact
+    /// value, and never more than the maximum.
+    pub fn quantile(&self, fraction: f64) -> u64 {
+        let rank = (fraction * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += *count;
+            if seen >= rank.max(1) {
+                return ((1u64 << bucket) - 1).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+
+impl Timer {
+    /// Starts timing a request for an operation.
+    pub fn new(latencies: &SharedLatencies, op: &'static str) -> Self {
+        Timer {
+            latencies: latencies.clone(),
+            op: op,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let micros = elapsed.as_secs() * 1_000_000 + elapsed.subsec_nanos() as u64 / 1000;
+        self.latencies.lock().unwrap().record(self.op, micros);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quantiles() {
+        let mut histogram = Histogram::new();
+        assert_eq!(histogram.quantile(0.5), 0);
+        for _ in 0..98 {
+            histogram.record(100);
+        }
+        histogram.record(5000);
+        histogram.record(70000);
+        // 100 is in the bucket from 64 to 127, 5000 in the one from 4096 to 8191
+        assert_eq!(histogram.quantile(0.5), 127);
+        assert_eq!(histogram.quantile(0.98), 127);
+        assert_eq!(histogram.quantile(0.99), 8191);
+        assert_eq!(histogram.quantile(1.0), 70000);
+
+        let mut latencies = Latencies::default();
+        latencies.record("lookup", 3);
+        latencies.record("getattr", 0);
+        latencies.record("lookup", 12);
+        assert_eq!(String::from_utf8(latencies.content()).unwrap(),
+                   "getattr: count 1, p50 0us, p99 0us, max 0us\nlookup: count 2, p50 3us, p99 \
+                    12us, max 12us\n");
+    }
+}
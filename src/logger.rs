@@ -1,7 +1,29 @@
 #![macro_use]
 
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::env;
 use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use chrono::Local;
+use libc;
 use log;
+use time::{self, Timespec};
+
+// how often a message that keeps repeating is reported, in seconds
+const REPEAT_REPORT_INTERVAL: i64 = 10;
+// how many of the most recent errors are kept
+const MAX_ERRORS: usize = 100;
+
+lazy_static! {
+    static ref ERRORS: Mutex<VecDeque<ErrorRecord>> = Mutex::new(VecDeque::new());
+    // the logger installed by `init`, used by `flush`
+    static ref INSTALLED: Mutex<Option<ConsoleLogger>> = Mutex::new(None);
+}
+
+thread_local!(static REQUEST: Cell<Option<(u64, &'static str)>> = Cell::new(None));
 
 macro_rules! fatal {
     ($($arg:tt)+) => {
@@ -10,14 +32,81 @@ macro_rules! fatal {
     }
 }
 
-pub fn init(level: log::LogLevelFilter) -> Result<(), log::SetLoggerError> {
-    log::set_logger(|max_level| {
+/// Initializes the logger, printing errors and warnings on stderr and the rest on stdout.
+///
+/// Levels are colored if `color` is true, the `NO_COLOR` environment variable is not set and the
+/// output is a terminal.
+pub fn init(level: log::LogLevelFilter, color: bool) -> Result<(), log::SetLoggerError> {
+    let color = color && env::var_os("NO_COLOR").is_none();
+    let (stdout_color, stderr_color) = unsafe {
+        (color && libc::isatty(libc::STDOUT_FILENO) != 0,
+         color && libc::isatty(libc::STDERR_FILENO) != 0)
+    };
+    let logger = ConsoleLogger {
+        stdout_color: stdout_color,
+        stderr_color: stderr_color,
+        last: Arc::new(Mutex::new(None)),
+    };
+    let installed = logger.clone();
+    try!(log::set_logger(|max_level| {
         max_level.set(level);
-        Box::new(ConsoleLogger)
-    })
+        Box::new(logger)
+    }));
+    *INSTALLED.lock().unwrap() = Some(installed.clone());
+    // a message that keeps repeating and then stops is reported without waiting for the next one
+    let spawned = thread::Builder::new().name("log".to_owned()).spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(REPEAT_REPORT_INTERVAL as u64));
+            installed.report_pending(false);
+        }
+    });
+    if let Err(e) = spawned {
+        warn!("Can't start reporting repeated messages: {}", e);
+    }
+    Ok(())
 }
 
-struct ConsoleLogger;
+/// Reports how many times the last message was repeated, if it was since the last report.
+///
+/// To be called before exiting, since the count is otherwise reported with the next message.
+pub fn flush() {
+    if let Some(ref logger) = *INSTALLED.lock().unwrap() {
+        logger.report_pending(true);
+    }
+}
+
+/// Tags the messages logged by the current thread with a FUSE request, until dropped.
+pub struct RequestScope {
+    previous: Option<(u64, &'static str)>,
+}
+
+/// A failed FUSE request.
+#[derive(Clone, Debug)]
+pub struct ErrorRecord {
+    pub time: Timespec,
+    /// The identifier and the name of the request, as found in the log.
+    pub request: Option<(u64, &'static str)>,
+    /// The error code replied to the kernel.
+    pub errno: i32,
+    pub message: String,
+}
+
+#[derive(Clone)]
+struct ConsoleLogger {
+    stdout_color: bool,
+    stderr_color: bool,
+    // shared by the copies of the logger
+    last: Arc<Mutex<Option<LastMessage>>>,
+}
+
+/// The last message logged, kept to collapse identical messages.
+struct LastMessage {
+    level: log::LogLevel,
+    text: String,
+    // how many times the message was suppressed since the last report
+    repeated: u64,
+    reported: Timespec,
+}
 
 impl log::Log for ConsoleLogger {
     fn enabled(&self, _metadata: &log::LogMetadata) -> bool {
@@ -25,16 +114,125 @@ impl log::Log for ConsoleLogger {
     }
 
     fn log(&self, record: &log::LogRecord) {
-        match record.level() {
-            log::LogLevel::Error => {
-                writeln!(&mut io::stderr(), "{} - {}", record.level(), record.args()).unwrap();
+        let text = record.args().to_string();
+        let now = time::get_time();
+        let mut last = self.last.lock().unwrap();
+        if let Some(ref mut last) = *last {
+            if last.level == record.level() && last.text == text {
+                last.repeated += 1;
+                if (now - last.reported).num_seconds() >= REPEAT_REPORT_INTERVAL {
+                    self.report_repeated(last);
+                    last.reported = now;
+                }
+                return;
+            }
+            if last.repeated > 0 {
+                self.report_repeated(last);
+            }
+        }
+        self.write(record.level(), &text);
+        *last = Some(LastMessage {
+            level: record.level(),
+            text: text,
+            repeated: 0,
+            reported: now,
+        });
+    }
+}
+
+/// Records an error replied to the current request, to be reported by `recent_errors`.
+pub fn record_error(errno: i32, message: String) {
+    let record = ErrorRecord {
+        time: time::get_time(),
+        request: REQUEST.with(|r| r.get()),
+        errno: errno,
+        message: message,
+    };
+    let mut errors = ERRORS.lock().unwrap();
+    if errors.len() == MAX_ERRORS {
+        errors.pop_front();
+    }
+    errors.push_back(record);
+}
+
+/// Returns the most recent errors, the oldest first.
+pub fn recent_errors() -> Vec<ErrorRecord> {
+    ERRORS.lock().unwrap().iter().cloned().collect()
+}
+
+
+impl RequestScope {
+    pub fn enter(id: u64, op: &'static str) -> Self {
+        RequestScope { previous: REQUEST.with(|r| r.replace(Some((id, op)))) }
+    }
+}
+
+impl Drop for RequestScope {
+    fn drop(&mut self) {
+        let previous = self.previous;
+        REQUEST.with(|r| r.set(previous));
+    }
+}
+
+
+impl ConsoleLogger {
+    // reports the repetitions of the last message, if forced or if they went unreported for a
+    // while
+    fn report_pending(&self, force: bool) {
+        let now = time::get_time();
+        if let Some(ref mut last) = *self.last.lock().unwrap() {
+            let due = (now - last.reported).num_seconds() >= REPEAT_REPORT_INTERVAL;
+            if last.repeated > 0 && (force || due) {
+                self.report_repeated(last);
+                last.reported = now;
             }
-            log::LogLevel::Warn => {
-                writeln!(&mut io::stderr(), "{} - {}", record.level(), record.args()).unwrap();
+        }
+    }
+
+    fn report_repeated(&self, last: &mut LastMessage) {
+        self.write(last.level, &format!("last message repeated {} times", last.repeated));
+        last.repeated = 0;
+    }
+
+    fn write(&self, level: log::LogLevel, text: &str) {
+        let ts = Local::now().format("%Y-%m-%d %H:%M:%S");
+        let text = match REQUEST.with(|r| r.get()) {
+            Some((id, op)) => format!("[{} #{} {}] {}", thread_name(), id, op, text),
+            None => text.to_owned(),
+        };
+        match level {
+            log::LogLevel::Error | log::LogLevel::Warn => {
+                let level = colored(level, self.stderr_color);
+                writeln!(&mut io::stderr(), "{} {} - {}", ts, level, text).unwrap();
             }
             _ => {
-                writeln!(&mut io::stderr(), "{} - {}", record.level(), record.args()).unwrap();
+                let level = colored(level, self.stdout_color);
+                writeln!(&mut io::stdout(), "{} {} - {}", ts, level, text).unwrap();
             }
         }
     }
 }
+
+/// Returns the name of the level, surrounded by the terminal escapes for its color if requested.
+fn colored(level: log::LogLevel, color: bool) -> String {
+    if !color {
+        return level.to_string();
+    }
+    let code = match level {
+        log::LogLevel::Error => 31,
+        log::LogLevel::Warn => 33,
+        log::LogLevel::Info => 32,
+        log::LogLevel::Debug => 36,
+        log::LogLevel::Trace => 90,
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, level)
+}
+
+/// Returns the name of the current thread, or its id if it has no name.
+fn thread_name() -> String {
+    let current = thread::current();
+    match current.name() {
+        Some(name) => name.to_owned(),
+        None => format!("{:?}", current.id()),
+    }
+}
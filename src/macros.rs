@@ -1,38 +1,90 @@
 #![macro_use]
 
-macro_rules! try_or_log(
+/// Helper macro for unwrapping an Option if possible, continuing the loop
+/// if the value is None.
+macro_rules! unwrap_opt_or_continue(
     ($e:expr) => (
         match $e {
-            Ok(v) => v,
-            Err(e) => {
-                error!("{}", e);
-                return;
-            }
+            Some(v) => v,
+            _ => { continue; }
         }
     )
 );
 
-/// Helper macro for unwrapping an Option if possible, continuing the loop
+/// Helper macro for unwrapping an Option, returning None from the function
 /// if the value is None.
-macro_rules! unwrap_opt_or_continue(
+macro_rules! try_opt(
     ($e:expr) => (
         match $e {
             Some(v) => v,
-            _ => { continue; }
+            None => { return None; }
         }
     )
 );
 
 
+/// Helper macro replying with the given error code, and logging the given message.
+///
+/// Failures are also recorded, to be listed in the errors file of the mount.
+macro_rules! reply_error(
+    ($reply:expr, $err:expr, $($msg:tt)+) => ({
+        let (err, msg) = ($err, format!($($msg)+));
+        error!("{}", msg);
+        ::logger::record_error(err, msg);
+        $reply.error(err);
+    })
+);
+
+/// Helper macro for unwrapping an Option, logging the given message and replying with the given
+/// error code if the value is None.
 macro_rules! unwrap_opt_or_error(
     ($e:expr, $reply:expr, $err:expr, $($msg:tt)+) => (
         match $e {
             Some(e) => e,
             None => {
-                error!($($msg)+);
-                $reply.error($err);
+                reply_error!($reply, $err, $($msg)+);
+                return;
+            }
+        }
+    )
+);
+
+/// Helper macro for unwrapping a Result, logging the given context along with the error and
+/// replying with the given error code if it fails.
+macro_rules! try_or_error(
+    ($e:expr, $reply:expr, $err:expr, $($msg:tt)+) => (
+        match $e {
+            Ok(v) => v,
+            Err(e) => {
+                reply_error!($reply, $err, "{}: {}", format!($($msg)+), e);
+                return;
+            }
+        }
+    )
+);
+
+/// Helper macro for unwrapping an `io::Result` like `try_or_error`, replying with the error code
+/// matching the kind of the error.
+macro_rules! try_or_errno(
+    ($e:expr, $reply:expr, $($msg:tt)+) => (
+        match $e {
+            Ok(v) => v,
+            Err(e) => {
+                reply_error!($reply, ::fs::errno(e.kind()), "{}: {}", format!($($msg)+), e);
                 return;
             }
         }
     )
 );
+
+/// Helper macro tagging the log messages emitted until the end of the current block with the
+/// identifier and the name of a FUSE request, and tracing its duration. The duration is also
+/// recorded in the latencies of the filesystem, and the request keeps the scrubbers waiting.
+macro_rules! log_request(
+    ($fs:expr, $req:expr, $op:expr) => (
+        let _scope = ::logger::RequestScope::enter($req.unique(), $op);
+        let _span = ::trace::Span::new("fuse", $op);
+        let _timer = ::latency::Timer::new(&$fs.latencies, $op);
+        ::scrub::record_activity();
+    )
+);
@@ -2,59 +2,388 @@
 #![cfg_attr(feature = "lints", feature(plugin))]
 #![cfg_attr(feature = "lints", plugin(clippy))]
 
+#[macro_use]
+extern crate chan;
 extern crate chan_signal;
+extern crate chrono;
 extern crate fuse;
+extern crate getopts;
+#[macro_use]
+extern crate lazy_static;
 extern crate libc;
 #[macro_use]
 extern crate log;
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
 extern crate ruplicity;
+extern crate sha1;
+extern crate sha2;
+#[cfg(test)]
+extern crate tar;
 extern crate time;
 
 mod macros;
+#[cfg(test)]
+mod backend;
+mod commands;
+mod compare;
+mod compat;
+mod diff;
+mod digest;
+mod error;
 mod fs;
+mod heap;
+mod json;
+mod latency;
 mod logger;
+mod manifest;
+mod options;
 mod path_utils;
+mod scrub;
+mod short_names;
+mod stats;
+mod time_utils;
+mod trace;
+mod verify;
 
 use std::env;
+use std::ffi::OsString;
 use std::io::{self, Write};
-use std::path::Path;
+use std::mem;
+use std::os::unix::fs::{DirBuilderExt, symlink};
+use std::path::{Path, PathBuf};
 use std::process;
+use chan::Sender;
 use chan_signal::Signal;
-use ruplicity::Backup;
+use fuse::BackgroundSession;
+use ruplicity::{Backend, Backup};
 use ruplicity::backend::local::LocalBackend;
 
-use fs::RuplicityFs;
+use error::Error;
+use fs::{FsOptions, Ownership, RuplicityFs, SharedInodeMap};
+use latency::SharedLatencies;
+use options::{MountTarget, Options, Parsed};
+use scrub::{ScrubMode, Scrubber};
+use short_names::ShortNamesBackend;
+use stats::{CountingBackend, SharedStats};
+
+// the backend used to access local backups
+type Local = ShortNamesBackend<CountingBackend<LocalBackend>>;
+
+// the device used to talk with the kernel
+const FUSE_DEVICE: &'static str = "/dev/fuse";
+
+#[global_allocator]
+static ALLOCATOR: heap::CountingAllocator = heap::CountingAllocator;
 
 fn main() {
-    let (mountp, backupp) = match (env::args().nth(1), env::args().nth(2)) {
-        (Some(mount), Some(path)) => (mount, path),
-        _ => {
-            let _ = writeln!(&mut io::stderr(),
-                             "Usage: {} <MOUNTPOINT> <BACKUP_PATH>",
-                             env::args().nth(0).unwrap());
+    let program = env::args().nth(0).unwrap();
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if commands::is_command(&args) {
+        // subcommands print their results on stdout, so only problems are logged
+        init_logger(log::LogLevelFilter::Warn, true);
+        let result = commands::run(&program, &args);
+        logger::flush();
+        if let Err(e) = result {
+            let _ = writeln!(&mut io::stderr(), "{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let opts = match Options::parse(args) {
+        Ok(Parsed::Options(opts)) => opts,
+        Ok(Parsed::Help) => {
+            println!("{}", usage(&program));
+            process::exit(0);
+        }
+        Err(e) => {
+            let _ = writeln!(&mut io::stderr(), "{}\n\n{}", e, usage(&program));
             process::exit(1);
         }
     };
-    if let Err(e) = logger::init(log::LogLevelFilter::Trace) {
+    init_logger(opts.log_level, !opts.no_color);
+    for warning in opts.warnings() {
+        warn!("{}", warning);
+    }
+    if let Some(ref path) = opts.trace_out {
+        ordie(trace::start(path).map_err(|e| {
+            Error::WriteTrace {
+                path: path.clone(),
+                cause: e,
+            }
+        }));
+    }
+    let fusermount_shim = check_fuse();
+
+    let signal = chan_signal::notify(&[Signal::INT, Signal::TERM, Signal::USR1, Signal::USR2]);
+    let (unmounted_tx, unmounted) = chan::async();
+    let mut sessions = Vec::new();
+    let mut mounts = Vec::new();
+    let mut scrubbers = Vec::new();
+    for (index, target) in opts.mounts.iter().enumerate() {
+        let (session, stats, inodes, latencies, scrubber) =
+            mount(target, &opts, index, unmounted_tx.clone());
+        sessions.push(Some(session));
+        mounts.push((stats, inodes, latencies));
+        scrubbers.push(scrubber);
+    }
+
+    // Blocks until this process is sent an INT or TERM signal, or all the mounts are unmounted
+    // externally, dumping the traffic statistics and the request latencies on USR1 and the inodes
+    // allocation on USR2. Since the channels are never closed, we can unwrap the received values.
+    loop {
+        chan_select! {
+            signal.recv() -> signal => match signal.unwrap() {
+                Signal::USR1 => {
+                    for (target, mounted) in opts.mounts.iter().zip(mounts.iter()) {
+                        let (ref stats, _, ref latencies) = *mounted;
+                        info!("Backend traffic for {:?}:\n{}",
+                              target.backup_path,
+                              *stats.lock().unwrap());
+                        info!("Request latencies for {:?}:\n{}",
+                              target.mountpoint,
+                              *latencies.lock().unwrap());
+                    }
+                    info!("Heap usage: {}", heap::stats());
+                }
+                Signal::USR2 => {
+                    for (target, &(_, ref inodes, _)) in opts.mounts.iter().zip(mounts.iter()) {
+                        info!("Inodes for {:?}:\n{}",
+                              target.backup_path,
+                              *inodes.lock().unwrap());
+                    }
+                }
+                _ => break,
+            },
+            unmounted.recv() -> index => {
+                let index = index.unwrap();
+                info!("{:?} was unmounted", opts.mounts[index].mountpoint);
+                // the session thread is already over, and dropping the session would try to
+                // unmount again
+                mem::forget(sessions[index].take());
+                if let Some(scrubber) = scrubbers[index].take() {
+                    scrubber.stop();
+                }
+                if sessions.iter().all(Option::is_none) {
+                    break;
+                }
+            },
+        }
+    }
+    // unmounting may need the fusermount link
+    drop(sessions);
+    if let Some(dir) = fusermount_shim {
+        if let Err(e) = ::std::fs::remove_dir_all(&dir) {
+            warn!("Can't remove {:?}: {}", dir, e);
+        }
+    }
+    if let Err(e) = trace::finish() {
+        error!("Can't complete the trace file: {}", e);
+    }
+    logger::flush();
+}
+
+fn usage(program: &str) -> String {
+    format!("{}\nCommands working without mounting:\n{}",
+            Options::usage(program),
+            commands::usage(program))
+}
+
+fn init_logger(level: log::LogLevelFilter, color: bool) {
+    if let Err(e) = logger::init(level, color) {
         println!("Logger initialization error {}", e);
         process::exit(1);
+    }
+}
+
+/// Mounts a backup, sending `index` to `unmounted` when the session ends.
+///
+/// The scrubber of the mount, if any, is left running until the caller stops it.
+fn mount(target: &MountTarget,
+         opts: &Options,
+         index: usize,
+         unmounted: Sender<usize>)
+         -> (BackgroundSession<'static>,
+             SharedStats,
+             SharedInodeMap,
+             SharedLatencies,
+             Option<Scrubber>) {
+    let stats = SharedStats::default();
+    let backup = ordie(backup_from_path(&target.backup_path, &stats));
+    let backend = local_backend(&target.backup_path, &stats);
+    if opts.verify_on_mount {
+        verify_or_die(&backup, &backend);
+    }
+    let problems = ordie(compat::check(&backup, &backend));
+    for problem in &problems {
+        warn!("{:?}: {}, the mount may be incomplete", target.backup_path, problem);
+    }
+    if opts.strict && !problems.is_empty() {
+        fatal!("Refusing to mount {:?} in strict mode", target.backup_path);
+    }
+    let volumes = if opts.scrub == ScrubMode::Idle {
+        ordie(scrub_volumes(&backup, &backend))
+    } else {
+        Vec::new()
     };
+    let mut fs = ordie(RuplicityFs::new(backup, backend, fs_options(opts, target)));
+    let scrubber = if opts.scrub == ScrubMode::Idle {
+        // the scrubber reads the volumes on its own, counting its traffic as well
+        let backend = local_backend(&target.backup_path, &stats);
+        let scrubber = scrub::start(volumes, backend);
+        fs.show_scrub_report(scrubber.report());
+        Some(scrubber)
+    } else {
+        None
+    };
+    if let Some(which) = opts.preload_metadata {
+        // each thread loads from a backend of its own
+        ordie(fs.preload(which,
+                         opts.preload_threads,
+                         || Ok(local_backend(&target.backup_path, &stats))));
+    }
+    fs.show_traffic(stats.clone());
+    fs.on_unmount(move || unmounted.send(index));
+    let inodes = fs.inode_map();
+    let latencies = fs.latencies();
+    info!("Mounting {:?} on {:?}", target.backup_path, target.mountpoint);
+    let degraded = fs.is_degraded();
+    let mount_opts = opts.mount_options(target, degraded).join(",");
+    let mount_opts = [OsString::from("-o"), OsString::from(mount_opts)];
+    let mount_opts = mount_opts.iter().map(|o| o.as_os_str()).collect::<Vec<_>>();
+    let session = unsafe { fuse::spawn_mount(fs, &target.mountpoint, &mount_opts) };
+    let session = ordie(session.map_err(|e| {
+        Error::Mount {
+            mountpoint: target.mountpoint.clone(),
+            cause: e,
+        }
+    }));
+    (session, stats, inodes, latencies, scrubber)
+}
 
-    let backup = ordie(backup_from_path(backupp));
-    let fs = ordie(RuplicityFs::new(backup));
+fn backup_from_path<P: AsRef<Path>>(path: P, stats: &SharedStats) -> error::Result<Backup<Local>> {
+    let path = path.as_ref();
+    info!("Loading backup from path {:?}", path);
+    Backup::new(local_backend(path, stats)).map_err(|e| {
+        Error::OpenBackup {
+            path: path.to_path_buf(),
+            cause: e,
+        }
+    })
+}
 
-    let signal = chan_signal::notify(&[Signal::INT, Signal::TERM]);
-    let _mount = unsafe { fuse::spawn_mount(fs, &mountp, &[]) };
+/// Returns a backend accessing a local backup, counting its traffic in `stats`.
+fn local_backend<P: AsRef<Path>>(path: P, stats: &SharedStats) -> Local {
+    let local = LocalBackend::new(path.as_ref());
+    ShortNamesBackend::new(CountingBackend::new(local, stats.clone()))
+}
 
-    // Blocks until this process is sent an INT or TERM signal.
-    // Since the channel is never closed, we can unwrap the received value.
-    signal.recv().unwrap();
+fn fs_options(opts: &Options, target: &MountTarget) -> FsOptions {
+    let ownership = if opts.preserve_ownership {
+        Ownership::Preserve
+    } else {
+        unsafe {
+            Ownership::Fixed {
+                uid: libc::getuid(),
+                gid: libc::getgid(),
+            }
+        }
+    };
+    // the mountpoint exists by now, unless the mount is going to fail anyway
+    let mountpoint = ::std::fs::canonicalize(&target.mountpoint)
+                         .unwrap_or_else(|_| PathBuf::from(&target.mountpoint));
+    FsOptions {
+        ownership: ownership,
+        layout: opts.layout,
+        volatile: opts.volatile,
+        track_atime: opts.track_atime,
+        unknown_entries: opts.unknown_entries,
+        rewrite_symlinks: opts.rewrite_symlinks,
+        mountpoint: mountpoint,
+        strip_prefix: opts.strip_prefix.clone(),
+        add_prefix: opts.add_prefix.clone(),
+        strict: opts.strict,
+        check_tree: opts.check_tree,
+        best_effort: opts.best_effort,
+    }
+}
+
+/// Returns the volumes of a backup that can be checked against their hashes.
+fn scrub_volumes<B: Backend>(backup: &Backup<B>,
+                             backend: &B)
+                             -> io::Result<Vec<(String, manifest::VolumeInfo)>> {
+    let mut result = Vec::new();
+    for snapshot in try!(backup.snapshots()) {
+        result.extend(verify::hashed_volumes(&snapshot, backend));
+    }
+    Ok(result)
+}
+
+fn verify_or_die<B: Backend>(backup: &Backup<B>, backend: &B) {
+    info!("Verifying backup consistency");
+    let problems = ordie(verify::verify(backup, backend));
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        fatal!("Backup verification failed: {} problems found", problems.len());
+    }
+    info!("Backup verified successfully");
+}
+
+/// Exits with an explanation if this process can't mount a FUSE filesystem.
+///
+/// Returns the directory added to the PATH to mount through fusermount3, if any.
+fn check_fuse() -> Option<PathBuf> {
+    if !Path::new(FUSE_DEVICE).exists() {
+        fatal!("{} is missing: load the fuse kernel module, or when running in a container, make \
+                the device available to it (e.g. docker run --device /dev/fuse)",
+               FUSE_DEVICE);
+    }
+    // without CAP_SYS_ADMIN libfuse mounts through the setuid fusermount helper, which comes
+    // from FUSE 2 and is not provided by the FUSE 3 packages
+    if unsafe { libc::getuid() } == 0 || find_in_path("fusermount").is_some() {
+        return None;
+    }
+    match find_in_path("fusermount3") {
+        Some(helper) => {
+            info!("fusermount is missing, mounting through {:?}", helper);
+            Some(ordie(shim_fusermount(&helper)))
+        }
+        None => {
+            fatal!("fusermount is missing: install the fuse package, or run as root with \
+                    CAP_SYS_ADMIN");
+        }
+    }
+}
+
+/// Makes libfuse run the FUSE 3 helper in place of the missing fusermount, by linking it from a
+/// private directory put first in the PATH.
+///
+/// fusermount3 talks with libfuse in the same way, and accepts the same arguments to mount and
+/// unmount. The returned directory must be kept until every mount is unmounted.
+fn shim_fusermount(helper: &Path) -> io::Result<PathBuf> {
+    let parent = env::var_os("XDG_RUNTIME_DIR").map_or_else(env::temp_dir, PathBuf::from);
+    let dir = parent.join(format!("ruplicity-fuse-{}", process::id()));
+    // fails if anybody else created the directory, so the link can't be replaced
+    try!(::std::fs::DirBuilder::new().mode(0o700).create(&dir));
+    try!(symlink(helper, dir.join("fusermount")));
+    let mut paths = vec![dir.clone()];
+    if let Some(path) = env::var_os("PATH") {
+        paths.extend(env::split_paths(&path));
+    }
+    let path = try!(env::join_paths(paths)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)));
+    env::set_var("PATH", path);
+    Ok(dir)
 }
 
-fn backup_from_path<P: AsRef<Path>>(path: P) -> io::Result<Backup<LocalBackend>> {
-    info!("Loading backup from path {:?}", path.as_ref());
-    let backend = LocalBackend::new(path);
-    Backup::new(backend)
+/// Returns the path of an executable in one of the directories of the PATH.
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths).map(|dir| dir.join(name)).find(|path| path.is_file())
+    })
 }
 
 fn ordie<T, E: ToString>(r: Result<T, E>) -> T {
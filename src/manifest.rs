@@ -0,0 +1,305 @@
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::str;
+
+use path_utils::bytes2path;
+
+
+/// The content of a duplicity manifest file.
+///
+/// A manifest describes the volumes of a backup set: for each volume, the range of paths it
+/// contains and the hash of the volume file.
+#[derive(Debug, Default)]
+pub struct Manifest {
+    /// The host in which the backup was taken.
+    pub hostname: Option<String>,
+    /// The directory that was backed up.
+    pub local_dir: Option<PathBuf>,
+    /// The volumes of the backup set, in the order they appear in the manifest.
+    pub volumes: Vec<VolumeInfo>,
+    /// The fields not understood, each listed once.
+    pub unknown_keys: Vec<String>,
+}
+
+/// Information about a single volume in a manifest.
+#[derive(Debug, Default)]
+pub struct VolumeInfo {
+    /// The volume number, starting from 1.
+    pub number: i32,
+    /// The first path contained in the volume.
+    pub start_path: Option<PathBuf>,
+    /// The block of the first path where the volume starts, if the path is split.
+    pub start_block: Option<u64>,
+    /// The last path contained in the volume.
+    pub end_path: Option<PathBuf>,
+    /// The block of the last path where the volume ends, if the path is split.
+    pub end_block: Option<u64>,
+    /// The hash algorithm name and the hexadecimal digest of the volume file.
+    pub hash: Option<(String, String)>,
+}
+
+
+impl Manifest {
+    /// Reads and parses a manifest.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut content = Vec::new();
+        try!(reader.read_to_end(&mut content));
+        Self::parse(&content)
+    }
+
+    /// Parses the content of a manifest file.
+    pub fn parse(content: &[u8]) -> io::Result<Self> {
+        let mut manifest = Manifest::default();
+        for line in content.split(|b| *b == b'\n') {
+            let line = trim(line);
+            let (key, value) = split_first_word(line);
+            match key {
+                b"Hostname" => {
+                    manifest.hostname = Some(String::from_utf8_lossy(value).into_owned());
+                }
+                b"Localdir" => {
+                    manifest.local_dir = Some(bytes2path(&unquote(value)));
+                }
+                b"Volume" => {
+                    let number = value.split(|b| *b == b':').next().unwrap_or(value);
+                    let number = try!(str::from_utf8(number)
+                                          .ok()
+                                          .and_then(|n| n.trim().parse::<i32>().ok())
+                                          .ok_or_else(|| invalid("invalid volume number")));
+                    manifest.volumes.push(VolumeInfo {
+                        number: number,
+                        ..VolumeInfo::default()
+                    });
+                }
+                b"StartingPath" | b"EndingPath" => {
+                    let volume = try!(manifest.volumes
+                                              .last_mut()
+                                              .ok_or_else(|| invalid("path outside a volume")));
+                    let (path, block) = parse_path_and_block(value);
+                    if key == b"StartingPath" {
+                        volume.start_path = Some(path);
+                        volume.start_block = block;
+                    } else {
+                        volume.end_path = Some(path);
+                        volume.end_block = block;
+                    }
+                }
+                b"Hash" => {
+                    let volume = try!(manifest.volumes
+                                              .last_mut()
+                                              .ok_or_else(|| invalid("hash outside a volume")));
+                    let (algorithm, digest) = split_first_word(value);
+                    volume.hash = Some((String::from_utf8_lossy(algorithm).into_owned(),
+                                        String::from_utf8_lossy(trim(digest)).into_owned()));
+                }
+                b"" | b"Filelist" | b"new" | b"changed" | b"deleted" => {
+                    // file lists are ignored
+                }
+                _ => {
+                    let key = String::from_utf8_lossy(key).into_owned();
+                    if !manifest.unknown_keys.contains(&key) {
+                        manifest.unknown_keys.push(key);
+                    }
+                }
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn trim(mut s: &[u8]) -> &[u8] {
+    while let Some((first, rest)) = s.split_first() {
+        if !(*first as char).is_whitespace() {
+            break;
+        }
+        s = rest;
+    }
+    while let Some((last, rest)) = s.split_last() {
+        if !(*last as char).is_whitespace() {
+            break;
+        }
+        s = rest;
+    }
+    s
+}
+
+/// Splits a line in its first word and the rest, trimmed.
+fn split_first_word(line: &[u8]) -> (&[u8], &[u8]) {
+    match line.iter().position(|b| (*b as char).is_whitespace()) {
+        Some(pos) => (&line[..pos], trim(&line[pos..])),
+        None => (line, &[]),
+    }
+}
+
+/// Parses a path, optionally quoted, followed by an optional block number.
+fn parse_path_and_block(value: &[u8]) -> (PathBuf, Option<u64>) {
+    let (path, rest) = if value.first() == Some(&b'"') {
+        // find the closing quote, skipping the escaped ones
+        let mut end = 1;
+        while end < value.len() && value[end] != b'"' {
+            if value[end] == b'\\' {
+                end += 1;
+            }
+            end += 1;
+        }
+        let end = if end < value.len() { end + 1 } else { value.len() };
+        (&value[..end], trim(&value[end..]))
+    } else {
+        split_first_word(value)
+    };
+    let block = str::from_utf8(rest).ok().and_then(|b| b.parse::<u64>().ok());
+    (bytes2path(&unquote(path)), block)
+}
+
+/// Reverses the quoting applied by duplicity to paths in the manifest.
+///
+/// Quoted paths are enclosed in double quotes; backslashes, double quotes and non printable
+/// characters inside are escaped.
+fn unquote(s: &[u8]) -> Vec<u8> {
+    if s.len() < 2 || s[0] != b'"' || s[s.len() - 1] != b'"' {
+        return s.to_vec();
+    }
+    let s = &s[1..s.len() - 1];
+    let mut result = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] == b'\\' && i + 1 < s.len() {
+            match s[i + 1] {
+                b'x' if i + 3 < s.len() => {
+                    let hex = str::from_utf8(&s[i + 2..i + 4])
+                                  .ok()
+                                  .and_then(|h| u8::from_str_radix(h, 16).ok());
+                    if let Some(byte) = hex {
+                        result.push(byte);
+                        i += 4;
+                        continue;
+                    }
+                    result.push(s[i + 1]);
+                }
+                b'n' => result.push(b'\n'),
+                b't' => result.push(b'\t'),
+                other => result.push(other),
+            }
+            i += 2;
+        } else {
+            result.push(s[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MANIFEST: &'static [u8] = br#"Hostname example.org
+Localdir "/home/user/my docs"
+Volume 1:
+    StartingPath   .
+    EndingPath     "dir/big file" 3
+    Hash SHA1 da39a3ee5e6b4b0d3255bfef95601890afd80709
+Volume 2:
+    StartingPath   "dir/big file" 4
+    EndingPath     "quote \" backslash \\ tab \t byte \x0a end"
+    Hash SHA256 e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855
+Volume 3:
+    StartingPath   zzz
+    EndingPath     zzz
+Filelist 2
+    new          a
+    changed      dir/big file
+"#;
+
+    fn path(p: &str) -> Option<PathBuf> {
+        Some(PathBuf::from(p))
+    }
+
+    fn hash(algorithm: &str, digest: &str) -> Option<(String, String)> {
+        Some((algorithm.to_owned(), digest.to_owned()))
+    }
+
+    #[test]
+    fn multi_volume() {
+        let manifest = Manifest::from_reader(MANIFEST).unwrap();
+        assert_eq!(manifest.hostname, Some("example.org".to_owned()));
+        assert_eq!(manifest.local_dir, path("/home/user/my docs"));
+        assert!(manifest.unknown_keys.is_empty());
+        let volumes = &manifest.volumes;
+        assert_eq!(volumes.iter().map(|v| v.number).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        assert_eq!(volumes[0].start_path, path("."));
+        assert_eq!(volumes[0].start_block, None);
+        assert_eq!(volumes[0].end_path, path("dir/big file"));
+        assert_eq!(volumes[0].end_block, Some(3));
+        assert_eq!(volumes[0].hash,
+                   hash("SHA1", "da39a3ee5e6b4b0d3255bfef95601890afd80709"));
+
+        // the file split between the volumes continues from the next block
+        assert_eq!(volumes[1].start_path, path("dir/big file"));
+        assert_eq!(volumes[1].start_block, Some(4));
+        assert_eq!(volumes[1].hash,
+                   hash("SHA256",
+                        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"));
+    }
+
+    #[test]
+    fn escaped_paths() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+        let volume = &manifest.volumes[1];
+        assert_eq!(volume.end_path, path("quote \" backslash \\ tab \t byte \n end"));
+        assert_eq!(volume.end_block, None);
+
+        assert_eq!(unquote(br#""a\nb\"c""#), b"a\nb\"c");
+        // invalid hex escapes keep the escaped character
+        assert_eq!(unquote(br#""\xzz""#), b"xzz");
+        assert_eq!(unquote(br#""\x4""#), b"x4");
+        // unquoted and unterminated paths are taken as they are
+        assert_eq!(unquote(br#"a\nb"#), br#"a\nb"#);
+        assert_eq!(parse_path_and_block(br#""no end 5"#),
+                   (PathBuf::from("\"no end 5"), None));
+        assert_eq!(parse_path_and_block(br#""with space" 12"#),
+                   (PathBuf::from("with space"), Some(12)));
+        assert_eq!(parse_path_and_block(b"plain 7"), (PathBuf::from("plain"), Some(7)));
+    }
+
+    #[test]
+    fn missing_hash() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+        let volume = &manifest.volumes[2];
+        assert_eq!(volume.start_path, path("zzz"));
+        assert_eq!(volume.end_path, path("zzz"));
+        assert_eq!(volume.hash, None);
+
+        let manifest = Manifest::parse(b"Volume 1:\n").unwrap();
+        assert_eq!(manifest.volumes[0].number, 1);
+        assert_eq!(manifest.volumes[0].start_path, None);
+        assert_eq!(manifest.volumes[0].hash, None);
+    }
+
+    #[test]
+    fn invalid_content() {
+        for content in &[&b"Hash SHA1 0123\n"[..],
+                         &b"StartingPath a\n"[..],
+                         &b"Volume one:\n"[..],
+                         &b"Volume :\n"[..]] {
+            let error = Manifest::parse(content).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let manifest = Manifest::parse(b"Hostname h\nFoo 1\nBar 2\nFoo 3\n").unwrap();
+        assert_eq!(manifest.hostname, Some("h".to_owned()));
+        assert_eq!(manifest.unknown_keys, vec!["Foo".to_owned(), "Bar".to_owned()]);
+        assert!(manifest.volumes.is_empty());
+        assert!(Manifest::parse(b"").unwrap().volumes.is_empty());
+    }
+}
@@ -0,0 +1,686 @@
+use getopts;
+use libc;
+use log::LogLevelFilter;
+use std::env;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::str::FromStr;
+use std::thread;
+
+use fs::{Layout, Preload, SymlinkRewrite, UnknownEntries};
+use scrub::ScrubMode;
+
+// the configuration file of the fuse kernel module
+const FUSE_CONF: &'static str = "/etc/fuse.conf";
+
+// every option can be set with an environment variable having this prefix
+const ENV_PREFIX: &'static str = "RUPLICITY_FUSE_";
+
+// the parameters the fuse crate negotiates with the kernel by itself in its INIT reply: it accepts
+// the largest readahead the kernel offers, and the ABI 7.8 reply has no room for the others
+const NEGOTIATED_OPTIONS: [&'static str; 3] = ["max_readahead",
+                                                "max_background",
+                                                "congestion_threshold"];
+
+// the long options that can be set in the environment, and whether they take a value
+const ENV_OPTIONS: [(&'static str, bool); 22] = [("verify-on-mount", false),
+                                                 ("max-read", true),
+                                                 ("allow-root", false),
+                                                 ("allow-other", false),
+                                                 ("default-permissions", false),
+                                                 ("preserve-ownership", false),
+                                                 ("layout", true),
+                                                 ("volatile", false),
+                                                 ("track-atime", false),
+                                                 ("unknown-entries", true),
+                                                 ("rewrite-symlinks", true),
+                                                 ("strip-prefix", true),
+                                                 ("add-prefix", true),
+                                                 ("strict", false),
+                                                 ("preload-metadata", true),
+                                                 ("preload-threads", true),
+                                                 ("check-tree", false),
+                                                 ("best-effort", false),
+                                                 ("scrub", true),
+                                                 ("trace-out", true),
+                                                 ("no-color", false),
+                                                 ("log-level", true)];
+
+
+/// Options given in the command line.
+#[derive(Debug)]
+pub struct Options {
+    /// The backups to be mounted.
+    pub mounts: Vec<MountTarget>,
+    /// Check the backup for consistency before mounting it.
+    pub verify_on_mount: bool,
+    /// Maximum size of a single read request, in bytes.
+    pub max_read: Option<u32>,
+    /// Allow the root user to access the mount.
+    pub allow_root: bool,
+    /// Allow all the users to access the mount.
+    pub allow_other: bool,
+    /// Let the kernel check permissions by using the backed up modes.
+    pub default_permissions: bool,
+    /// Report the owners recorded in the backup instead of the mounting user.
+    pub preserve_ownership: bool,
+    /// How the snapshots are arranged in the root directory.
+    pub layout: Layout,
+    /// The backup may be modified while mounted.
+    pub volatile: bool,
+    /// Report the last time entries were opened as their access time.
+    pub track_atime: bool,
+    /// What to do with the entries of unknown type.
+    pub unknown_entries: UnknownEntries,
+    /// How the absolute targets of symbolic links are rewritten.
+    pub rewrite_symlinks: SymlinkRewrite,
+    /// The path components removed from the entries.
+    pub strip_prefix: Vec<String>,
+    /// The directories added above the entries.
+    pub add_prefix: Vec<String>,
+    /// Report errors instead of made up metadata.
+    pub strict: bool,
+    /// The snapshots to be loaded before mounting, if any.
+    pub preload_metadata: Option<Preload>,
+    /// How many signature chains are preloaded at the same time.
+    pub preload_threads: usize,
+    /// Verify the snapshot trees after building them.
+    pub check_tree: bool,
+    /// Leave out the snapshots that can't be loaded instead of failing.
+    pub best_effort: bool,
+    /// When to check the volumes against their hashes in the background.
+    pub scrub: ScrubMode,
+    /// Where to record the duration of the operations, if anywhere.
+    pub trace_out: Option<String>,
+    /// Don't color the log messages.
+    pub no_color: bool,
+    /// The most verbose log messages shown.
+    pub log_level: LogLevelFilter,
+    /// Additional options passed verbatim to the FUSE mount.
+    pub fuse_options: Vec<String>,
+}
+
+/// A backup to be mounted.
+#[derive(Debug)]
+pub struct MountTarget {
+    /// Where to mount the backup.
+    pub mountpoint: String,
+    /// Path of the directory containing the backup.
+    pub backup_path: String,
+}
+
+/// The outcome of command line parsing.
+pub enum Parsed {
+    /// The options are valid.
+    Options(Options),
+    /// The user asked for help.
+    Help,
+}
+
+
+impl Options {
+    /// Parses the command line arguments, excluding the program name.
+    ///
+    /// Options not given in the command line are taken from the `RUPLICITY_FUSE_*` environment
+    /// variables, if set.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Parsed, String> {
+        let mut args = args.into_iter().collect::<Vec<_>>();
+        let from_env = try!(env_args(&args));
+        args.extend(from_env);
+        let matches = try!(spec().parse(args).map_err(|e| e.to_string()));
+        if matches.opt_present("h") {
+            return Ok(Parsed::Help);
+        }
+        // the targets given in the command line take precedence over any in the environment
+        let config = matches.opt_str("config").or_else(|| if matches.free.is_empty() {
+            env_var("CONFIG")
+        } else {
+            None
+        });
+        let mounts = match config {
+            Some(config) => {
+                if !matches.free.is_empty() {
+                    return Err("unexpected arguments together with --config".to_owned());
+                }
+                try!(read_mounts_file(&config))
+            }
+            None => {
+                match (matches.free.get(0), matches.free.get(1)) {
+                    (Some(mount), Some(path)) if matches.free.len() == 2 => {
+                        vec![MountTarget {
+                                 mountpoint: mount.clone(),
+                                 backup_path: path.clone(),
+                             }]
+                    }
+                    (None, None) if env_var("MOUNTPOINT").is_some() &&
+                                    env_var("BACKUP_PATH").is_some() => {
+                        vec![MountTarget {
+                                 mountpoint: env_var("MOUNTPOINT").unwrap(),
+                                 backup_path: env_var("BACKUP_PATH").unwrap(),
+                             }]
+                    }
+                    _ => {
+                        return Err("expected a mountpoint and a backup path".to_owned());
+                    }
+                }
+            }
+        };
+        let max_read = try!(parse_opt_num(&matches, "max-read"));
+        let allow_root = matches.opt_present("allow-root");
+        let allow_other = matches.opt_present("allow-other");
+        if allow_root && allow_other {
+            return Err("--allow-root and --allow-other are mutually exclusive".to_owned());
+        }
+        let layout = try!(matches.opt_str("layout").map_or(Ok(Layout::Flat), |l| l.parse()));
+        let unknown_entries = try!(matches.opt_str("unknown-entries")
+                                          .map_or(Ok(UnknownEntries::Show), |u| u.parse()));
+        let rewrite_symlinks = try!(matches.opt_str("rewrite-symlinks")
+                                           .map_or(Ok(SymlinkRewrite::None), |r| r.parse()));
+        let strip_prefix = try!(parse_prefix(&matches, "strip-prefix"));
+        let add_prefix = try!(parse_prefix(&matches, "add-prefix"));
+        let scrub = try!(matches.opt_str("scrub").map_or(Ok(ScrubMode::Off), |s| s.parse()));
+        let preload_metadata = match matches.opt_str("preload-metadata") {
+            Some(which) => Some(try!(which.parse())),
+            None => None,
+        };
+        let preload_threads = match try!(parse_opt_num(&matches, "preload-threads")) {
+            Some(0) => {
+                return Err("invalid preload-threads: at least one thread is needed".to_owned());
+            }
+            Some(threads) => threads,
+            None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        };
+        let log_level = match matches.opt_str("log-level") {
+            Some(level) => {
+                try!(level.parse()
+                          .map_err(|_| format!("invalid log-level: unknown level '{}'", level)))
+            }
+            None => LogLevelFilter::Trace,
+        };
+        let fuse_options = matches.opt_strs("o")
+                                  .iter()
+                                  .flat_map(|opts| opts.split(','))
+                                  .filter(|opt| !opt.is_empty())
+                                  .map(ToOwned::to_owned)
+                                  .collect::<Vec<String>>();
+        if let Some(opt) = fuse_options.iter().find(|opt| {
+            let name = opt.split('=').next().unwrap_or_default();
+            NEGOTIATED_OPTIONS.contains(&name)
+        }) {
+            return Err(format!("unsupported FUSE option '{}': it is negotiated with the kernel \
+                                when mounting, and can't be changed",
+                               opt));
+        }
+        Ok(Parsed::Options(Options {
+            mounts: mounts,
+            verify_on_mount: matches.opt_present("verify-on-mount"),
+            max_read: max_read,
+            allow_root: allow_root,
+            allow_other: allow_other,
+            default_permissions: matches.opt_present("default-permissions"),
+            preserve_ownership: matches.opt_present("preserve-ownership"),
+            layout: layout,
+            volatile: matches.opt_present("volatile"),
+            track_atime: matches.opt_present("track-atime"),
+            unknown_entries: unknown_entries,
+            rewrite_symlinks: rewrite_symlinks,
+            strip_prefix: strip_prefix,
+            add_prefix: add_prefix,
+            strict: matches.opt_present("strict"),
+            preload_metadata: preload_metadata,
+            preload_threads: preload_threads,
+            check_tree: matches.opt_present("check-tree"),
+            best_effort: matches.opt_present("best-effort"),
+            scrub: scrub,
+            trace_out: matches.opt_str("trace-out"),
+            no_color: matches.opt_present("no-color"),
+            log_level: log_level,
+            fuse_options: fuse_options,
+        }))
+    }
+
+    /// Returns the options to be used for the FUSE mount of a target.
+    ///
+    /// A degraded mount, leaving out some snapshots, is told apart by its filesystem subtype.
+    pub fn mount_options(&self, target: &MountTarget, degraded: bool) -> Vec<String> {
+        // the kernel refuses any modification, and unmounts if this process dies
+        let mut result = vec!["ro".to_owned(), "auto_unmount".to_owned()];
+        // shown by mount, df and findmnt
+        result.push(format!("fsname={}", escape_mount_option(&target.backup_path)));
+        if degraded {
+            result.push("subtype=ruplicity-degraded".to_owned());
+        } else {
+            result.push("subtype=ruplicity".to_owned());
+        }
+        // max_read is the only one of the read parameters that is a mount option, the others are
+        // negotiated once mounted
+        if let Some(max_read) = self.max_read {
+            result.push(format!("max_read={}", max_read));
+        }
+        if self.allow_root {
+            result.push("allow_root".to_owned());
+        }
+        if self.allow_other {
+            result.push("allow_other".to_owned());
+        }
+        if self.default_permissions {
+            result.push("default_permissions".to_owned());
+        }
+        result.extend(self.fuse_options.iter().cloned());
+        result
+    }
+
+    /// Returns warnings about options that are likely to make the mount fail.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        let shared = self.allow_root || self.allow_other ||
+                     self.fuse_options
+                         .iter()
+                         .any(|opt| opt == "allow_root" || opt == "allow_other");
+        let is_root = unsafe { libc::getuid() } == 0;
+        if shared && !is_root && !user_allow_other() {
+            result.push(format!("sharing the mount with other users requires \
+                                 'user_allow_other' in {}",
+                                FUSE_CONF));
+        }
+        result
+    }
+
+    /// Returns the help message.
+    pub fn usage(program: &str) -> String {
+        let brief = format!("Usage: {0} [options] <MOUNTPOINT> <BACKUP_PATH>\n       {0} \
+                             [options] --config <FILE>",
+                            program);
+        format!("{0}\nEvery long option can also be set with a {1}<OPTION> environment variable, \
+                 as in {1}MAX_READ=4096, where flags accept 1 or 0. The FUSE options go in \
+                 {1}OPTIONS, the targets in {1}MOUNTPOINT and {1}BACKUP_PATH.\n",
+                spec().usage(&brief),
+                ENV_PREFIX)
+    }
+}
+
+
+fn spec() -> getopts::Options {
+    let mut opts = getopts::Options::new();
+    opts.optflag("h", "help", "print this help message");
+    opts.optopt("",
+                "config",
+                "mount all the backups listed in FILE, one '<MOUNTPOINT> <BACKUP_PATH>' per line",
+                "FILE");
+    opts.optflag("",
+                 "verify-on-mount",
+                 "check manifests, volumes and their hashes, and signatures before mounting");
+    opts.optopt("",
+                "max-read",
+                "maximum size in bytes of a single read request",
+                "BYTES");
+    opts.optflag("", "allow-root", "allow the root user to access the mount");
+    opts.optflag("", "allow-other", "allow all the users to access the mount");
+    opts.optflag("",
+                 "default-permissions",
+                 "let the kernel check permissions against the backed up modes");
+    opts.optflag("",
+                 "preserve-ownership",
+                 "report the owners recorded in the backup instead of the mounting user");
+    opts.optopt("",
+                "layout",
+                "arrange the snapshots as 'flat' directories (default), 'by-date' in \
+                 YYYY/MM/DD/HH-MM-SS, 'by-chain' below their full snapshot, 'indexed', adding \
+                 full.N, inc.N and -N links counting from the latest, or show only the latest \
+                 as the root with 'single-snapshot'",
+                "LAYOUT");
+    opts.optflag("",
+                 "volatile",
+                 "the backup is being modified: disable kernel caching, hide snapshots as soon \
+                  as they are removed and the ones not completely uploaded");
+    opts.optflag("",
+                 "track-atime",
+                 "report the last time entries were opened while mounted as their access time, \
+                  instead of the modification time");
+    opts.optopt("",
+                "unknown-entries",
+                "show the entries of unknown type as empty files (show, default), leave them \
+                 out (hide) or show them and refuse to open them (fail)",
+                "POLICY");
+    opts.optopt("",
+                "rewrite-symlinks",
+                "report absolute symlink targets as recorded (none, default), relative to the \
+                 link so they stay in the snapshot (relative), or below the snapshot path in the \
+                 mount (prefix)",
+                "REWRITE");
+    opts.optopt("",
+                "strip-prefix",
+                "show the entries below PATH in place of the snapshot root, leaving out the \
+                 others",
+                "PATH");
+    opts.optopt("",
+                "add-prefix",
+                "show the entries of the snapshots below PATH, usually the directory the backup \
+                 was made from",
+                "PATH");
+    opts.optflag("",
+                 "strict",
+                 "fail instead of making up missing modes, owners and sizes, report link counts \
+                  and the exact error numbers");
+    opts.optopt("",
+                "preload-metadata",
+                "load the signatures of 'all' the snapshots or of the 'latest' one before \
+                 mounting, instead of when they are first browsed",
+                "WHICH");
+    opts.optopt("",
+                "preload-threads",
+                "load up to N signature chains at the same time when preloading (default: the \
+                 number of CPUs)",
+                "N");
+    opts.optflag("",
+                 "check-tree",
+                 "verify the consistency of every snapshot tree after building it, refusing to \
+                  show the inconsistent ones (for debugging)");
+    opts.optflag("",
+                 "best-effort",
+                 "leave out the snapshots that can't be loaded instead of failing, listing them \
+                  in .ruplicity/DEGRADED and mounting with subtype ruplicity-degraded");
+    opts.optopt("",
+                "scrub",
+                "check the volumes against the hashes in their manifests, slowly and only while \
+                 the mount is 'idle', reporting in .ruplicity/scrub-report, or never ('off', \
+                 default)",
+                "MODE");
+    opts.optopt("",
+                "trace-out",
+                "record the duration of FUSE requests and backend accesses in FILE, to be viewed \
+                 in chrome://tracing",
+                "FILE");
+    opts.optflag("",
+                 "no-color",
+                 "don't color the log messages (also disabled by setting NO_COLOR)");
+    opts.optopt("",
+                "log-level",
+                "show log messages up to LEVEL: error, warn, info, debug or trace (default)",
+                "LEVEL");
+    opts.optmulti("o", "", "comma separated FUSE mount options", "OPTIONS");
+    opts
+}
+
+/// Returns the arguments corresponding to the options set in the environment, skipping the ones
+/// already present in the command line.
+fn env_args(args: &[String]) -> Result<Vec<String>, String> {
+    let mut result = Vec::new();
+    for &(name, has_value) in &ENV_OPTIONS {
+        let var = name.to_uppercase().replace('-', "_");
+        let value = match env_var(&var) {
+            Some(value) => value,
+            None => {
+                continue;
+            }
+        };
+        let flag = format!("--{}", name);
+        if args.iter().any(|arg| *arg == flag || arg.starts_with(&format!("{}=", flag))) {
+            continue;
+        }
+        if has_value {
+            result.push(flag);
+            result.push(value);
+        } else {
+            match value.as_str() {
+                "1" | "true" | "yes" => result.push(flag),
+                "0" | "false" | "no" | "" => (),
+                _ => {
+                    return Err(format!("invalid {}{}: expected 1 or 0, got '{}'",
+                                       ENV_PREFIX,
+                                       var,
+                                       value));
+                }
+            }
+        }
+    }
+    // the FUSE options add up with the ones given in the command line
+    if let Some(fuse_options) = env_var("OPTIONS") {
+        result.push("-o".to_owned());
+        result.push(fuse_options);
+    }
+    Ok(result)
+}
+
+/// Returns the value of a `RUPLICITY_FUSE_*` environment variable.
+fn env_var(name: &str) -> Option<String> {
+    env::var(format!("{}{}", ENV_PREFIX, name)).ok()
+}
+
+/// Parses the numeric value of an option, if present.
+pub fn parse_opt_num<T>(matches: &getopts::Matches, name: &str) -> Result<Option<T>, String>
+    where T: FromStr,
+          T::Err: Display
+{
+    match matches.opt_str(name) {
+        Some(value) => value.parse().map(Some).map_err(|e| format!("invalid {}: {}", name, e)),
+        None => Ok(None),
+    }
+}
+
+/// Splits the path given to an option into its components, if present.
+fn parse_prefix(matches: &getopts::Matches, name: &str) -> Result<Vec<String>, String> {
+    let path = matches.opt_str(name).unwrap_or_default();
+    let components = path.split('/')
+                         .filter(|c| !c.is_empty() && *c != ".")
+                         .map(ToOwned::to_owned)
+                         .collect::<Vec<_>>();
+    if components.iter().any(|c| c == "..") {
+        return Err(format!("invalid {}: '..' is not allowed", name));
+    }
+    Ok(components)
+}
+
+/// Reads the list of backups to be mounted from a file.
+///
+/// Each non empty line contains a mountpoint followed by the backup path, separated by
+/// whitespace. Lines starting with '#' are comments.
+fn read_mounts_file(path: &str) -> Result<Vec<MountTarget>, String> {
+    let file = try!(File::open(path).map_err(|e| format!("cannot open {}: {}", path, e)));
+    let mut result = Vec::new();
+    for (num, line) in BufReader::new(file).lines().enumerate() {
+        let line = try!(line.map_err(|e| format!("cannot read {}: {}", path, e)));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, char::is_whitespace);
+        match (parts.next(), parts.next().map(str::trim)) {
+            (Some(mountpoint), Some(backup_path)) if !backup_path.is_empty() => {
+                result.push(MountTarget {
+                    mountpoint: mountpoint.to_owned(),
+                    backup_path: backup_path.to_owned(),
+                });
+            }
+            _ => {
+                return Err(format!("{}:{}: expected '<MOUNTPOINT> <BACKUP_PATH>'",
+                                   path,
+                                   num + 1));
+            }
+        }
+    }
+    if result.is_empty() {
+        return Err(format!("no backups listed in {}", path));
+    }
+    Ok(result)
+}
+
+/// Escapes the characters having a special meaning in a FUSE mount option value.
+fn escape_mount_option(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(',', "\\,")
+}
+
+/// Returns whether non root users are allowed to share their mounts.
+fn user_allow_other() -> bool {
+    let file = match File::open(FUSE_CONF) {
+        Ok(file) => file,
+        Err(_) => {
+            return false;
+        }
+    };
+    BufReader::new(file)
+        .lines()
+        .filter_map(Result::ok)
+        .any(|line| line.trim() == "user_allow_other")
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    lazy_static! {
+        // the environment is shared by the tests running at the same time
+        static ref ENV: Mutex<()> = Mutex::new(());
+    }
+
+    /// Parses the arguments with some `RUPLICITY_FUSE_*` variables set, given without prefix.
+    fn parse_with_env(args: &[&str], vars: &[(&str, &str)]) -> Result<Parsed, String> {
+        let _lock = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        for &(name, value) in vars {
+            env::set_var(format!("{}{}", ENV_PREFIX, name), value);
+        }
+        let result = Options::parse(args.iter().map(|arg| arg.to_string()));
+        for &(name, _) in vars {
+            env::remove_var(format!("{}{}", ENV_PREFIX, name));
+        }
+        result
+    }
+
+    fn parse_args(args: &[&str]) -> Result<Options, String> {
+        parse_with_env(args, &[]).map(expect_options)
+    }
+
+    fn expect_options(parsed: Parsed) -> Options {
+        match parsed {
+            Parsed::Options(opts) => opts,
+            Parsed::Help => panic!("unexpected help"),
+        }
+    }
+
+    #[test]
+    fn targets() {
+        let opts = parse_args(&["/mnt", "/backup"]).unwrap();
+        assert_eq!(opts.mounts.len(), 1);
+        assert_eq!(opts.mounts[0].mountpoint, "/mnt");
+        assert_eq!(opts.mounts[0].backup_path, "/backup");
+        assert_eq!(opts.max_read, None);
+        assert_eq!(opts.layout, Layout::Flat);
+        assert!(!opts.allow_other);
+        assert!(opts.fuse_options.is_empty());
+
+        assert!(parse_args(&[]).is_err());
+        assert!(parse_args(&["/mnt"]).is_err());
+        assert!(parse_args(&["/mnt", "/backup", "/other"]).is_err());
+        match parse_with_env(&["--help"], &[]).unwrap() {
+            Parsed::Help => (),
+            Parsed::Options(_) => panic!("expected help"),
+        }
+    }
+
+    #[test]
+    fn values() {
+        let opts = parse_args(&["--max-read",
+                                "4096",
+                                "--layout=by-date",
+                                "--strip-prefix",
+                                "/home/./user/",
+                                "--log-level",
+                                "warn",
+                                "/mnt",
+                                "/backup"])
+                       .unwrap();
+        assert_eq!(opts.max_read, Some(4096));
+        assert_eq!(opts.layout, Layout::ByDate);
+        assert_eq!(opts.strip_prefix, vec!["home", "user"]);
+        assert_eq!(opts.log_level, LogLevelFilter::Warn);
+
+        for args in &[&["--max-read", "4k"][..],
+                      &["--layout", "nested"][..],
+                      &["--strip-prefix", "a/../b"][..],
+                      &["--preload-threads", "0"][..],
+                      &["--log-level", "loud"][..],
+                      &["--no-such-option"][..]] {
+            let mut args = args.to_vec();
+            args.extend(&["/mnt", "/backup"]);
+            assert!(parse_args(&args).is_err(), "{:?}", args);
+        }
+        assert_eq!(parse_args(&["--allow-root", "--allow-other", "/mnt", "/backup"])
+                       .unwrap_err(),
+                   "--allow-root and --allow-other are mutually exclusive");
+    }
+
+    #[test]
+    fn fuse_options() {
+        let opts = parse_args(&["-o", "noatime,,debug", "-o", "max_write=4096", "/mnt", "/backup"])
+                       .unwrap();
+        assert_eq!(opts.fuse_options, vec!["noatime", "debug", "max_write=4096"]);
+        // the parameters negotiated by the fuse crate can't be set
+        for opt in &NEGOTIATED_OPTIONS {
+            let opt = format!("ro,{}=1024", opt);
+            assert!(parse_args(&["-o", opt.as_str(), "/mnt", "/backup"]).is_err(), "{}", opt);
+        }
+    }
+
+    #[test]
+    fn mount_options() {
+        let opts = parse_args(&["--max-read",
+                                "65536",
+                                "--allow-other",
+                                "--default-permissions",
+                                "-o",
+                                "noatime",
+                                "/mnt",
+                                "/back,up\\"])
+                       .unwrap();
+        assert_eq!(opts.mount_options(&opts.mounts[0], false),
+                   vec!["ro",
+                        "auto_unmount",
+                        "fsname=/back\\,up\\\\",
+                        "subtype=ruplicity",
+                        "max_read=65536",
+                        "allow_other",
+                        "default_permissions",
+                        "noatime"]);
+        let opts = parse_args(&["/mnt", "/backup"]).unwrap();
+        assert_eq!(opts.mount_options(&opts.mounts[0], true),
+                   vec!["ro", "auto_unmount", "fsname=/backup", "subtype=ruplicity-degraded"]);
+    }
+
+    #[test]
+    fn environment() {
+        let vars = [("MAX_READ", "4096"),
+                    ("ALLOW_OTHER", "1"),
+                    ("VOLATILE", "0"),
+                    ("LAYOUT", "indexed"),
+                    ("OPTIONS", "noatime")];
+        let opts = expect_options(parse_with_env(&["/mnt", "/backup"], &vars).unwrap());
+        assert_eq!(opts.max_read, Some(4096));
+        assert!(opts.allow_other);
+        assert!(!opts.volatile);
+        assert_eq!(opts.layout, Layout::Indexed);
+        assert_eq!(opts.fuse_options, vec!["noatime"]);
+
+        // the command line takes precedence, and the FUSE options add up
+        let args = ["--max-read=8192", "--layout", "flat", "-o", "debug", "/mnt", "/backup"];
+        let opts = expect_options(parse_with_env(&args, &vars).unwrap());
+        assert_eq!(opts.max_read, Some(8192));
+        assert_eq!(opts.layout, Layout::Flat);
+        assert_eq!(opts.fuse_options, vec!["debug", "noatime"]);
+
+        let vars = [("MOUNTPOINT", "/mnt"), ("BACKUP_PATH", "/backup")];
+        let opts = expect_options(parse_with_env(&[], &vars).unwrap());
+        assert_eq!(opts.mounts[0].mountpoint, "/mnt");
+        assert_eq!(opts.mounts[0].backup_path, "/backup");
+        // the targets in the command line replace the ones in the environment
+        let opts = expect_options(parse_with_env(&["/other", "/path"], &vars).unwrap());
+        assert_eq!(opts.mounts[0].mountpoint, "/other");
+
+        match parse_with_env(&["/mnt", "/backup"], &[("STRICT", "maybe")]) {
+            Err(e) => assert_eq!(e, "invalid RUPLICITY_FUSE_STRICT: expected 1 or 0, got 'maybe'"),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+}
@@ -1,5 +1,7 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
 use std::io;
-use std::path::Path;
+use std::path::{self, Path, PathBuf};
 #[cfg(unix)]
 use std::os::unix::prelude::*;
 #[cfg(windows)]
@@ -17,3 +19,115 @@ pub fn path2bytes(p: &Path) -> io::Result<&[u8]> {
 pub fn path2bytes(p: &Path) -> io::Result<&[u8]> {
     Ok(p.as_os_str().as_bytes())
 }
+
+#[cfg(windows)]
+pub fn bytes2path(b: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(b).into_owned())
+}
+
+#[cfg(unix)]
+pub fn bytes2path(b: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(b))
+}
+
+#[cfg(windows)]
+fn bytes2os_str(b: &[u8]) -> &OsStr {
+    OsStr::new(::std::str::from_utf8(b).unwrap())
+}
+
+#[cfg(unix)]
+fn bytes2os_str(b: &[u8]) -> &OsStr {
+    OsStr::from_bytes(b)
+}
+
+/// How the components of the paths recorded in a backup are separated.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PathStyle {
+    /// Components are separated by slashes, and backslashes are part of the file names.
+    Unix,
+    /// Components may be separated by backslashes too, and a drive letter may come first.
+    Windows,
+}
+
+impl PathStyle {
+    /// Guesses the style of the paths of a backup.
+    ///
+    /// They are Windows paths if none of them contains a slash, some contain a backslash, and
+    /// splitting on backslashes gives a hierarchy where every parent directory is also recorded.
+    /// Otherwise backslashes are taken as part of Unix file names.
+    ///
+    /// `paths` is called twice, so that only the parents of the paths with backslashes are kept
+    /// in memory instead of all the paths.
+    pub fn detect<'a, F, I>(paths: F) -> Self
+        where F: Fn() -> I,
+              I: Iterator<Item = &'a Path>
+    {
+        let mut parents = HashSet::new();
+        for path in paths() {
+            let bytes = unwrap_opt_or_continue!(path2bytes(path).ok());
+            if bytes.contains(&b'/') {
+                return PathStyle::Unix;
+            }
+            if let Some(pos) = bytes.iter().rposition(|&b| b == b'\\') {
+                parents.insert(&bytes[..pos]);
+            }
+        }
+        if parents.is_empty() {
+            return PathStyle::Unix;
+        }
+        for path in paths() {
+            let bytes = unwrap_opt_or_continue!(path2bytes(path).ok());
+            parents.remove(bytes);
+            if parents.is_empty() {
+                return PathStyle::Windows;
+            }
+        }
+        PathStyle::Unix
+    }
+}
+
+/// Splits a path recorded in a backup into its components.
+///
+/// Paths of backups taken on Windows may use backslashes as separators and start with a drive
+/// letter, so in that style both the separators are accepted, and a leading `C:` becomes a `C`
+/// directory. The components are split as they are iterated, without allocating.
+pub fn backup_components(p: &Path, style: PathStyle) -> BackupComponents {
+    BackupComponents {
+        rest: path2bytes(p).map_err(|_| p.iter()),
+        windows: style == PathStyle::Windows,
+        first: true,
+    }
+}
+
+/// The iterator returned by `backup_components`.
+pub struct BackupComponents<'a> {
+    // the bytes of the path left to split, or its components if it can't be read as bytes
+    rest: Result<&'a [u8], path::Iter<'a>>,
+    windows: bool,
+    first: bool,
+}
+
+impl<'a> Iterator for BackupComponents<'a> {
+    type Item = &'a OsStr;
+
+    fn next(&mut self) -> Option<&'a OsStr> {
+        let windows = self.windows;
+        let is_separator = |b: &u8| *b == b'/' || (windows && *b == b'\\');
+        let bytes = match self.rest {
+            Ok(bytes) => bytes,
+            Err(ref mut components) => {
+                return components.next();
+            }
+        };
+        let start = try_opt!(bytes.iter().position(|b| !is_separator(b)));
+        let bytes = &bytes[start..];
+        let (component, rest) = bytes.split_at(bytes.iter()
+                                                    .position(&is_separator)
+                                                    .unwrap_or(bytes.len()));
+        self.rest = Ok(rest);
+        let is_drive = windows && self.first && component.len() == 2 && component[1] == b':' &&
+                       (component[0] as char).is_ascii_alphabetic();
+        self.first = false;
+        Some(bytes2os_str(if is_drive { &component[..1] } else { component }))
+    }
+}
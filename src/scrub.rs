@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use ruplicity::Backend;
+use time::{self, Timespec};
+
+use manifest::VolumeInfo;
+use time_utils;
+use verify;
+
+
+// how long the mounts have to go without requests before volumes are read, in seconds
+const IDLE_SECS: i64 = 30;
+// the most bytes read from the backend in a second
+const MAX_RATE: usize = 1024 * 1024;
+// the most bytes read at once, so that a request arriving meanwhile waits little
+const CHUNK_SIZE: usize = 64 * 1024;
+// how long to wait after checking all the volumes before starting over, in seconds
+const PASS_INTERVAL: u64 = 24 * 60 * 60;
+
+// the time of the last request served by any mount, in seconds
+static LAST_REQUEST: AtomicUsize = AtomicUsize::new(0);
+
+
+/// When the volumes are checked against their hashes in the background.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ScrubMode {
+    /// They are never checked.
+    Off,
+    /// They are read slowly while the mounts are idle.
+    Idle,
+}
+
+/// The progress and the results of a scrubber, shared with the filesystem showing them.
+pub type SharedScrubReport = Arc<Mutex<ScrubReport>>;
+
+/// A scrubber checking volumes in a background thread.
+pub struct Scrubber {
+    report: SharedScrubReport,
+    stopped: Arc<AtomicBool>,
+}
+
+/// What a scrubber found so far.
+#[derive(Debug, Default)]
+pub struct ScrubReport {
+    /// The number of the pass over the volumes in progress, starting from 1.
+    pass: u64,
+    /// How many volumes there are to check.
+    total: usize,
+    /// How many volumes were checked in this pass.
+    checked: usize,
+    /// When each volume was last checked, and the problem found if any.
+    results: BTreeMap<String, (Timespec, Option<String>)>,
+}
+
+// a volume file waiting for idle periods and limiting the rate at which it is read
+struct Throttled<'a, R> {
+    inner: R,
+    stopped: &'a AtomicBool,
+}
+
+
+impl FromStr for ScrubMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ScrubMode::Off),
+            "idle" => Ok(ScrubMode::Idle),
+            _ => Err(format!("unknown scrub mode '{}', expected 'off' or 'idle'", s)),
+        }
+    }
+}
+
+
+/// Records that a mount is serving a request, so the scrubbers stay off the backend for a while.
+pub fn record_activity() {
+    LAST_REQUEST.store(time::get_time().sec as usize, Ordering::Relaxed);
+}
+
+/// Starts checking the given volumes against their hashes in a background thread, over and
+/// over.
+///
+/// The thread runs until `Scrubber::stop` is called.
+pub fn start<B>(volumes: Vec<(String, VolumeInfo)>, backend: B) -> Scrubber
+    where B: Backend + Send + 'static
+{
+    let scrubber = Scrubber {
+        report: Arc::new(Mutex::new(ScrubReport {
+            total: volumes.len(),
+            ..ScrubReport::default()
+        })),
+        stopped: Arc::new(AtomicBool::new(false)),
+    };
+    let report = scrubber.report.clone();
+    let stopped = scrubber.stopped.clone();
+    let spawned = thread::Builder::new()
+                      .name("scrub".to_owned())
+                      .spawn(move || run(&volumes, &backend, &report, &stopped));
+    if let Err(e) = spawned {
+        error!("Can't start the scrubber: {}", e);
+    }
+    scrubber
+}
+
+
+fn run<B: Backend>(volumes: &[(String, VolumeInfo)],
+                   backend: &B,
+                   report: &SharedScrubReport,
+                   stopped: &AtomicBool) {
+    loop {
+        {
+            let mut report = report.lock().unwrap();
+            report.pass += 1;
+            report.checked = 0;
+        }
+        for &(ref path, ref volume) in volumes {
+            let problem = match backend.open_file(Path::new(path)) {
+                Ok(file) => {
+                    let file = Throttled {
+                        inner: file,
+                        stopped: stopped,
+                    };
+                    verify::hash_problem(path, volume, file)
+                }
+                Err(e) => Some(format!("cannot read volume {}: {}", path, e)),
+            };
+            if stopped.load(Ordering::Relaxed) {
+                return;
+            }
+            if let Some(ref problem) = problem {
+                warn!("Scrubbing found a problem: {}", problem);
+            }
+            let mut report = report.lock().unwrap();
+            report.checked += 1;
+            report.results.insert(path.clone(), (time::get_time(), problem));
+        }
+        info!("Scrubbing checked all the {} volumes", volumes.len());
+        for _ in 0..PASS_INTERVAL {
+            if stopped.load(Ordering::Relaxed) {
+                return;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+impl Scrubber {
+    /// Returns the report of the scrubber, to show in the filesystem.
+    pub fn report(&self) -> SharedScrubReport {
+        self.report.clone()
+    }
+
+    /// Stops the scrubber as soon as it is done with the chunk it is reading, if any.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+
+impl ScrubReport {
+    /// Returns the content of the report file.
+    ///
+    /// A line tells the progress of the current pass, followed by a line for each volume checked
+    /// so far, with the time of the last check and the problem found.
+    pub fn content(&self) -> Vec<u8> {
+        let mut result = format!("pass {}: {} of {} volumes checked\n",
+                                 self.pass,
+                                 self.checked,
+                                 self.total);
+        for (path, &(time, ref problem)) in &self.results {
+            let ts = time_utils::local(time).format("%Y-%m-%d %H:%M:%S");
+            match *problem {
+                Some(ref problem) => result.push_str(&format!("{} error {}\n", ts, problem)),
+                None => result.push_str(&format!("{} ok {}\n", ts, path)),
+            }
+        }
+        result.into_bytes()
+    }
+}
+
+
+impl<'a, R: Read> Read for Throttled<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.stopped.load(Ordering::Relaxed) {
+                return Err(io::Error::new(io::ErrorKind::Other, "the scrubbing was stopped"));
+            }
+            let last = LAST_REQUEST.load(Ordering::Relaxed) as i64;
+            if time::get_time().sec - last >= IDLE_SECS {
+                break;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+        let len = buf.len().min(CHUNK_SIZE);
+        let read = try!(self.inner.read(&mut buf[..len]));
+        thread::sleep(Duration::from_millis((read * 1000 / MAX_RATE) as u64));
+        Ok(read)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use digest;
+
+    #[test]
+    fn report_content() {
+        time_utils::fixed_time_zone();
+        let checked = Timespec::new(1_500_000_000, 0);
+        let mut report = ScrubReport {
+            pass: 2,
+            total: 3,
+            checked: 2,
+            ..ScrubReport::default()
+        };
+        report.results.insert("vol1".to_owned(), (checked, None));
+        report.results.insert("vol2".to_owned(), (checked, Some("vol2 is broken".to_owned())));
+        let ts = time_utils::local(checked).format("%Y-%m-%d %H:%M:%S");
+        assert_eq!(String::from_utf8(report.content()).unwrap(),
+                   format!("pass 2: 2 of 3 volumes checked\n{} ok vol1\n{} error vol2 is broken\n",
+                           ts,
+                           ts));
+    }
+
+    #[test]
+    fn corrupted_volume() {
+        let content = b"some volume content".to_vec();
+        let sha1 = digest::hex_digest(digest::from_name("SHA1").unwrap(),
+                                      Cursor::new(&content))
+                       .unwrap();
+        let volume = VolumeInfo {
+            number: 1,
+            hash: Some(("SHA1".to_owned(), sha1.clone())),
+            ..VolumeInfo::default()
+        };
+        assert_eq!(verify::hash_problem("vol1", &volume, Cursor::new(content.clone())), None);
+
+        let mut corrupted = content;
+        corrupted[3] ^= 1;
+        let problem = verify::hash_problem("vol1", &volume, Cursor::new(corrupted)).unwrap();
+        assert!(problem.starts_with("volume vol1 has SHA1 hash "), "{}", problem);
+        assert!(problem.ends_with(&format!(", but the manifest records {}", sha1)),
+                "{}",
+                problem);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use ruplicity::Backend;
+
+
+// the time format used in the long file names
+const TIME_FORMAT: &'static str = "%Y%m%dT%H%M%SZ";
+
+
+/// A backend wrapper translating the file names of backups taken with `--short-filenames`.
+///
+/// Those backups name their files as `df.<time>.m` instead of `duplicity-full.<time>.manifest`,
+/// with times and volume numbers in base 36. The short names are listed in the long form
+/// understood by ruplicity, and opened by their listed name. Backups with long file names are
+/// passed through unchanged.
+#[derive(Debug)]
+pub struct ShortNamesBackend<B> {
+    inner: B,
+    // the listed short names, by their long form
+    short_names: Mutex<HashMap<PathBuf, PathBuf>>,
+}
+
+
+impl<B: Backend> ShortNamesBackend<B> {
+    pub fn new(inner: B) -> Self {
+        ShortNamesBackend {
+            inner: inner,
+            short_names: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<B: Backend> Backend for ShortNamesBackend<B> {
+    type FileName = PathBuf;
+    type FileNameIter = Vec<PathBuf>;
+    type FileStream = B::FileStream;
+
+    fn file_names(&self) -> io::Result<Self::FileNameIter> {
+        let mut short_names = HashMap::new();
+        let names = try!(self.inner.file_names())
+                        .into_iter()
+                        .map(|name| {
+                            let name = name.as_ref();
+                            match name.to_str().and_then(long_name) {
+                                Some(long) => {
+                                    let long = PathBuf::from(long);
+                                    short_names.insert(long.clone(), name.to_path_buf());
+                                    long
+                                }
+                                None => name.to_path_buf(),
+                            }
+                        })
+                        .collect();
+        if !short_names.is_empty() {
+            debug!("Found {} files with short names", short_names.len());
+        }
+        *self.short_names.lock().unwrap() = short_names;
+        Ok(names)
+    }
+
+    fn open_file(&self, name: &Path) -> io::Result<Self::FileStream> {
+        // the listed names are opened as they are, whatever their case
+        let short = self.short_names.lock().unwrap().get(name).cloned();
+        if let Some(short) = short {
+            return self.inner.open_file(&short);
+        }
+        match self.inner.open_file(name) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                match name.to_str().and_then(short_name) {
+                    Some(short) => self.inner.open_file(Path::new(&short)),
+                    None => self.inner.open_file(name),
+                }
+            }
+            result => result,
+        }
+    }
+}
+
+
+/// Translates a short file name into the long one, if it is a short name.
+///
+/// The recognized names are `df.T.m`, `df.T.N.dt`, `di.T.T.m`, `di.T.T.N.dt`, `dfs.T.st` and
+/// `dns.T.T.st`, where manifests and signatures may end with `.p` if partial, and all of them
+/// may have `.z` (gzip) or `.g` (gpg) suffixes. The names are recognized in any case, and the
+/// other suffixes are kept as they are.
+fn long_name(name: &str) -> Option<String> {
+    let original = name.split('.').collect::<Vec<_>>();
+    let lower = name.to_lowercase();
+    let parts = lower.split('.').collect::<Vec<_>>();
+    let (kind, rest) = try_opt!(parts.split_first());
+    let kind = *kind;
+    let times = match kind {
+        "df" | "dfs" => 1,
+        "di" | "dns" => 2,
+        _ => {
+            return None;
+        }
+    };
+    if rest.len() < times + 1 {
+        return None;
+    }
+    let mut long_times = Vec::new();
+    for time in &rest[..times] {
+        long_times.push(try_opt!(long_time(time)));
+    }
+    let mut rest = &rest[times..];
+    let body = match (kind, rest[0]) {
+        ("df", "m") | ("di", "m") | ("dfs", "st") | ("dns", "st") => {
+            let body = if rest[0] == "m" { "manifest" } else { "sigtar" };
+            rest = &rest[1..];
+            if rest.first() == Some(&"p") {
+                rest = &rest[1..];
+                format!("{}.part", body)
+            } else {
+                body.to_owned()
+            }
+        }
+        ("df", num) | ("di", num) if rest.get(1) == Some(&"dt") => {
+            let num = try_opt!(u64::from_str_radix(num, 36).ok());
+            rest = &rest[2..];
+            format!("vol{}.difftar", num)
+        }
+        _ => {
+            return None;
+        }
+    };
+    let prefix = match kind {
+        "df" => "duplicity-full",
+        "di" => "duplicity-inc",
+        "dfs" => "duplicity-full-signatures",
+        _ => "duplicity-new-signatures",
+    };
+    let mut result = format!("{}.{}.{}", prefix, long_times.join(".to."), body);
+    let first_suffix = parts.len() - rest.len();
+    for (suffix, original) in rest.iter().zip(&original[first_suffix..]) {
+        result.push('.');
+        result.push_str(match *suffix {
+            "z" => "gz",
+            "g" => "gpg",
+            _ => *original,
+        });
+    }
+    Some(result)
+}
+
+/// Translates a long file name into the short one, if it is one of the duplicity names.
+fn short_name(name: &str) -> Option<String> {
+    let parts = name.split('.').collect::<Vec<_>>();
+    let (kind, rest) = try_opt!(parts.split_first());
+    let (short_kind, times) = match *kind {
+        "duplicity-full" => ("df", 1),
+        "duplicity-full-signatures" => ("dfs", 1),
+        "duplicity-inc" => ("di", 2),
+        "duplicity-new-signatures" => ("dns", 2),
+        _ => {
+            return None;
+        }
+    };
+    // incremental names separate the two times with ".to."
+    let (time_parts, mut rest) = if times == 1 {
+        (vec![try_opt!(rest.get(0))], try_opt!(rest.get(1..)))
+    } else if rest.get(1) == Some(&"to") {
+        (vec![try_opt!(rest.get(0)), try_opt!(rest.get(2))], try_opt!(rest.get(3..)))
+    } else {
+        return None;
+    };
+    let mut result = short_kind.to_owned();
+    for time in time_parts {
+        result.push('.');
+        result.push_str(&try_opt!(short_time(time)));
+    }
+    match try_opt!(rest.first()) {
+        &"manifest" => result.push_str(".m"),
+        &"sigtar" => result.push_str(".st"),
+        vol if vol.starts_with("vol") => {
+            let num = try_opt!(vol[3..].parse::<u64>().ok());
+            result.push('.');
+            result.push_str(&to_base36(num));
+            result.push_str(".dt");
+            rest = &rest[1..];
+            if rest.first() != Some(&"difftar") {
+                return None;
+            }
+        }
+        _ => {
+            return None;
+        }
+    }
+    for suffix in &rest[1..] {
+        result.push('.');
+        result.push_str(match *suffix {
+            "part" => "p",
+            "gz" => "z",
+            "gpg" => "g",
+            other => other,
+        });
+    }
+    Some(result)
+}
+
+/// Converts a base 36 time, in seconds from the epoch, into the long format.
+fn long_time(time: &str) -> Option<String> {
+    let secs = try_opt!(i64::from_str_radix(time, 36).ok());
+    Utc.timestamp_opt(secs, 0).single().map(|time| time.format(TIME_FORMAT).to_string())
+}
+
+/// Converts a time in the long format into base 36.
+fn short_time(time: &str) -> Option<String> {
+    let time = try_opt!(NaiveDateTime::parse_from_str(time, TIME_FORMAT).ok());
+    let secs = Utc.from_utc_datetime(&time).timestamp();
+    if secs < 0 {
+        return None;
+    }
+    Some(to_base36(secs as u64))
+}
+
+fn to_base36(mut num: u64) -> String {
+    const DIGITS: &'static [u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut result = Vec::new();
+    loop {
+        result.push(DIGITS[(num % 36) as usize]);
+        num /= 36;
+        if num == 0 {
+            break;
+        }
+    }
+    result.reverse();
+    String::from_utf8(result).unwrap()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    use backend::memory::MemoryBackend;
+
+    // every documented form, with the short name and the long one
+    const NAMES: [(&'static str, &'static str); 12] =
+        [("df.ot27eo.m", "duplicity-full.20170714T024000Z.manifest"),
+         ("df.ot27eo.m.p", "duplicity-full.20170714T024000Z.manifest.part"),
+         ("df.ot27eo.1.dt", "duplicity-full.20170714T024000Z.vol1.difftar"),
+         ("df.ot27eo.a.dt.z", "duplicity-full.20170714T024000Z.vol10.difftar.gz"),
+         ("di.ot27eo.ot2a6o.m.g",
+          "duplicity-inc.20170714T024000Z.to.20170714T034000Z.manifest.gpg"),
+         ("di.ot27eo.ot2a6o.m.p.g",
+          "duplicity-inc.20170714T024000Z.to.20170714T034000Z.manifest.part.gpg"),
+         ("di.ot27eo.ot2a6o.10.dt.g",
+          "duplicity-inc.20170714T024000Z.to.20170714T034000Z.vol36.difftar.gpg"),
+         ("dfs.ot27eo.st", "duplicity-full-signatures.20170714T024000Z.sigtar"),
+         ("dfs.ot27eo.st.p.z", "duplicity-full-signatures.20170714T024000Z.sigtar.part.gz"),
+         ("dns.ot27eo.ot2a6o.st.z",
+          "duplicity-new-signatures.20170714T024000Z.to.20170714T034000Z.sigtar.gz"),
+         ("dns.ot27eo.ot2a6o.st.p",
+          "duplicity-new-signatures.20170714T024000Z.to.20170714T034000Z.sigtar.part"),
+         ("dns.ot27eo.ot2a6o.st.p.g",
+          "duplicity-new-signatures.20170714T024000Z.to.20170714T034000Z.sigtar.part.gpg")];
+
+    #[test]
+    fn round_trip() {
+        for &(short, long) in &NAMES {
+            assert_eq!(long_name(short), Some(long.to_owned()));
+            assert_eq!(short_name(long), Some(short.to_owned()));
+        }
+    }
+
+    #[test]
+    fn any_case() {
+        assert_eq!(long_name("DF.OT27EO.1.DT.Z"),
+                   Some("duplicity-full.20170714T024000Z.vol1.difftar.gz".to_owned()));
+        // the suffixes having no short form keep their case
+        assert_eq!(long_name("dfs.ot27eo.st.Old"),
+                   Some("duplicity-full-signatures.20170714T024000Z.sigtar.Old".to_owned()));
+        assert_eq!(short_name("duplicity-full-signatures.20170714T024000Z.sigtar.Old"),
+                   Some("dfs.ot27eo.st.Old".to_owned()));
+    }
+
+    #[test]
+    fn other_names() {
+        for name in &["duplicity-full.20170714T024000Z.manifest",
+                      "df.ot27eo",
+                      "df.ot27eo.x",
+                      "di.ot27eo.m",
+                      "dx.ot27eo.m",
+                      "df.not-a-time.m",
+                      "df.ot27eo.1.difftar",
+                      "README"] {
+            assert_eq!(long_name(name), None, "{}", name);
+        }
+        for name in &["df.ot27eo.m",
+                      "duplicity-inc.20170714T024000Z.manifest",
+                      "duplicity-full.20170714T024000Z.volume.difftar",
+                      "README"] {
+            assert_eq!(short_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn open_listed_names() {
+        let mut inner = MemoryBackend::default();
+        inner.insert("DF.OT27EO.M", b"short".to_vec());
+        inner.insert("duplicity-full.20170714T034000Z.manifest", b"long".to_vec());
+        let backend = ShortNamesBackend::new(inner);
+        let mut names = backend.file_names().unwrap();
+        names.sort();
+        assert_eq!(names,
+                   vec![PathBuf::from("duplicity-full.20170714T024000Z.manifest"),
+                        PathBuf::from("duplicity-full.20170714T034000Z.manifest")]);
+        for (name, expected) in names.iter().zip(&[&b"short"[..], &b"long"[..]]) {
+            let mut content = Vec::new();
+            backend.open_file(name).unwrap().read_to_end(&mut content).unwrap();
+            assert_eq!(content, *expected);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use ruplicity::Backend;
+
+use trace::Span;
+
+
+/// Traffic statistics shared between a backend and whoever reports them.
+pub type SharedStats = Arc<Mutex<TrafficStats>>;
+
+/// Counts the data transferred from a backend.
+#[derive(Debug, Default)]
+pub struct TrafficStats {
+    /// Number of times the file list was requested.
+    pub listings: u64,
+    /// Total number of file names returned by listings.
+    pub names_listed: u64,
+    /// Number of files opened.
+    pub files_opened: u64,
+    /// Bytes read for each file.
+    pub bytes_read: BTreeMap<PathBuf, u64>,
+}
+
+/// A backend wrapper accounting for the data read through it.
+#[derive(Debug)]
+pub struct CountingBackend<B> {
+    inner: B,
+    stats: SharedStats,
+}
+
+/// A file stream accounting for the bytes read from it.
+pub struct CountingStream<R> {
+    inner: R,
+    name: PathBuf,
+    stats: SharedStats,
+    // traces the time the file is kept open
+    _span: Span,
+}
+
+
+impl TrafficStats {
+    /// Returns the total number of bytes read from all the files.
+    pub fn total_bytes_read(&self) -> u64 {
+        self.bytes_read.values().fold(0, |acc, b| acc + b)
+    }
+}
+
+impl Display for TrafficStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        try!(writeln!(f,
+                      "listings: {}, names listed: {}, files opened: {}, bytes read: {}",
+                      self.listings,
+                      self.names_listed,
+                      self.files_opened,
+                      self.total_bytes_read()));
+        for (name, bytes) in &self.bytes_read {
+            try!(writeln!(f, "  {}: {} bytes", name.display(), bytes));
+        }
+        Ok(())
+    }
+}
+
+
+impl<B: Backend> CountingBackend<B> {
+    /// Wraps a backend, accounting its traffic into the given statistics.
+    pub fn new(inner: B, stats: SharedStats) -> Self {
+        CountingBackend {
+            inner: inner,
+            stats: stats,
+        }
+    }
+}
+
+impl<B: Backend> Backend for CountingBackend<B> {
+    type FileName = B::FileName;
+    type FileNameIter = Vec<B::FileName>;
+    type FileStream = CountingStream<B::FileStream>;
+
+    fn file_names(&self) -> io::Result<Self::FileNameIter> {
+        let _span = Span::new("backend", "list files");
+        let names = try!(self.inner.file_names()).into_iter().collect::<Vec<_>>();
+        let mut stats = self.stats.lock().unwrap();
+        stats.listings += 1;
+        stats.names_listed += names.len() as u64;
+        Ok(names)
+    }
+
+    fn open_file(&self, name: &Path) -> io::Result<Self::FileStream> {
+        let span = Span::new("backend", &format!("fetch {}", name.display()));
+        let file = try!(self.inner.open_file(name));
+        self.stats.lock().unwrap().files_opened += 1;
+        Ok(CountingStream {
+            inner: file,
+            name: name.to_path_buf(),
+            stats: self.stats.clone(),
+            _span: span,
+        })
+    }
+}
+
+
+impl<R: Read> Read for CountingStream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = try!(self.inner.read(buf));
+        let mut stats = self.stats.lock().unwrap();
+        if let Some(bytes) = stats.bytes_read.get_mut(&self.name) {
+            *bytes += read as u64;
+            return Ok(read);
+        }
+        stats.bytes_read.insert(self.name.clone(), read as u64);
+        Ok(read)
+    }
+}
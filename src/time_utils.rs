@@ -0,0 +1,172 @@
+use chrono::{DateTime, Local, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use time::Timespec;
+
+
+/// Converts a time, as used by fuse and ruplicity, into UTC.
+pub fn utc(time: Timespec) -> DateTime<Utc> {
+    Utc.timestamp_opt(time.sec, time.nsec as u32).unwrap()
+}
+
+/// Converts a time, as used by fuse and ruplicity, into the local time zone.
+pub fn local(time: Timespec) -> DateTime<Local> {
+    utc(time).with_timezone(&Local)
+}
+
+/// Converts a time into the representation used by fuse and ruplicity.
+pub fn to_timespec<Tz: TimeZone>(time: &DateTime<Tz>) -> Timespec {
+    Timespec::new(time.timestamp(), time.timestamp_subsec_nanos() as i32)
+}
+
+/// Formats a time in the local time zone, followed by its offset from UTC if the same wall clock
+/// time occurs twice, when DST ends.
+pub fn format_local_unambiguous(time: Timespec, format: &str) -> String {
+    let local = local(time);
+    let mut result = local.format(format).to_string();
+    if Local.from_local_datetime(&local.naive_local()).single().is_none() {
+        result.push_str(&local.format("%z").to_string());
+    }
+    result
+}
+
+/// Returns the times a local wall clock time refers to.
+///
+/// That's two times in the hour repeated when DST ends, and none in the hour skipped when it
+/// starts.
+pub fn from_local(time: &NaiveDateTime) -> Vec<Timespec> {
+    match Local.from_local_datetime(time) {
+        LocalResult::Single(t) => vec![to_timespec(&t)],
+        LocalResult::Ambiguous(earliest, latest) => {
+            vec![to_timespec(&earliest), to_timespec(&latest)]
+        }
+        LocalResult::None => Vec::new(),
+    }
+}
+
+/// Parses a time given as in duplicity's `--restore-time`, relative to `now`.
+///
+/// Accepts `now`, seconds from the epoch, intervals in the past like `3D` or `1W2D` (with units
+/// `s`, `m`, `h`, `D`, `W`, `M` and `Y`), RFC 3339 times, and dates like `2018-10-06` or
+/// `2018/10/06`, which refer to the end of that day.
+pub fn parse_time_spec(spec: &str, now: Timespec) -> Option<Timespec> {
+    if spec == "now" {
+        return Some(now);
+    }
+    if let Ok(secs) = spec.parse::<i64>() {
+        return representable(secs);
+    }
+    if let Some(secs) = parse_interval(spec) {
+        return now.sec.checked_sub(secs).and_then(representable);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(spec) {
+        return Some(to_timespec(&time));
+    }
+    let date = try_opt!(["%Y-%m-%d", "%Y/%m/%d"]
+                            .iter()
+                            .filter_map(|format| NaiveDate::parse_from_str(spec, format).ok())
+                            .next());
+    let end_of_day = try_opt!(date.and_hms_opt(23, 59, 59));
+    from_local(&end_of_day).pop()
+}
+
+/// Parses an interval like `1W2D` into seconds.
+fn parse_interval(spec: &str) -> Option<i64> {
+    let mut total = 0i64;
+    let mut num = String::new();
+    for c in spec.chars() {
+        if c.is_digit(10) {
+            num.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'D' => 24 * 60 * 60,
+            'W' => 7 * 24 * 60 * 60,
+            'M' => 30 * 24 * 60 * 60,
+            'Y' => 365 * 24 * 60 * 60,
+            _ => {
+                return None;
+            }
+        };
+        let count = try_opt!(num.parse::<i64>().ok());
+        total = try_opt!(count.checked_mul(unit).and_then(|secs| total.checked_add(secs)));
+        num.clear();
+    }
+    if num.is_empty() && total > 0 {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+/// Returns the time the given seconds from the epoch refer to, if it can be converted to a date.
+fn representable(secs: i64) -> Option<Timespec> {
+    Utc.timestamp_opt(secs, 0).single().map(|_| Timespec::new(secs, 0))
+}
+
+/// Sets the local time zone to Central European Time, following the EU rules for DST.
+///
+/// Must be called by the tests depending on the local time zone, since it is shared by all of
+/// them.
+#[cfg(test)]
+pub fn fixed_time_zone() {
+    use std::env;
+    use std::sync::Once;
+
+    static SET: Once = Once::new();
+    // DST starts at 02:00 on the last Sunday of March, and ends at 03:00 on the last Sunday of
+    // October
+    SET.call_once(|| env::set_var("TZ", "CET-1CEST,M3.5.0,M10.5.0/3"));
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 2017-10-29T02:30 local time, before and after DST ends
+    const REPEATED_FIRST: i64 = 1509237000;
+    const REPEATED_SECOND: i64 = 1509240600;
+    // 2017-03-26T01:30 and 03:30 local time, before and after the hour skipped when DST starts
+    const BEFORE_SKIPPED: i64 = 1490488200;
+    const AFTER_SKIPPED: i64 = 1490491800;
+
+    fn format(secs: i64) -> String {
+        format_local_unambiguous(Timespec::new(secs, 0), "%Y-%m-%d %H-%M-%S")
+    }
+
+    fn naive(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn unambiguous_repeated_hour() {
+        fixed_time_zone();
+        assert_eq!(format(REPEATED_FIRST), "2017-10-29 02-30-00+0200");
+        assert_eq!(format(REPEATED_SECOND), "2017-10-29 02-30-00+0100");
+        // the hours around it are not repeated
+        assert_eq!(format(REPEATED_FIRST - 3600), "2017-10-29 01-30-00");
+        assert_eq!(format(REPEATED_SECOND + 3600), "2017-10-29 03-30-00");
+        assert_eq!(format(1500000000), "2017-07-14 04-40-00");
+    }
+
+    #[test]
+    fn unambiguous_skipped_hour() {
+        fixed_time_zone();
+        assert_eq!(format(BEFORE_SKIPPED), "2017-03-26 01-30-00");
+        assert_eq!(format(AFTER_SKIPPED), "2017-03-26 03-30-00");
+    }
+
+    #[test]
+    fn local_times() {
+        fixed_time_zone();
+        assert_eq!(from_local(&naive(2017, 10, 29, 2, 30)),
+                   vec![Timespec::new(REPEATED_FIRST, 0), Timespec::new(REPEATED_SECOND, 0)]);
+        assert_eq!(from_local(&naive(2017, 3, 26, 2, 30)), Vec::new());
+        assert_eq!(from_local(&naive(2017, 3, 26, 1, 30)),
+                   vec![Timespec::new(BEFORE_SKIPPED, 0)]);
+        assert_eq!(from_local(&naive(2017, 3, 26, 3, 30)),
+                   vec![Timespec::new(AFTER_SKIPPED, 0)]);
+    }
+}
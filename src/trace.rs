@@ -0,0 +1,117 @@
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use json;
+
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_TID: AtomicUsize = AtomicUsize::new(1);
+
+lazy_static! {
+    static ref OUTPUT: Mutex<Option<TraceFile>> = Mutex::new(None);
+}
+
+thread_local!(static TID: Cell<usize> = Cell::new(0));
+
+
+/// A timed operation, recorded in the trace when dropped.
+pub struct Span {
+    // None if tracing is disabled
+    event: Option<(String, &'static str, Instant)>,
+}
+
+struct TraceFile {
+    writer: BufWriter<File>,
+    start: Instant,
+    empty: bool,
+}
+
+
+/// Starts recording spans into the given file, in the Chrome trace event format.
+///
+/// The file can be loaded in chrome://tracing or in Perfetto. It is a valid JSON array only
+/// after `finish` is called, but both tools can also read it as it is while being written.
+pub fn start(path: &str) -> io::Result<()> {
+    let mut writer = BufWriter::new(try!(File::create(path)));
+    try!(writer.write_all(b"["));
+    *OUTPUT.lock().unwrap() = Some(TraceFile {
+        writer: writer,
+        start: Instant::now(),
+        empty: true,
+    });
+    ENABLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Stops recording and completes the trace file.
+pub fn finish() -> io::Result<()> {
+    ENABLED.store(false, Ordering::SeqCst);
+    match OUTPUT.lock().unwrap().take() {
+        Some(mut output) => {
+            try!(output.writer.write_all(b"\n]\n"));
+            output.writer.flush()
+        }
+        None => Ok(()),
+    }
+}
+
+
+impl Span {
+    /// Starts a span for an operation of the given category.
+    pub fn new(category: &'static str, name: &str) -> Self {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return Span { event: None };
+        }
+        Span { event: Some((name.to_owned(), category, Instant::now())) }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let (name, category, begin) = match self.event.take() {
+            Some(event) => event,
+            None => {
+                return;
+            }
+        };
+        let end = Instant::now();
+        let mut output = OUTPUT.lock().unwrap();
+        if let Some(ref mut output) = *output {
+            let separator = if output.empty { "\n" } else { ",\n" };
+            output.empty = false;
+            let result = write!(output.writer,
+                                "{}{{\"name\": {}, \"cat\": \"{}\", \"ph\": \"X\", \"ts\": {}, \
+                                 \"dur\": {}, \"pid\": {}, \"tid\": {}}}",
+                                separator,
+                                json::string(&name),
+                                category,
+                                micros(begin - output.start),
+                                micros(end - begin),
+                                process::id(),
+                                thread_id());
+            if let Err(e) = result {
+                error!("Can't write trace event: {}", e);
+            }
+        }
+    }
+}
+
+
+/// Returns a small number identifying the current thread.
+fn thread_id() -> usize {
+    TID.with(|tid| {
+        if tid.get() == 0 {
+            tid.set(NEXT_TID.fetch_add(1, Ordering::SeqCst));
+        }
+        tid.get()
+    })
+}
+
+fn micros(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000 + d.subsec_nanos() as u64 / 1000
+}
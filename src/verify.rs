@@ -0,0 +1,149 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::Read;
+use std::path::Path;
+
+use ruplicity::{Backend, Backup, Snapshot};
+
+use digest;
+use error::{self, Error};
+use fs::time_to_path;
+use manifest::{Manifest, VolumeInfo};
+
+
+/// A consistency problem found in a backup.
+#[derive(Debug)]
+pub struct Problem {
+    /// The directory name of the affected snapshot.
+    pub snapshot: String,
+    /// What is wrong.
+    pub description: String,
+}
+
+
+/// Checks the backup for consistency, returning all the problems found.
+///
+/// For each snapshot this verifies that the manifest is present, that every volume referenced by
+/// the manifest exists and matches its hash, and that the signatures can be parsed.
+pub fn verify<B: Backend>(backup: &Backup<B>, backend: &B) -> error::Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    for snapshot in try!(backup.snapshots().map_err(|e| Error::ListSnapshots { cause: e })) {
+        let name = time_to_path(snapshot.time());
+        debug!("Verifying snapshot {}", name);
+        for description in verify_snapshot(&snapshot, backend) {
+            problems.push(Problem {
+                snapshot: name.clone(),
+                description: description,
+            });
+        }
+    }
+    Ok(problems)
+}
+
+
+fn verify_snapshot<B: Backend>(snapshot: &Snapshot, backend: &B) -> Vec<String> {
+    let mut result = upload_problems(snapshot, backend);
+    if result.is_empty() {
+        for (path, volume) in hashed_volumes(snapshot, backend) {
+            let problem = match backend.open_file(Path::new(&path)) {
+                Ok(file) => hash_problem(&path, &volume, file),
+                Err(e) => Some(format!("cannot read volume {}: {}", path, e)),
+            };
+            result.extend(problem);
+        }
+    }
+    if let Err(e) = snapshot.entries() {
+        result.push(format!("cannot read signatures: {}", e));
+    }
+    result
+}
+
+/// Checks that the files of a snapshot are all uploaded, returning what is missing.
+///
+/// Duplicity uploads the manifest once the volumes are in place, so a snapshot still being
+/// written has no manifest, or misses some of the volumes listed by it.
+pub fn upload_problems<B: Backend>(snapshot: &Snapshot, backend: &B) -> Vec<String> {
+    let mut result = Vec::new();
+    let set = snapshot.as_backup_set();
+    if !set.is_complete() {
+        result.push("the manifest is missing".to_owned());
+    } else if set.is_encrypted() {
+        info!("Skipping volumes check for encrypted manifest {}",
+              set.manifest_path());
+    } else {
+        let manifest = backend.open_file(Path::new(set.manifest_path()))
+                              .and_then(Manifest::from_reader);
+        match manifest {
+            Ok(manifest) => {
+                for volume in &manifest.volumes {
+                    if set.volume_path(volume.number).is_none() {
+                        result.push(format!("volume {} referenced by manifest {} is missing",
+                                            volume.number,
+                                            set.manifest_path()));
+                    }
+                }
+            }
+            Err(e) => {
+                result.push(format!("cannot read manifest {}: {}", set.manifest_path(), e));
+            }
+        }
+    }
+    result
+}
+
+/// Returns the volumes of a snapshot having a hash in the manifest, with their paths.
+///
+/// Encrypted manifests can't be read, so their volumes are never returned.
+pub fn hashed_volumes<B: Backend>(snapshot: &Snapshot, backend: &B) -> Vec<(String, VolumeInfo)> {
+    let set = snapshot.as_backup_set();
+    if !set.is_complete() || set.is_encrypted() {
+        return Vec::new();
+    }
+    let manifest = backend.open_file(Path::new(set.manifest_path()))
+                          .and_then(Manifest::from_reader);
+    let volumes = match manifest {
+        Ok(manifest) => manifest.volumes,
+        Err(_) => {
+            return Vec::new();
+        }
+    };
+    volumes.into_iter()
+           .filter(|volume| volume.hash.is_some())
+           .filter_map(|volume| set.volume_path(volume.number).map(|p| (p.to_owned(), volume)))
+           .collect()
+}
+
+/// Checks the content of a volume file against the hash recorded in the manifest.
+pub fn hash_problem<R: Read>(path: &str, volume: &VolumeInfo, file: R) -> Option<String> {
+    let (algorithm, expected) = match volume.hash {
+        Some((ref algorithm, ref expected)) => (algorithm, expected),
+        None => {
+            return None;
+        }
+    };
+    let digest = match digest::from_name(algorithm) {
+        Some(digest) => digest,
+        None => {
+            return Some(format!("volume {} has a hash of unknown algorithm {}, can't check it",
+                                path,
+                                algorithm));
+        }
+    };
+    match digest::hex_digest(digest, file) {
+        Ok(ref actual) if actual.eq_ignore_ascii_case(expected) => None,
+        Ok(actual) => {
+            Some(format!("volume {} has {} hash {}, but the manifest records {}",
+                         path,
+                         algorithm,
+                         actual,
+                         expected))
+        }
+        Err(e) => Some(format!("cannot read volume {}: {}", path, e)),
+    }
+}
+
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "snapshot {}: {}", self.snapshot, self.description)
+    }
+}